@@ -16,7 +16,7 @@ fn main() {
 
     let version_file_path: std::path::PathBuf;
     if let Ok(out_dir) = get_out_dir() {
-        version_file_path = std::path::Path::new(&out_dir).join("version.rs".to_string());
+        version_file_path = std::path::Path::new(&out_dir).join("version.rs");
     } else {
         panic!("in the streets of London.");
     };
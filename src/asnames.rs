@@ -0,0 +1,384 @@
+//! Names of ASNs and the organisations holding them.
+//!
+//! The names are read from the CAIDA AS Organizations dataset, i.e. the
+//! `<date>.as-org2info.txt.gz` files, or from the list of AS names RIPE NCC
+//! publishes as `asn.txt`. The CAIDA files have separate sections for the
+//! ASNs and the organisations, each starting with a `# format:` comment. The
+//! RIPE list has one `<asn> <name> <description>, <country>` line per ASN.
+
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::{Asn, JsonBuilder};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::BufRead;
+use std::str::FromStr;
+
+//------------ AsName --------------------------------------------------------
+
+/// The name of an AS and the organisation holding it.
+#[derive(Clone, Debug)]
+pub struct AsName {
+    /// The name of the AS, e.g. "RIPE-NCC-AS".
+    pub name: String,
+
+    /// The name of the organisation holding the AS, if known.
+    pub org_name: Option<String>,
+
+    /// The ISO 3166 country code of the organisation, if known.
+    pub country: Option<String>,
+
+    /// The data set the name was taken from, "caida" or "ripe".
+    pub source: &'static str,
+}
+
+impl AsName {
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("name", &self.name);
+        match &self.org_name {
+            Some(org_name) => builder.member_str("orgName", org_name),
+            None => builder.member_raw("orgName", "null"),
+        }
+        match &self.country {
+            Some(country) => builder.member_str("country", country),
+            None => builder.member_raw("country", "null"),
+        }
+    }
+}
+
+impl Snapshot for AsName {
+    fn write(&self, w: &mut Writer) {
+        self.name.write(w);
+        self.org_name.write(w);
+        self.country.write(w);
+        self.source.to_string().write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(AsName {
+            name: Snapshot::read(r)?,
+            org_name: Snapshot::read(r)?,
+            country: Snapshot::read(r)?,
+            source: match String::read(r)?.as_str() {
+                "caida" => "caida",
+                "ripe" => "ripe",
+                source => return Err(format!("unknown AS name source '{}' in snapshot", source).into()),
+            },
+        })
+    }
+}
+
+//------------ AsDirectory ---------------------------------------------------
+
+/// The names of all ASNs that were loaded.
+#[derive(Clone, Debug, Default)]
+pub struct AsDirectory {
+    names: HashMap<Asn, AsName>,
+}
+
+impl AsDirectory {
+    pub fn insert(&mut self, asn: Asn, name: AsName) {
+        self.names.insert(asn, name);
+    }
+
+    pub fn get(&self, asn: Asn) -> Option<&AsName> {
+        self.names.get(&asn)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Adds the names of the origin ASNs of a meta object, in the same
+    /// order as the ASNs.
+    ///
+    /// Nothing is added if no names were loaded.
+    pub fn to_json_builder(&self, origins: &[Asn], builder: &mut JsonBuilder) {
+        if self.is_empty() {
+            return;
+        }
+        builder.member_array("originASNNames", |builder| {
+            for origin in origins {
+                match self.get(*origin) {
+                    Some(name) => builder.array_object(|builder| name.to_json_builder(builder)),
+                    None => builder.array_raw("null"),
+                }
+            }
+        });
+    }
+}
+
+impl Snapshot for AsDirectory {
+    fn write(&self, w: &mut Writer) {
+        self.names.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(AsDirectory {
+            names: Snapshot::read(r)?,
+        })
+    }
+}
+
+//------------ Parsing -------------------------------------------------------
+
+/// The section of a CAIDA file a line is in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Section {
+    /// Lines before any `# format:` comment, which means a RIPE list.
+    None,
+    Aut,
+    Org,
+}
+
+/// Reads the AS names from a CAIDA as2org file or a RIPE AS names list.
+///
+/// Lines that can't be parsed are handed to `skip` with their number,
+/// the reason and the line itself, and left out.
+pub(crate) fn read<R: BufRead>(
+    mut reader: R,
+    mut skip: impl FnMut(u64, String, &str) -> Result<(), Box<dyn Error>>,
+) -> Result<Vec<(Asn, AsName)>, Box<dyn Error>> {
+    let mut section = Section::None;
+    let mut names = Vec::new();
+
+    // The ASNs of a CAIDA file refer to their organisation by its ID, and
+    // the organisations may come after the ASNs.
+    let mut auts: Vec<(Asn, String, String)> = Vec::new();
+    let mut orgs: HashMap<String, (String, Option<String>)> = HashMap::new();
+
+    let mut buf = Vec::new();
+    let mut line_no = 0;
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        line_no += 1;
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end();
+        if let Some(format) = line.strip_prefix("# format:") {
+            section = if format.starts_with("aut|") {
+                Section::Aut
+            } else if format.starts_with("org_id|") {
+                Section::Org
+            } else {
+                skip(line_no, "unknown format".into(), line)?;
+                section
+            };
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('|').collect();
+        let res = match section {
+            Section::None => parse_ripe_line(line).map(|(asn, name)| names.push((asn, name))),
+            Section::Aut => match fields.as_slice() {
+                [aut, _changed, name, org_id, _opaque_id, _source] => Asn::from_str(aut)
+                    .map(|asn| auts.push((asn, name.to_string(), org_id.to_string())))
+                    .map_err(|err| format!("invalid ASN: {}", err)),
+                _ => Err(format!("expected 6 fields, found {}", fields.len())),
+            },
+            Section::Org => match fields.as_slice() {
+                [org_id, _changed, name, country, _source] => {
+                    let country = Some(country.to_string()).filter(|cc| !cc.is_empty());
+                    orgs.insert(org_id.to_string(), (name.to_string(), country));
+                    Ok(())
+                }
+                _ => Err(format!("expected 5 fields, found {}", fields.len())),
+            },
+        };
+        if let Err(err) = res {
+            skip(line_no, err, line)?;
+        }
+    }
+
+    for (asn, name, org_id) in auts {
+        let (org_name, country) = match orgs.get(&org_id) {
+            Some((org_name, country)) => (Some(org_name.clone()), country.clone()),
+            None => (None, None),
+        };
+        names.push((
+            asn,
+            AsName {
+                name,
+                org_name,
+                country,
+                source: "caida",
+            },
+        ));
+    }
+    Ok(names)
+}
+
+/// Parses a line of the RIPE AS names list.
+///
+/// The description after the name is mostly the name of the organisation,
+/// and the list ends every line with the country code.
+fn parse_ripe_line(line: &str) -> Result<(Asn, AsName), String> {
+    let (asn, rest) = line.split_once(' ').ok_or("missing AS name")?;
+    let asn = Asn::from_str(asn).map_err(|err| format!("invalid ASN: {}", err))?;
+    let (rest, country) = match rest.rsplit_once(", ") {
+        Some((rest, cc)) if cc.len() == 2 && cc.bytes().all(|ch| ch.is_ascii_uppercase()) => {
+            (rest, Some(cc.to_string()))
+        }
+        _ => (rest, None),
+    };
+    let (name, org_name) = match rest.split_once(' ') {
+        Some((name, org_name)) => (name, Some(org_name.trim().to_string())),
+        None => (rest, None),
+    };
+    if name.is_empty() {
+        return Err("missing AS name".into());
+    }
+    Ok((
+        asn,
+        AsName {
+            name: name.to_string(),
+            org_name: org_name.filter(|org_name| !org_name.is_empty()),
+            country,
+            source: "ripe",
+        },
+    ))
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The numbers of the lines that were skipped and why.
+    type Skipped = Vec<(u64, String)>;
+
+    fn read_all(data: &str) -> (Vec<(Asn, AsName)>, Skipped) {
+        let mut skipped = Vec::new();
+        let names = read(data.as_bytes(), |line_no, reason, _| {
+            skipped.push((line_no, reason));
+            Ok(())
+        })
+        .unwrap();
+        (names, skipped)
+    }
+
+    fn fields(name: &AsName) -> (&str, Option<&str>, Option<&str>, &str) {
+        (&name.name, name.org_name.as_deref(), name.country.as_deref(), name.source)
+    }
+
+    /// The organisations follow the ASNs that refer to them.
+    const AS2ORG: &str = "\
+# name: AS Org
+# format:aut|changed|aut_name|org_id|opaque_id|source
+3333|20120224|RIPE-NCC-AS|ORG-RIEN1-RIPE|59a3c76e_RIPE|RIPE
+1103|20231201|SURFNET-NL|ORG-SURF1-RIPE||RIPE
+64496|20240101|DOC-AS|ORG-NONE||ARIN
+AS1|20240101|BAD-AS|ORG-RIEN1-RIPE||RIPE
+# format:org_id|changed|name|country|source
+ORG-RIEN1-RIPE|20120224|Reseaux IP Europeens Network Coordination Centre (RIPE NCC)|NL|RIPE
+ORG-SURF1-RIPE|20231201|SURF B.V.||RIPE
+";
+
+    #[test]
+    fn read_as2org() {
+        let (names, skipped) = read_all(AS2ORG);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, 6);
+        assert!(skipped[0].1.starts_with("invalid ASN"));
+
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0].0, Asn(3333));
+        assert_eq!(
+            fields(&names[0].1),
+            (
+                "RIPE-NCC-AS",
+                Some("Reseaux IP Europeens Network Coordination Centre (RIPE NCC)"),
+                Some("NL"),
+                "caida"
+            )
+        );
+        assert_eq!(names[1].0, Asn(1103));
+        assert_eq!(fields(&names[1].1), ("SURFNET-NL", Some("SURF B.V."), None, "caida"));
+        assert_eq!(names[2].0, Asn(64496));
+        assert_eq!(fields(&names[2].1), ("DOC-AS", None, None, "caida"));
+    }
+
+    #[test]
+    fn read_as2org_bad_lines() {
+        let data = "\
+# format:aut|changed|aut_name|org_id|opaque_id|source
+3333|20120224|RIPE-NCC-AS
+# format:something|else
+# format:org_id|changed|name|country|source
+ORG-RIEN1-RIPE|RIPE
+";
+        let (names, skipped) = read_all(data);
+        assert!(names.is_empty());
+        assert_eq!(
+            skipped,
+            [
+                (2, "expected 6 fields, found 3".to_string()),
+                (3, "unknown format".to_string()),
+                (5, "expected 5 fields, found 2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_ripe_list() {
+        let data = "\
+3333 RIPE-NCC-AS Reseaux IP Europeens Network Coordination Centre (RIPE NCC), NL
+1103 SURFNET-NL SURFnet, The Netherlands, NL
+23456 AS_TRANS
+64496 DOC-AS, ZZ
+64497
+";
+        let (names, skipped) = read_all(data);
+        assert_eq!(skipped, [(5, "missing AS name".to_string())]);
+        let names: Vec<_> = names.iter().map(|(asn, name)| (*asn, fields(name))).collect();
+        assert_eq!(
+            names,
+            [
+                (
+                    Asn(3333),
+                    (
+                        "RIPE-NCC-AS",
+                        Some("Reseaux IP Europeens Network Coordination Centre (RIPE NCC)"),
+                        Some("NL"),
+                        "ripe"
+                    )
+                ),
+                (Asn(1103), ("SURFNET-NL", Some("SURFnet, The Netherlands"), Some("NL"), "ripe")),
+                (Asn(23456), ("AS_TRANS", None, None, "ripe")),
+                (Asn(64496), ("DOC-AS", None, Some("ZZ"), "ripe")),
+            ]
+        );
+    }
+
+    #[test]
+    fn origin_names_to_json() {
+        let json = |directory: &AsDirectory, origins: &[Asn]| {
+            let json = JsonBuilder::build(|builder| directory.to_json_builder(origins, builder));
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()
+        };
+        let mut directory = AsDirectory::default();
+        assert_eq!(json(&directory, &[Asn(3333)]), serde_json::json!({}));
+
+        let (names, _) = read_all(AS2ORG);
+        for (asn, name) in names {
+            directory.insert(asn, name);
+        }
+        assert_eq!(
+            json(&directory, &[Asn(1103), Asn(64511)]),
+            serde_json::json!({
+                "originASNNames": [
+                    { "name": "SURFNET-NL", "orgName": "SURF B.V.", "country": null },
+                    null
+                ]
+            })
+        );
+    }
+}
@@ -0,0 +1,269 @@
+//! Business relationships between ASes.
+//!
+//! The relationships are read from the CAIDA AS Relationships dataset, the
+//! `<date>.as-rel.txt.bz2` and `<date>.as-rel2.txt.bz2` files. Every line
+//! is either `<provider>|<customer>|-1` or `<peer>|<peer>|0`, the serial-2
+//! files add the source of the inference as a fourth field. See
+//! https://www.caida.org/catalog/datasets/as-relationships/.
+
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::{Asn, JsonBuilder};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+//------------ AsRelation ----------------------------------------------------
+
+/// The ASes related to an AS that can be queried.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AsRelation {
+    /// The providers of the AS.
+    Upstreams,
+
+    /// The customers of the AS.
+    Downstreams,
+
+    /// The settlement-free peers of the AS.
+    Peers,
+
+    /// The AS itself and all ASes reachable from it by following
+    /// provider-to-customer links.
+    CustomerCone,
+}
+
+impl FromStr for AsRelation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "upstreams" => Ok(AsRelation::Upstreams),
+            "downstreams" => Ok(AsRelation::Downstreams),
+            "peers" => Ok(AsRelation::Peers),
+            "customer-cone" => Ok(AsRelation::CustomerCone),
+            _ => Err(format!("unknown AS relation '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for AsRelation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsRelation::Upstreams => write!(f, "upstreams"),
+            AsRelation::Downstreams => write!(f, "downstreams"),
+            AsRelation::Peers => write!(f, "peers"),
+            AsRelation::CustomerCone => write!(f, "customer-cone"),
+        }
+    }
+}
+
+//------------ AsGraph -------------------------------------------------------
+
+/// The relationships between ASes.
+///
+/// Every link is kept in both directions, so the upstreams and the
+/// downstreams of an AS are both a single lookup.
+#[derive(Clone, Debug, Default)]
+pub struct AsGraph {
+    providers: HashMap<Asn, BTreeSet<Asn>>,
+    customers: HashMap<Asn, BTreeSet<Asn>>,
+    peers: HashMap<Asn, BTreeSet<Asn>>,
+    links: usize,
+}
+
+impl AsGraph {
+    /// Adds a link from a line of an as-rel file.
+    pub fn insert_line(&mut self, line: &str) -> Result<(), String> {
+        let mut fields = line.split('|');
+        let mut asn = || -> Result<Asn, String> {
+            let field = fields.next().ok_or("missing field")?;
+            Asn::from_str(field).map_err(|err| format!("invalid ASN '{}': {}", field, err))
+        };
+        let (left, right) = (asn()?, asn()?);
+        match fields.next() {
+            Some("-1") => {
+                self.customers.entry(left).or_default().insert(right);
+                self.providers.entry(right).or_default().insert(left);
+            }
+            Some("0") => {
+                self.peers.entry(left).or_default().insert(right);
+                self.peers.entry(right).or_default().insert(left);
+            }
+            Some(rel) => return Err(format!("unknown relationship '{}'", rel)),
+            None => return Err("missing relationship".into()),
+        }
+        self.links += 1;
+        Ok(())
+    }
+
+    /// Returns the number of links that were loaded.
+    pub fn len(&self) -> usize {
+        self.links
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links == 0
+    }
+
+    /// Returns the ASes related to `asn`, in ascending order.
+    pub fn related(&self, asn: Asn, relation: AsRelation) -> Vec<Asn> {
+        let direct = |map: &HashMap<Asn, BTreeSet<Asn>>| {
+            map.get(&asn)
+                .map(|asns| asns.iter().copied().collect())
+                .unwrap_or_default()
+        };
+        match relation {
+            AsRelation::Upstreams => direct(&self.providers),
+            AsRelation::Downstreams => direct(&self.customers),
+            AsRelation::Peers => direct(&self.peers),
+            AsRelation::CustomerCone => self.customer_cone(asn),
+        }
+    }
+
+    fn customer_cone(&self, asn: Asn) -> Vec<Asn> {
+        let mut cone = HashSet::new();
+        cone.insert(asn);
+        let mut todo = vec![asn];
+        while let Some(asn) = todo.pop() {
+            for customer in self.customers.get(&asn).into_iter().flatten() {
+                if cone.insert(*customer) {
+                    todo.push(*customer);
+                }
+            }
+        }
+        let mut cone: Vec<Asn> = cone.into_iter().collect();
+        cone.sort_unstable();
+        cone
+    }
+
+    /// Adds the upstreams of the origin ASNs of a bgp meta object, in the
+    /// same order as the ASNs.
+    ///
+    /// Nothing is added if no relationships were loaded.
+    pub fn to_json_builder(&self, origins: &[Asn], builder: &mut JsonBuilder) {
+        if self.is_empty() {
+            return;
+        }
+        builder.member_array("originUpstreams", |builder| {
+            for origin in origins {
+                builder.array_array(|builder| {
+                    for upstream in self.providers.get(origin).into_iter().flatten() {
+                        builder.array_str(upstream);
+                    }
+                });
+            }
+        });
+    }
+}
+
+impl Snapshot for AsGraph {
+    fn write(&self, w: &mut Writer) {
+        self.providers.write(w);
+        self.customers.write(w);
+        self.peers.write(w);
+        self.links.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(AsGraph {
+            providers: Snapshot::read(r)?,
+            customers: Snapshot::read(r)?,
+            peers: Snapshot::read(r)?,
+            links: Snapshot::read(r)?,
+        })
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn graph(lines: &[&str]) -> AsGraph {
+        let mut graph = AsGraph::default();
+        for line in lines {
+            graph.insert_line(line).unwrap();
+        }
+        graph
+    }
+
+    fn asns(asns: &[u32]) -> Vec<Asn> {
+        asns.iter().copied().map(Asn).collect()
+    }
+
+    #[test]
+    fn insert_line() {
+        let graph = graph(&["1299|3333|-1", "3333|1103|0", "174|3333|-1|bgp", "3333|64496|-1|mlp"]);
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.related(Asn(3333), AsRelation::Upstreams), asns(&[174, 1299]));
+        assert_eq!(graph.related(Asn(3333), AsRelation::Downstreams), asns(&[64496]));
+        assert_eq!(graph.related(Asn(3333), AsRelation::Peers), asns(&[1103]));
+        assert_eq!(graph.related(Asn(1103), AsRelation::Peers), asns(&[3333]));
+        assert_eq!(graph.related(Asn(1299), AsRelation::Downstreams), asns(&[3333]));
+        assert!(graph.related(Asn(1299), AsRelation::Upstreams).is_empty());
+        assert!(graph.related(Asn(64511), AsRelation::Peers).is_empty());
+    }
+
+    #[test]
+    fn insert_bad_line() {
+        let mut graph = AsGraph::default();
+        assert_eq!(graph.insert_line("1299|3333|1"), Err("unknown relationship '1'".into()));
+        assert_eq!(graph.insert_line("1299|3333"), Err("missing relationship".into()));
+        assert_eq!(graph.insert_line("1299"), Err("missing field".into()));
+        assert!(graph.insert_line("AS1299|3333|-1").unwrap_err().starts_with("invalid ASN 'AS1299'"));
+        assert!(graph.is_empty());
+    }
+
+    #[test]
+    fn customer_cone() {
+        // 64500 -> 64501 -> 64502 -> 64503 -> 64501 is a cycle, 64504 is a
+        // peer whose customers are not in the cone.
+        let graph = graph(&[
+            "64500|64501|-1",
+            "64501|64502|-1",
+            "64502|64503|-1",
+            "64503|64501|-1",
+            "64500|64504|0",
+            "64504|64505|-1",
+        ]);
+        assert_eq!(
+            graph.related(Asn(64500), AsRelation::CustomerCone),
+            asns(&[64500, 64501, 64502, 64503])
+        );
+        assert_eq!(
+            graph.related(Asn(64502), AsRelation::CustomerCone),
+            asns(&[64501, 64502, 64503])
+        );
+        assert_eq!(graph.related(Asn(64505), AsRelation::CustomerCone), asns(&[64505]));
+        assert_eq!(graph.related(Asn(64511), AsRelation::CustomerCone), asns(&[64511]));
+    }
+
+    #[test]
+    fn origin_upstreams_to_json() {
+        let json = |graph: &AsGraph, origins: &[Asn]| {
+            let json = JsonBuilder::build(|builder| graph.to_json_builder(origins, builder));
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()
+        };
+        assert_eq!(json(&AsGraph::default(), &[Asn(3333)]), serde_json::json!({}));
+
+        let graph = graph(&["1299|3333|-1", "174|3333|-1", "3333|1103|0"]);
+        assert_eq!(
+            json(&graph, &[Asn(3333), Asn(1103)]),
+            serde_json::json!({ "originUpstreams": [["AS174", "AS1299"], []] })
+        );
+    }
+
+    #[test]
+    fn relation_names() {
+        for relation in [
+            AsRelation::Upstreams,
+            AsRelation::Downstreams,
+            AsRelation::Peers,
+            AsRelation::CustomerCone,
+        ] {
+            assert_eq!(AsRelation::from_str(&relation.to_string()), Ok(relation));
+        }
+        assert!(AsRelation::from_str("siblings").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! The parts of BGP messages shared by the MRT and BMP readers.
+//!
+//! Only what is needed to find the origin of a prefix is decoded: path
+//! attributes are split up, AS_PATHs flattened and NLRI turned into
+//! prefixes. See RFC 4271 and RFC 4760.
+
+use crate::{Addr, Asn};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub(crate) const ATTR_TYPE_AS_PATH: u8 = 2;
+pub(crate) const ATTR_TYPE_MP_REACH_NLRI: u8 = 14;
+pub(crate) const ATTR_TYPE_MP_UNREACH_NLRI: u8 = 15;
+pub(crate) const ATTR_TYPE_AS4_PATH: u8 = 17;
+
+const ATTR_FLAG_EXTENDED_LENGTH: u8 = 0x10;
+
+const AS_SET: u8 = 1;
+const AS_SEQUENCE: u8 = 2;
+
+//------------ BgpPeer -------------------------------------------------------
+
+/// A BGP peer of a route collector or monitored router.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BgpPeer {
+    pub addr: IpAddr,
+    pub asn: Asn,
+}
+
+//------------ BgpUpdate -----------------------------------------------------
+
+/// A change to the routes of a live BGP source.
+#[derive(Clone, Debug)]
+pub enum BgpUpdate {
+    /// The source announced a route, replacing its previous route for the
+    /// prefix, if any.
+    Announce {
+        source: String,
+        peer: BgpPeer,
+        net: Addr,
+        len: u8,
+        as_path: AsPath,
+    },
+
+    /// The source withdrew its route for a prefix.
+    Withdraw { source: String, net: Addr, len: u8 },
+
+    /// The source is gone, together with all its routes.
+    PeerDown { source: String },
+}
+
+//------------ AsPath --------------------------------------------------------
+
+#[derive(Clone, Debug, Default)]
+pub struct AsPath {
+    /// The AS path, flattened. The members of an AS_SET are included in
+    /// the order they appear in.
+    pub asns: Vec<Asn>,
+
+    /// The ASNs the route originates from.
+    ///
+    /// This is the last ASN of the path, or all members of the AS_SET if
+    /// the path ends in one.
+    pub origin_asns: Vec<Asn>,
+}
+
+impl AsPath {
+    /// Parses the value of an AS_PATH or AS4_PATH attribute.
+    ///
+    /// Confederation segments are not part of the path as seen from
+    /// outside, so they are left out.
+    pub(crate) fn parse(value: &[u8], four_octet: bool) -> Result<Self, String> {
+        let mut value = Parser(value);
+        let mut path = AsPath::default();
+        while !value.0.is_empty() {
+            let segment_type = value.u8()?;
+            let count = value.u8()?;
+            let mut asns = Vec::with_capacity(count.into());
+            for _ in 0..count {
+                let asn = if four_octet {
+                    value.u32()?
+                } else {
+                    value.u16()?.into()
+                };
+                asns.push(Asn::from(asn));
+            }
+            match segment_type {
+                AS_SET => path.origin_asns = asns.clone(),
+                AS_SEQUENCE => path.origin_asns = asns.last().copied().into_iter().collect(),
+                _ => continue,
+            }
+            path.asns.extend(asns);
+        }
+        Ok(path)
+    }
+}
+
+//------------ Attribute -----------------------------------------------------
+
+/// A path attribute with its value still encoded.
+pub(crate) struct Attribute<'a> {
+    pub attr_type: u8,
+    pub value: &'a [u8],
+}
+
+/// Splits the path attributes of a route into the separate attributes.
+pub(crate) fn attributes(data: &[u8]) -> Result<Vec<Attribute<'_>>, String> {
+    let mut data = Parser(data);
+    let mut res = Vec::new();
+    while !data.0.is_empty() {
+        let flags = data.u8()?;
+        let attr_type = data.u8()?;
+        let len = if flags & ATTR_FLAG_EXTENDED_LENGTH != 0 {
+            data.u16()?
+        } else {
+            data.u8()?.into()
+        };
+        res.push(Attribute {
+            attr_type,
+            value: data.take(len.into())?,
+        });
+    }
+    Ok(res)
+}
+
+//------------ Parser --------------------------------------------------------
+
+/// The remainder of a message that is being parsed.
+pub(crate) struct Parser<'a>(pub &'a [u8]);
+
+impl<'a> Parser<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("message too short".into());
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let buf = self.take(2)?;
+        Ok(u16::from_be_bytes([buf[0], buf[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let buf = self.take(4)?;
+        Ok(u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]))
+    }
+
+    pub fn addr(&mut self, ipv6: bool) -> Result<IpAddr, String> {
+        if ipv6 {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(self.take(16)?);
+            Ok(Ipv6Addr::from(octets).into())
+        } else {
+            let mut octets = [0; 4];
+            octets.copy_from_slice(self.take(4)?);
+            Ok(Ipv4Addr::from(octets).into())
+        }
+    }
+
+    /// Parses a prefix in NLRI encoding: the length in bits followed by
+    /// as many octets of the address as needed.
+    pub fn prefix(&mut self, ipv6: bool) -> Result<(Addr, u8), String> {
+        let len = self.u8()?;
+        let max_len = if ipv6 { 128 } else { 32 };
+        if len > max_len {
+            return Err(format!("invalid prefix length {}", len));
+        }
+        let mut octets = [0; 16];
+        let prefix_bytes = usize::from(len).div_ceil(8);
+        octets[..prefix_bytes].copy_from_slice(self.take(prefix_bytes)?);
+        let net = if ipv6 {
+            Addr::V6(u128::from_be_bytes(octets))
+        } else {
+            Addr::V4(u32::from_be_bytes([octets[0], octets[1], octets[2], octets[3]]))
+        };
+        Ok((net, len))
+    }
+
+    /// Parses prefixes in NLRI encoding until the end of the data.
+    pub fn prefixes(&mut self, ipv6: bool) -> Result<Vec<(Addr, u8)>, String> {
+        let mut res = Vec::new();
+        while !self.is_empty() {
+            res.push(self.prefix(ipv6)?);
+        }
+        Ok(res)
+    }
+}
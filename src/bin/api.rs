@@ -1,95 +1,323 @@
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server, StatusCode};
+use roto_api::asrel::AsRelation;
+use roto_api::bgp::BgpUpdate;
+use roto_api::bmp::{read_message, BmpSession};
+use roto_api::changes::ChangeSet;
+use roto_api::config::{Config, CorsConfig, DataKind, Location, LogConfig, SourceConfig, DEFAULT_REFRESH};
+use roto_api::download::{DataDir, Downloader, Source, SourceKind, DEFAULT_FETCH_TIMEOUT};
+use roto_api::history::{self, History};
+use roto_api::iana::{IanaAllocationRecord, SpecialPurposeAsn};
+use roto_api::source::{PrefixRecord, SourceFilter, SourceType};
+use roto_api::verify::{Verification, Verify};
 use roto_api::{
-    version, Addr, Asn, JsonBuilder, Prefix, SearchByAsnOptions, SearchType, Store,
-    TimeStamp, TimeStamps,
+    version, Addr, Asn, JsonBuilder, LoadMode, Prefix, RecordSet, Rir, RirDelExtRecord,
+    SearchByAsnOptions, SearchType, Store,
 };
 use rotonda_store::{MatchOptions, MatchType};
 use std::convert::Infallible;
+use std::error::Error;
+use std::io::Write;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str::FromStr;
-use std::{env, process, thread};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use std::{env, fs, process, thread};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
 
 const CURRENT_API_VERSION: &str = "v1";
 
 //------------ process_tasks -------------------------------------------------
 
-fn process_tasks(store: Store, mut queue: mpsc::Receiver<(Task, oneshot::Sender<Response<Body>>)>) {
+type Queue = mpsc::Sender<(Task, oneshot::Sender<Response<Body>>)>;
+
+// The store is owned by this thread and all tasks are handled one after the
+// other, so swapping in a new store in between two tasks is atomic: queries
+// that were already picked up finish against the old store.
+//
+// Past stores are loaded from the history on the blocking threads of the
+// runtime, so that other tasks don't wait for them. The task that needed
+// one comes back through `queue_tx` with the loaded store.
+fn process_tasks(
+    mut store: Store,
+    history: Option<History>,
+    runtime: tokio::runtime::Handle,
+    queue_tx: Queue,
+    mut queue: mpsc::Receiver<(Task, oneshot::Sender<Response<Body>>)>,
+) {
+    let mut past = PastStores::default();
+
     while let Some((task, tx)) = queue.blocking_recv() {
+        let (task, past_store) = match task {
+            Task::PastStoreLoaded(time, past_store, task) => {
+                let past_store = Rc::new(*past_store);
+                past.insert(time, past_store.clone());
+                (*task, Some((time, past_store)))
+            }
+            task => match past.lookup(&store, history.as_ref(), task.at()) {
+                Ok(PastLookup::Current) => (task, None),
+                Ok(PastLookup::Cached(time, past_store)) => (task, Some((time, past_store))),
+                Ok(PastLookup::Load(time, path)) => {
+                    load_past_store(&runtime, queue_tx.clone(), time, path, task, tx);
+                    continue;
+                }
+                Err(err) => {
+                    let _err = tx.send(not_found_response(err));
+                    continue;
+                }
+            },
+        };
+        let (query_store, time) = match &past_store {
+            Some((time, past_store)) => (past_store.as_ref(), Some(*time)),
+            None => (&store, None),
+        };
+
         let res = match task {
             Task::PrefixMatch(MatchPrefixRequest {
                 prefix,
-                match_options,
-            }) => match_prefix_output(&store, prefix, match_options),
+                match_type,
+                relations,
+                sources,
+                at: _,
+            }) => match match_prefix_output(query_store, time, prefix, match_type, relations, &sources) {
+                Ok(output) => ok_cors_response(output),
+                Err(err) => not_found_response(err),
+            },
             Task::ByAsnSearch(SearchByAsnRequest {
                 asns,
                 search_options,
-            }) => search_by_bgp_asn_output(&store, asns, search_options),
+                sources,
+                at: _,
+            }) => ok_cors_response(search_by_bgp_asn_output(query_store, time, asns, search_options, &sources)),
+            Task::AsRelations(AsRelationsRequest { asn, relation }) => {
+                ok_cors_response(as_relations_output(&store, asn, relation))
+            }
+            Task::RdapIp(RdapIpRequest { prefix, self_url }) => {
+                rdap_ip_output(&store, prefix, &self_url)
+            }
+            Task::RdapAutnum(RdapAutnumRequest { asn, self_url }) => {
+                rdap_autnum_output(&store, asn, &self_url)
+            }
+            Task::Changes(ChangesRequest { since: _, format }) => {
+                changes_response(&ChangeSet::between(query_store, &store), format)
+            }
+            Task::Status => ok_cors_response(status_output(&store, history.as_ref())),
+            Task::Diagnostics => ok_cors_response(diagnostics_output(&store)),
+            Task::ReplaceStore(mut new_store) => {
+                if let Err(err) = new_store.copy_live_routes(&store) {
+                    error!("Failed to keep the routes of the BMP feeds: {}", err);
+                }
+                store = *new_store;
+                ok_cors_response(status_output(&store, history.as_ref()))
+            }
+            Task::BgpUpdates(updates) => {
+                if let Err(err) = store.apply_bgp_updates(updates) {
+                    error!("Failed to apply BMP updates: {}", err);
+                }
+                ok_cors_response(String::new())
+            }
+            Task::PastStoreLoaded(..) => unreachable!("unwrapped above"),
         };
 
-        let _err = tx.send(
-            Response::builder()
-                .status(hyper::StatusCode::OK)
-                .header(hyper::header::CONTENT_TYPE, "application/json")
-                .header(hyper::header::ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS")
-                .header(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS,"DNT,User-Agent,X-Requested-With,If-Modified-Since,Cache-Control,Content-Type,Range")
-                .header(hyper::header::ACCESS_CONTROL_EXPOSE_HEADERS,"Content-Length,Content-Range")
-                .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-                .body(hyper::Body::from(res))
-                .unwrap()
-        );
+        let _err = tx.send(res);
+    }
+}
+
+/// Loads a past store on a blocking thread and queues `task` again with
+/// it.
+///
+/// If loading fails, the task is answered with an error right away.
+fn load_past_store(
+    runtime: &tokio::runtime::Handle,
+    queue_tx: Queue,
+    time: DateTime<Utc>,
+    path: PathBuf,
+    task: Task,
+    tx: oneshot::Sender<Response<Body>>,
+) {
+    let at = task.at().unwrap_or(time);
+    runtime.spawn_blocking(move || match Store::load_snapshot(&path) {
+        Ok(past_store) => {
+            let task = Task::PastStoreLoaded(time, Box::new(past_store), Box::new(task));
+            let _err = queue_tx.blocking_send((task, tx));
+        }
+        Err(err) => {
+            error!("Cannot load snapshot {}: {}", path.display(), err);
+            let _err = tx.send(not_found_response(format!(
+                "Cannot load the data for {}.",
+                at.format("%+")
+            )));
+        }
+    });
+}
+
+//------------ PastStores ----------------------------------------------------
+
+/// How many past stores are kept in memory.
+const PAST_STORES: usize = 4;
+
+/// The past stores that were queried last, most recently used last.
+#[derive(Default)]
+struct PastStores {
+    stores: Vec<(DateTime<Utc>, Rc<Store>)>,
+}
+
+/// Where the store to answer a query from is to be found.
+enum PastLookup {
+    /// The query is answered from the current store.
+    Current,
+
+    /// The past store in effect at that time is in memory.
+    Cached(DateTime<Utc>, Rc<Store>),
+
+    /// The snapshot in effect at that time needs to be loaded.
+    Load(DateTime<Utc>, PathBuf),
+}
+
+impl PastStores {
+    /// Returns where to find the store to answer a query for the data at
+    /// `at`.
+    ///
+    /// Queries without `at` or for a time after the current store was
+    /// loaded are answered from the current store. Otherwise the snapshot
+    /// in effect at that time is taken from the history.
+    fn lookup(
+        &mut self,
+        store: &Store,
+        history: Option<&History>,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<PastLookup, String> {
+        let at = match at {
+            Some(at) if at < store.updated() => at,
+            _ => return Ok(PastLookup::Current),
+        };
+        let history = history.ok_or("This API keeps no past data.")?;
+        let (time, path) = history
+            .find(at)
+            .map_err(|err| {
+                error!("Cannot read history {}: {}", history.dir().display(), err);
+                "Cannot read past data.".to_string()
+            })?
+            .ok_or_else(|| format!("No data for {}.", at.format("%+")))?;
+        match self.stores.iter().position(|(past_time, _)| *past_time == time) {
+            Some(idx) => {
+                let entry = self.stores.remove(idx);
+                self.stores.push(entry.clone());
+                Ok(PastLookup::Cached(entry.0, entry.1))
+            }
+            None => Ok(PastLookup::Load(time, path)),
+        }
+    }
+
+    /// Adds a loaded past store, dropping the least recently used one if
+    /// there are too many.
+    fn insert(&mut self, time: DateTime<Utc>, store: Rc<Store>) {
+        self.stores.retain(|(past_time, _)| *past_time != time);
+        if self.stores.len() >= PAST_STORES {
+            self.stores.remove(0);
+        }
+        self.stores.push((time, store));
+    }
+}
+
+pub fn status_output(store: &Store, history: Option<&History>) -> String {
+    JsonBuilder::build(|builder| {
+        builder.member_str("version", format!("roto-api/{}", version()));
+        builder.member_str("lastLoaded", store.updated().format("%+"));
+        builder.member_raw("skippedLines", store.load_report().len());
+        store.sources().to_json_builder(builder);
+        builder.member_array("history", |builder| {
+            for time in history.map(History::dates).unwrap_or_default() {
+                builder.array_str(time.format("%+"));
+            }
+        });
+    })
+}
+
+/// Returns the changes in JSON or CSV.
+///
+/// The `serial` of the JSON output is the `since` to ask for the changes
+/// after these.
+fn changes_response(changes: &ChangeSet, format: ChangesFormat) -> Response<Body> {
+    match format {
+        ChangesFormat::Json => ok_cors_response(JsonBuilder::build(|builder| {
+            builder.member_raw("serial", changes.until.timestamp());
+            changes.to_json_builder(builder);
+        })),
+        ChangesFormat::Csv => match changes.to_csv() {
+            Ok(csv) => ok_cors_typed_response("text/csv", csv),
+            Err(err) => {
+                error!("Failed to write the changes as CSV: {}", err);
+                internal_server_error()
+            }
+        },
     }
 }
 
-pub fn match_prefix_output(store: &Store, prefix: Prefix, match_options: MatchOptions) -> String {
+pub fn diagnostics_output(store: &Store) -> String {
+    JsonBuilder::build(|builder| {
+        builder.member_str("lastLoaded", store.updated().format("%+"));
+        store.load_report().to_json_builder(builder);
+    })
+}
+
+/// Returns the result of a prefix search.
+///
+/// An exact match for a prefix that isn't there is an error rather than an
+/// empty result, so it can be told apart from a prefix without data.
+pub fn match_prefix_output(
+    store: &Store,
+    snapshot: Option<DateTime<Utc>>,
+    prefix: Prefix,
+    match_type: MatchType,
+    relations: Relations,
+    sources: &SourceFilter,
+) -> Result<String, String> {
+    let match_options = relations.match_options(match_type);
     let recs = match prefix.addr {
         Addr::V4(_addr) => store.match_longest_prefix::<u32>(prefix, &match_options),
         Addr::V6(_addr) => store.match_longest_prefix::<u128>(prefix, &match_options),
     };
+    if let (MatchType::ExactMatch, None) = (&match_options.match_type, recs.prefix) {
+        return Err(format!("Prefix {} is not present.", prefix));
+    }
     let query_result = recs.clone();
 
-    JsonBuilder::build(|builder| {
-        builder.member_str("type", match_options.match_type);
+    Ok(JsonBuilder::build(|builder| {
+        builder.member_str("type", &match_options.match_type);
         builder.member_str("prefix", prefix);
+        if let Some(snapshot) = snapshot {
+            builder.member_str("snapshot", snapshot.format("%+"));
+        }
         builder.member_object("result", |builder| {
             if let Some(pfx) = query_result.prefix {
                 builder.member_str("prefix", pfx);
                 if let Some(ext_rec) = query_result.prefix_meta {
                     builder.member_array("meta", |builder| {
-                        // rir delegated extended records
-                        match &ext_rec.0 {
-                            Some(rir_del_ext_r) => {
-                                builder.array_object(|builder| {
-                                    builder.member_str("sourceType", "rir-alloc");
-                                    builder.member_str("sourceID", rir_del_ext_r.rir.to_json_id());
-                                });
-                            }
-                            None => {}
-                        }
-                        // rishwhois records
-                        match &ext_rec.1 {
-                            Some(ris_whois_r) => {
-                                builder.array_object(|builder| {
-                                    builder.member_str("sourceType", "bgp");
-                                    builder.member_str("sourceID", "riswhois");
-                                    builder.member_array("originASNs", |builder| {
-                                        for asn in ris_whois_r.origin_asns.0.iter() {
-                                            builder.array_str(asn)
-                                        }
-                                    });
-                                    builder.member_str(
-                                        "type",
-                                        if prefix.len == pfx.len {
-                                            "exact-match"
-                                        } else {
-                                            "less-specific"
-                                        },
-                                    )
-                                });
+                        let unallocated = ext_rec.unallocated(pfx, store);
+                        for rec in ext_rec.iter() {
+                            let source = store.sources().get(rec.source);
+                            if !sources.matches(source.source_type, &source.id) {
+                                continue;
                             }
-                            None => {}
+                            builder.array_object(|builder| {
+                                store.source_record_to_json_builder(pfx, ext_rec, rec, unallocated, builder);
+                                builder.member_str(
+                                    "type",
+                                    if prefix.len == pfx.len {
+                                        "exact-match"
+                                    } else {
+                                        "less-specific"
+                                    },
+                                )
+                            });
                         }
                     });
                 };
@@ -100,194 +328,72 @@ pub fn match_prefix_output(store: &Store, prefix: Prefix, match_options: MatchOp
                 builder.member_raw("members", "[]");
             }
 
-            // See whether the result_prefix has a DelExtRecord, if it does
-            // then we're using that, if it doesn't we can see if there's a
-            // less-specific prefix that has one.
-            // The vecs in a RecordSet are ordered from least to most specific,
-            // hence the reverse. That way we'll get the longest-matching prefix
-            // with a RirDelExtRecord.
-            // The resulting prefix is then used to lookup all the related prefixes.
-
-            if let Some(rec) = query_result.prefix_meta {
-                let rev = recs.less_specifics.reverse();
-
-                let lmp_rel_rec = if rec.0.is_some() {
-                    rec.0.as_ref()
-                } else {
-                    rev.iter().find_map(|(_p, r)| match r {
-                        Some(rec) => rec.0.as_ref(),
-                        None => None,
-                    })
-                };
+            // The IANA block the requested prefix falls into tells who is
+            // responsible for it, even if no RIR delegated it.
+            match store.get_iana_allocation(prefix) {
+                Some((iana_pfx, iana_r)) => builder.member_object("allocation", |builder| {
+                    builder.member_str("prefix", iana_pfx);
+                    builder.member_str("sourceType", IanaAllocationRecord::SOURCE_TYPE);
+                    builder.member_str("sourceID", "iana");
+                    iana_r.to_json_builder(builder);
+                    if let Some(unallocated) = store.is_unallocated(prefix) {
+                        builder.member_raw("unallocated", unallocated);
+                    }
+                }),
+                None => builder.member_raw("allocation", "null"),
+            }
 
-                if let Some(lmp_rel_rec) = lmp_rel_rec {
-                    println!("lmp rec {:?}", lmp_rel_rec);
-                    let rel_rec = store.get_related_prefixes(lmp_rel_rec);
-                    builder.member_array("relations", |builder| {
-                        builder.array_object(|builder| {
-                            builder.member_str("type", "same-org");
-                            builder.member_array("members", |builder| {
-                                for (pfx, value) in rel_rec.iter() {
-                                    builder.array_object(|builder| {
-                                        builder.member_str("prefix", pfx);
-                                        builder.member_array("meta", |builder| {
-                                            if let Some(ext_rec) = value {
-                                                match &ext_rec.0 {
-                                                    Some(rir_del_ext_r) => {
-                                                        builder.array_object(|builder| {
-                                                            builder.member_str(
-                                                                "sourceType",
-                                                                "rir-alloc",
-                                                            );
-                                                            builder.member_str(
-                                                                "sourceID",
-                                                                rir_del_ext_r.rir.to_json_id(),
-                                                            );
-                                                        });
-                                                    }
-                                                    None => {}
-                                                }
-                                                match &ext_rec.1 {
-                                                    Some(ris_whois_r) => {
-                                                        builder.array_object(|builder| {
-                                                            builder.member_str("sourceType", "bgp");
-                                                            builder
-                                                                .member_str("sourceID", "riswhois");
-                                                            builder.member_array(
-                                                                "originASNs",
-                                                                |builder| {
-                                                                    for asn in ris_whois_r
-                                                                        .origin_asns
-                                                                        .0
-                                                                        .iter()
-                                                                    {
-                                                                        builder.array_str(asn)
-                                                                    }
-                                                                },
-                                                            );
-                                                        });
-                                                    }
-                                                    None => {}
-                                                }
-                                            }
-                                        })
-                                    });
-                                }
-                            });
-                        });
-                        builder.array_object(|builder| {
-                            builder.member_str("type", "less-specific");
-                            builder.member_array("members", |builder| {
-                                for (pfx, value) in query_result.less_specifics.iter() {
-                                    builder.array_object(|builder| {
-                                        builder.member_str("prefix", pfx);
-                                        builder.member_array("meta", |builder| {
-                                            if let Some(ext_rec) = value {
-                                                match &ext_rec.0 {
-                                                    Some(rir_del_ext_r) => {
-                                                        builder.array_object(|builder| {
-                                                            builder.member_str(
-                                                                "sourceType",
-                                                                "rir-alloc",
-                                                            );
-                                                            builder.member_str(
-                                                                "sourceID",
-                                                                rir_del_ext_r.rir.to_json_id(),
-                                                            );
-                                                        });
-                                                    }
-                                                    None => {}
-                                                }
-                                                match &ext_rec.1 {
-                                                    Some(ris_whois_r) => {
-                                                        builder.array_object(|builder| {
-                                                            builder.member_str("sourceType", "bgp");
-                                                            builder
-                                                                .member_str("sourceID", "riswhois");
-                                                            builder.member_array(
-                                                                "originASNs",
-                                                                |builder| {
-                                                                    for asn in ris_whois_r
-                                                                        .origin_asns
-                                                                        .0
-                                                                        .iter()
-                                                                    {
-                                                                        builder.array_str(asn)
-                                                                    }
-                                                                },
-                                                            );
-                                                        });
-                                                    }
-                                                    None => {}
-                                                }
-                                            }
-                                        })
-                                    });
-                                }
-                            });
-                        });
-                        builder.array_object(|builder| {
-                            builder.member_str("type", "more-specific");
-                            builder.member_array("members", |builder| {
-                                for (pfx, value) in query_result.more_specifics.iter() {
-                                    builder.array_object(|builder| {
-                                        builder.member_str("prefix", pfx);
-                                        builder.member_array("meta", |builder| {
-                                            if let Some(ext_rec) = value {
-                                                match &ext_rec.0 {
-                                                    Some(rir_del_ext_r) => {
-                                                        builder.array_object(|builder| {
-                                                            builder.member_str(
-                                                                "sourceType",
-                                                                "rir-alloc",
-                                                            );
-                                                            builder.member_str(
-                                                                "sourceID",
-                                                                rir_del_ext_r.rir.to_json_id(),
-                                                            );
-                                                        });
-                                                    }
-                                                    None => {}
-                                                }
-                                                match &ext_rec.1 {
-                                                    Some(ris_whois_r) => {
-                                                        builder.array_object(|builder| {
-                                                            builder.member_str("sourceType", "bgp");
-                                                            builder
-                                                                .member_str("sourceID", "riswhois");
-                                                            builder.member_array(
-                                                                "originASNs",
-                                                                |builder| {
-                                                                    for asn in ris_whois_r
-                                                                        .origin_asns
-                                                                        .0
-                                                                        .iter()
-                                                                    {
-                                                                        builder.array_str(asn)
-                                                                    }
-                                                                },
-                                                            );
-                                                        });
-                                                    }
-                                                    None => {}
-                                                }
-                                            }
-                                        })
-                                    });
-                                }
+            // The relations are those of the delegation the matched prefix
+            // falls into, the matched prefix itself or the longest-matching
+            // less-specific prefix with a RirDelExtRecord. An empty match
+            // has no matched prefix, it only asks for the relations of the
+            // requested one.
+            let members = |builder: &mut JsonBuilder, kind: &str, set: &RecordSet| {
+                builder.array_object(|builder| {
+                    builder.member_str("type", kind);
+                    builder.member_array("members", |builder| {
+                        for (pfx, value) in set.iter() {
+                            builder.array_object(|builder| {
+                                builder.member_str("prefix", pfx);
+                                builder.member_array("meta", |builder| {
+                                    if let Some(ext_rec) = value {
+                                        ext_rec.to_json_builder(pfx, store, sources, builder);
+                                    }
+                                })
                             });
-                        })
+                        }
                     });
-                }
+                });
+            };
+            let empty_match = matches!(match_options.match_type, MatchType::EmptyMatch);
+            let delegation = match (query_result.prefix, query_result.prefix_meta) {
+                _ if empty_match => store.get_delegation(prefix),
+                (Some(pfx), Some(_)) => store.get_delegation(pfx),
+                _ => None,
+            };
+            if delegation.is_some() || empty_match {
+                builder.member_array("relations", |builder| {
+                    if let Some((_, lmp_rel_rec)) = delegation.filter(|_| relations.same_org) {
+                        members(builder, "same-org", &store.get_related_prefixes(lmp_rel_rec));
+                    }
+                    if relations.less_specific {
+                        members(builder, "less-specific", &query_result.less_specifics);
+                    }
+                    if relations.more_specific {
+                        members(builder, "more-specific", &query_result.more_specifics);
+                    }
+                });
             }
         });
-    })
+    }))
 }
 
 pub fn search_by_bgp_asn_output(
     store: &Store,
+    snapshot: Option<DateTime<Utc>>,
     asns: Vec<Asn>,
     search_options: SearchByAsnOptions,
+    sources: &SourceFilter,
 ) -> String {
     let recs = store.get_prefixes_for_bgp_asn(&asns, &search_options);
     // let query_result = recs.clone();
@@ -295,18 +401,49 @@ pub fn search_by_bgp_asn_output(
     JsonBuilder::build(|builder| {
         builder.member_str("type", &search_options.search_type);
         builder.member_array("asns", |builder| {
-            for asn in asns.into_iter() {
+            for asn in asns.iter() {
                 builder.array_str(asn);
             }
         });
-        builder.member_raw("meta", "null");
+        if let Some(snapshot) = snapshot {
+            builder.member_str("snapshot", snapshot.format("%+"));
+        }
+        builder.member_array("meta", |builder| {
+            for asn in asns.iter() {
+                let delegation = store
+                    .get_asn_delegation(*asn)
+                    .filter(|rec| sources.matches(SourceType::RirAlloc, &rec.record.rir.to_json_id()));
+                if let Some(delegation) = delegation {
+                    builder.array_object(|builder| {
+                        builder.member_str("asn", asn);
+                        delegation.to_json_builder(builder);
+                    });
+                }
+                let special =
+                    SpecialPurposeAsn::find(*asn).filter(|_| sources.matches(SourceType::IanaSpecial, "iana"));
+                if let Some(special) = special {
+                    builder.array_object(|builder| {
+                        builder.member_str("asn", asn);
+                        special.to_json_builder(builder);
+                    });
+                }
+                let name = store
+                    .as_names()
+                    .get(*asn)
+                    .filter(|name| sources.matches(SourceType::AsName, name.source));
+                if let Some(name) = name {
+                    builder.array_object(|builder| {
+                        builder.member_str("asn", asn);
+                        builder.member_str("sourceType", "as-name");
+                        builder.member_str("sourceID", name.source);
+                        name.to_json_builder(builder);
+                    });
+                }
+            }
+        });
         builder.member_object("result", |builder| {
             builder.member_array("relations", |builder| {
                 builder.array_object(|builder| {
-                    // if let Some(asn) = query_result.asns.get(0) {
-                    // builder.member_array("asns", |builder| {
-                    //     builder.array_str(asn);
-                    // });
                     builder.member_str("type", "bgp-origin-asn");
                     builder.member_array("members", |builder| {
                         for (pfx, value) in recs.prefixes.iter() {
@@ -314,103 +451,341 @@ pub fn search_by_bgp_asn_output(
                                 builder.member_str("prefix", pfx);
                                 builder.member_array("meta", |builder| {
                                     if let Some(ext_rec) = value {
-                                        match &ext_rec.0 {
-                                            Some(rir_del_ext_r) => {
-                                                builder.array_object(|builder| {
-                                                    builder.member_str("sourceType", "rir-alloc");
-                                                    builder.member_str(
-                                                        "sourceID",
-                                                        rir_del_ext_r.rir.to_json_id(),
-                                                    );
-                                                });
-                                            }
-                                            None => {}
-                                        }
-                                        match &ext_rec.1 {
-                                            Some(ris_whois_r) => {
-                                                builder.array_object(|builder| {
-                                                    builder.member_str("sourceType", "bgp");
-                                                    builder.member_str("sourceID", "riswhois");
-                                                    builder.member_array("originASNs", |builder| {
-                                                        for asn in ris_whois_r.origin_asns.0.iter()
-                                                        {
-                                                            builder.array_str(asn)
-                                                        }
-                                                    });
-                                                });
-                                            }
-                                            None => {}
-                                        }
+                                        ext_rec.to_json_builder(pfx, store, sources, builder);
                                     }
                                 })
                             });
                         }
                     });
-                    // } else {
-                    //     builder.member_raw("asn", "null");
-                    //     builder.member_raw("relations", "null");
-                    // }
                 });
             });
         });
     })
 }
 
-pub fn import_timestamps() -> Result<TimeStamps, Box<dyn std::error::Error>> {
-    const TIMESTAMPS_FILE_PREFIX: &str = ".timestamps.json";
-    let mut timestamps: TimeStamps = Default::default();
-    for dataset in &["del_ext", "riswhois"] {
-        let f_name = format!("./data/{}{}", dataset, TIMESTAMPS_FILE_PREFIX);
-        let ts_file = std::fs::File::open(f_name)?;
-        let mut rdr = csv::ReaderBuilder::new()
-            .delimiter(b',')
-            .flexible(true)
-            .trim(csv::Trim::Headers)
-            .from_reader(ts_file);
+pub fn as_relations_output(store: &Store, asn: Asn, relation: AsRelation) -> String {
+    let related = store.as_graph().related(asn, relation);
+    JsonBuilder::build(|builder| {
+        builder.member_str("type", relation);
+        builder.member_str("asn", asn);
+        builder.member_raw("count", related.len());
+        builder.member_array("result", |builder| {
+            for asn in related {
+                builder.array_object(|builder| {
+                    builder.member_str("asn", asn);
+                    if let Some(name) = store.as_names().get(asn) {
+                        name.to_json_builder(builder);
+                    }
+                });
+            }
+        });
+    })
+}
+
+//------------ RDAP ----------------------------------------------------------
+
+// The RDAP responses (RFC 9083) are built from the delegated-extended
+// records and the BGP origins, so RDAP clients can use the api as an
+// offline mirror of the RIRs. Links point to the RDAP service of the RIR
+// that holds the authoritative registration.
 
-        for record in rdr.records() {
-            let record = record?;
+pub fn rdap_ip_output(store: &Store, prefix: Prefix, self_url: &str) -> Response<Body> {
+    let (net, delegation) = match store.get_delegation(prefix) {
+        Some(delegation) => delegation,
+        None => {
+            return rdap_error_response(
+                StatusCode::NOT_FOUND,
+                format!("No delegation found for {}", prefix),
+            )
+        }
+    };
+    // The delegation may be larger than this one prefix of it.
+    let (start, end) = delegation.range.unwrap_or_else(|| net.range());
+    rdap_response(
+        StatusCode::OK,
+        JsonBuilder::build(|builder| {
+            builder.member_array("rdapConformance", |builder| builder.array_str("rdap_level_0"));
+            builder.member_str("objectClassName", "ip network");
+            builder.member_str("handle", net);
+            builder.member_str("startAddress", start);
+            builder.member_str("endAddress", end);
+            match net.addr {
+                Addr::V4(_) => builder.member_str("ipVersion", "v4"),
+                Addr::V6(_) => builder.member_str("ipVersion", "v6"),
+            }
+            builder.member_str("type", delegation.status.to_string().to_uppercase());
+            rdap_registration_to_json_builder(store, delegation, &format!("ip/{}", net), self_url, builder);
+            if let Some((announced, origins)) = store.get_bgp_origins(prefix) {
+                builder.member_array("remarks", |builder| {
+                    builder.array_object(|builder| {
+                        builder.member_str("title", format!("BGP origins of {}", announced));
+                        builder.member_array("description", |builder| {
+                            for origin in origins {
+                                builder.array_str(origin);
+                            }
+                        });
+                    });
+                });
+            }
+        }),
+    )
+}
 
-            timestamps.push(TimeStamp(
-                record[0].into(),
-                record[1].parse::<u64>().unwrap(),
-                DateTime::parse_from_rfc2822(&record[2]).unwrap(),
-            ))?;
+pub fn rdap_autnum_output(store: &Store, asn: Asn, self_url: &str) -> Response<Body> {
+    let delegation = match store.get_asn_delegation(asn) {
+        Some(delegation) => delegation,
+        None => {
+            return rdap_error_response(
+                StatusCode::NOT_FOUND,
+                format!("No delegation found for {}", asn),
+            )
         }
+    };
+    let announced = store.get_prefixes_for_bgp_asn(
+        &[asn],
+        &SearchByAsnOptions {
+            search_type: SearchType::PrefixesByBgpAsn,
+        },
+    );
+    rdap_response(
+        StatusCode::OK,
+        JsonBuilder::build(|builder| {
+            builder.member_array("rdapConformance", |builder| builder.array_str("rdap_level_0"));
+            builder.member_str("objectClassName", "autnum");
+            builder.member_str("handle", asn);
+            builder.member_raw("startAutnum", u32::from(delegation.first));
+            builder.member_raw("endAutnum", u32::from(delegation.last));
+            if let Some(name) = store.as_names().get(asn) {
+                builder.member_str("name", &name.name);
+            }
+            builder.member_str("type", delegation.record.status.to_string().to_uppercase());
+            let path = format!("autnum/{}", u32::from(asn));
+            rdap_registration_to_json_builder(store, &delegation.record, &path, self_url, builder);
+            builder.member_array("remarks", |builder| {
+                builder.array_object(|builder| {
+                    builder.member_str("title", format!("BGP announcements originated by {}", asn));
+                    builder.member_array("description", |builder| {
+                        for (pfx, _) in announced.prefixes.iter() {
+                            builder.array_str(pfx);
+                        }
+                    });
+                });
+            });
+        }),
+    )
+}
+
+/// Adds the members describing the registration by a RIR to an RDAP
+/// object.
+///
+/// `path` is the path of the object relative to the RDAP base URL of the
+/// RIR, e.g. "ip/193.0.0.0/21".
+fn rdap_registration_to_json_builder(
+    store: &Store,
+    record: &RirDelExtRecord,
+    path: &str,
+    self_url: &str,
+    builder: &mut JsonBuilder,
+) {
+    if let Some(country) = &record.country {
+        builder.member_str("country", country);
     }
-    Ok(timestamps)
+    builder.member_array("status", |builder| builder.array_str("active"));
+    builder.member_array("entities", |builder| {
+        builder.array_object(|builder| {
+            builder.member_str("objectClassName", "entity");
+            builder.member_str("handle", record.org_handle());
+            builder.member_array("roles", |builder| builder.array_str("registrant"));
+        });
+    });
+    builder.member_array("events", |builder| {
+        if let Some(date) = record.date {
+            builder.array_object(|builder| {
+                builder.member_str("eventAction", "registration");
+                builder.member_str("eventDate", date.format("%Y-%m-%dT00:00:00Z"));
+            });
+        }
+        builder.array_object(|builder| {
+            builder.member_str("eventAction", "last update of RDAP database");
+            builder.member_str("eventDate", store.updated().format("%Y-%m-%dT%H:%M:%SZ"));
+        });
+    });
+    builder.member_array("links", |builder| {
+        builder.array_object(|builder| {
+            builder.member_str("value", self_url);
+            builder.member_str("rel", "self");
+            builder.member_str("href", self_url);
+            builder.member_str("type", "application/rdap+json");
+        });
+        if let Some(base) = record.rir.rdap_url() {
+            builder.array_object(|builder| {
+                builder.member_str("value", self_url);
+                builder.member_str("rel", "related");
+                builder.member_str("href", format!("{}{}", base, path));
+                builder.member_str("type", "application/rdap+json");
+            });
+        }
+    });
+    builder.member_array("notices", |builder| {
+        builder.array_object(|builder| {
+            builder.member_str("title", "Source");
+            builder.member_array("description", |builder| {
+                builder.array_str(format!(
+                    "Taken from the delegated-extended statistics of {} by roto-api/{}.",
+                    record.rir,
+                    version()
+                ));
+                builder.array_str("The authoritative registration is available from the related link.");
+            });
+        });
+    });
 }
 
 //------------ process_request -----------------------------------------------
 
 struct MatchPrefixRequest {
     prefix: Prefix,
-    match_options: MatchOptions,
+    match_type: MatchType,
+    relations: Relations,
+    sources: SourceFilter,
+
+    /// The time of the data to answer from, the current data if `None`.
+    at: Option<DateTime<Utc>>,
 }
 
 struct SearchByAsnRequest {
     asns: Vec<Asn>,
     search_options: SearchByAsnOptions,
+    sources: SourceFilter,
+
+    /// The time of the data to answer from, the current data if `None`.
+    at: Option<DateTime<Utc>>,
+}
+
+struct AsRelationsRequest {
+    asn: Asn,
+    relation: AsRelation,
+}
+
+struct RdapIpRequest {
+    prefix: Prefix,
+    self_url: String,
+}
+
+struct RdapAutnumRequest {
+    asn: Asn,
+    self_url: String,
+}
+
+struct ChangesRequest {
+    since: DateTime<Utc>,
+    format: ChangesFormat,
+}
+
+#[derive(Clone, Copy)]
+enum ChangesFormat {
+    Json,
+    Csv,
+}
+
+/// The relations of the matched prefix a prefix search includes.
+#[derive(Clone, Copy, Debug)]
+pub struct Relations {
+    same_org: bool,
+    less_specific: bool,
+    more_specific: bool,
+}
+
+impl Relations {
+    const ALL: Relations = Relations {
+        same_org: true,
+        less_specific: true,
+        more_specific: true,
+    };
+    const NONE: Relations = Relations {
+        same_org: false,
+        less_specific: false,
+        more_specific: false,
+    };
+
+    /// Sets whether a relation is included, by its name in the output.
+    fn set(&mut self, name: &str, include: bool) -> Result<(), String> {
+        match name {
+            "same-org" => self.same_org = include,
+            "less-specific" => self.less_specific = include,
+            "more-specific" => self.more_specific = include,
+            _ => {
+                return Err(format!(
+                    "Unknown relation `{}`. Current relations are: `same-org`, `less-specific`, `more-specific`.",
+                    name
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the options for a search of the store.
+    ///
+    /// The less- and more-specific prefixes are only collected if their
+    /// relation is included, since there can be a great many of them.
+    fn match_options(self, match_type: MatchType) -> MatchOptions {
+        MatchOptions {
+            match_type,
+            include_less_specifics: self.less_specific,
+            include_more_specifics: self.more_specific,
+        }
+    }
 }
 
 enum Task {
     PrefixMatch(MatchPrefixRequest),
     ByAsnSearch(SearchByAsnRequest),
+    AsRelations(AsRelationsRequest),
+    RdapIp(RdapIpRequest),
+    RdapAutnum(RdapAutnumRequest),
+    Changes(ChangesRequest),
+    Status,
+    Diagnostics,
+    ReplaceStore(Box<Store>),
+    BgpUpdates(Vec<BgpUpdate>),
+
+    /// A task for past data, queued again once its store was loaded.
+    PastStoreLoaded(DateTime<Utc>, Box<Store>, Box<Task>),
+}
+
+impl Task {
+    /// Returns the time of the data the task asks for, if it isn't the
+    /// current data.
+    fn at(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Task::PrefixMatch(req) => req.at,
+            Task::ByAsnSearch(req) => req.at,
+            Task::Changes(req) => Some(req.since),
+            _ => None,
+        }
+    }
 }
 
 async fn process_request(
     req: Request<Body>,
-    timestamps: TimeStamps,
     tx: mpsc::Sender<(Task, oneshot::Sender<Response<Body>>)>,
 ) -> Result<Response<Body>, Infallible> {
-    let match_options = MatchOptions {
-        match_type: MatchType::LongestMatch,
-        include_less_specifics: true,
-        include_more_specifics: true,
+    let mut url = req.uri().path().split('/');
+    debug!("{:?}", req.uri().path());
+
+    // Searches can be answered from past data with `?at=<date>`.
+    let at = match query_param(&req, "at").map(|at| history::parse_at(&at)) {
+        Some(Ok(at)) => Some(at),
+        Some(Err(err)) => return not_found(Some(format!("Cannot parse `at`: {}", err))),
+        None => None,
     };
 
-    let mut url = req.uri().path().split('/');
-    println!("{:?}", req.uri().path());
+    // Searches show the records of the sources selected with
+    // `include[sourceType]` and friends.
+    let sources = match source_filter(&req) {
+        Ok(sources) => sources,
+        Err(err) => return not_found(Some(err)),
+    };
 
     let _slash = url.next();
 
@@ -418,6 +793,12 @@ async fn process_request(
     // both single-domain API/UI and separate domain hosting.
     let api_v1 = url.next();
 
+    // RDAP clients expect the object type right below the base URL, so the
+    // RDAP endpoints live outside of the versioned api.
+    if api_v1 == Some("rdap") {
+        return process_rdap_request(&req, url, tx).await;
+    }
+
     if api_v1.as_ref() != Some(&CURRENT_API_VERSION) && api_v1.as_ref() != Some(&"api") {
         return not_found(Some(
             "Cannot parse query. Request url should start with `[api]/v1`".to_string(),
@@ -452,7 +833,7 @@ async fn process_request(
                             .member_str("description", "Prefix with enriched data from data sources");
                         builder.member_str(
                             "syntax",
-                            "/api/v1/prefix/<IP_ADDRESS>/<PREFIX_LENGTH>/search[?include[relations]=[bgp|rir-alloc]]",
+                            "/api/v1/prefix/<IP_ADDRESS>/<PREFIX_LENGTH>/search[?match=longest|exact|empty][&include[relations]=same-org,less-specific,more-specific]",
                         );
                         builder.member_str("uri", format!("https://{}{}prefix/", host, uri));
                     });
@@ -462,53 +843,131 @@ async fn process_request(
                         builder.member_raw("syntax", "null");
                         builder.member_str("uri", format!("https://{}{}status", host, uri))
                     });
+                    builder.array_object(|builder| {
+                        builder.member_str("id", "rdap");
+                        builder.member_str("description", "RDAP ip network and autnum objects built from the RIR delegations");
+                        builder.member_str("syntax", "/rdap/ip/<IP_ADDRESS>[/<PREFIX_LENGTH>], /rdap/autnum/<ASN>");
+                        builder.member_str("uri", format!("https://{}/rdap/", host));
+                    });
+                    builder.array_object(|builder| {
+                        builder.member_str("id", "changes");
+                        builder.member_str("description", "Changes of the delegations and BGP announcements since an earlier load");
+                        builder.member_str("syntax", "/api/v1/changes?since=<SERIAL>[&format=json|csv]");
+                        builder.member_str("uri", format!("https://{}{}changes", host, uri))
+                    });
+                    builder.array_object(|builder| {
+                        builder.member_str("id", "diagnostics");
+                        builder.member_str("description", "Lines skipped while loading the data files");
+                        builder.member_raw("syntax", "null");
+                        builder.member_str("uri", format!("https://{}{}diagnostics", host, uri))
+                    });
                 });
             })))
         }
-        Some("status") => Ok(ok_cors_response(JsonBuilder::build(|builder| {
-            builder.member_str("version", format!("roto-api/{}", version()));
-            timestamps.to_json_builder(builder);
-        }))),
+        Some("status") => {
+            let (resp_tx, resp_rx) = oneshot::channel();
+            if tx.send((Task::Status, resp_tx)).await.is_err() {
+                return Ok(internal_server_error());
+            }
+            Ok(resp_rx.await.unwrap_or_else(|_| internal_server_error()))
+        }
+        Some("diagnostics") => {
+            let (resp_tx, resp_rx) = oneshot::channel();
+            if tx.send((Task::Diagnostics, resp_tx)).await.is_err() {
+                return Ok(internal_server_error());
+            }
+            Ok(resp_rx.await.unwrap_or_else(|_| internal_server_error()))
+        }
+        Some("changes") => {
+            if url.next().is_some() {
+                return not_found(Some(
+                    "Found trailing statements beyond `changes`. Please remove those.".to_string(),
+                ));
+            }
+            let since = match query_param(&req, "since").map(|since| history::parse_since(&since)) {
+                Some(Ok(since)) => since,
+                Some(Err(err)) => return not_found(Some(format!("Cannot parse `since`: {}", err))),
+                None => return not_found(Some("The `changes` resource needs a `since` parameter.".to_string())),
+            };
+            let format = match query_param(&req, "format").as_deref() {
+                None | Some("json") => ChangesFormat::Json,
+                Some("csv") => ChangesFormat::Csv,
+                Some(format) => {
+                    return not_found(Some(format!(
+                        "Unknown format `{}`. Current formats are: `json`, `csv`.",
+                        format
+                    )))
+                }
+            };
+
+            let (resp_tx, resp_rx) = oneshot::channel();
+            if tx
+                .send((Task::Changes(ChangesRequest { since, format }), resp_tx))
+                .await
+                .is_err()
+            {
+                return Ok(internal_server_error());
+            }
+            Ok(resp_rx.await.unwrap_or_else(|_| internal_server_error()))
+        }
         Some("prefix") => {
             let addr = match url.next().and_then(|s| {
-                println!("s {}", s);
+                debug!("s {}", s);
                 Addr::from_str(s).ok()
             }) {
                 Some(addr) => addr,
                 None => {
-                    println!("address parse failure");
+                    debug!("address parse failure");
                     return not_found(Some("Cannot parse address part of the prefix. Prefix should be in format <IP_ADDRESS>/<LENGTH>".to_string()));
                 }
             };
             let len = match url.next().and_then(|s| u8::from_str(s).ok()) {
                 Some(len) => len,
                 None => {
-                    println!("length parse failure");
+                    debug!("length parse failure");
                     return not_found(Some("Cannot parse length part of the prefix. Prefix should be in format <IP_ADDRESS>/<LENGTH>".to_string()));
                 }
             };
             if url.next().as_ref() != Some(&"search") {
-                println!("action parse failure");
+                debug!("action parse failure");
                 return not_found(Some(
                     "Cannot parse action part of the prefix. Current actions are: `search`."
                         .to_string(),
                 ));
             }
             if url.next().is_some() {
-                println!("trailing stuff failure");
+                debug!("trailing stuff failure");
                 return not_found(Some(
                     "Found trailing statements beyon the action part. Please remove those."
                         .to_string(),
                 ));
             }
-            println!("--- end request ---");
+            debug!("--- end request ---");
+            let match_type = match query_param(&req, "match").as_deref() {
+                None | Some("longest") => MatchType::LongestMatch,
+                Some("exact") => MatchType::ExactMatch,
+                Some("empty") => MatchType::EmptyMatch,
+                Some(match_type) => {
+                    return not_found(Some(format!(
+                        "Unknown match type `{}`. Current match types are: `longest`, `exact`, `empty`.",
+                        match_type
+                    )))
+                }
+            };
+            let relations = match relations(&req) {
+                Ok(relations) => relations,
+                Err(err) => return not_found(Some(err)),
+            };
 
             let (resp_tx, resp_rx) = oneshot::channel();
             if tx
                 .send((
                     Task::PrefixMatch(MatchPrefixRequest {
                         prefix: Prefix::new(addr, len),
-                        match_options,
+                        match_type,
+                        relations,
+                        sources,
+                        at,
                     }),
                     resp_tx,
                 ))
@@ -521,7 +980,7 @@ async fn process_request(
         }
         Some("asn") => {
             let asns = match url.next().and_then(|s| {
-                println!("s {}", s);
+                debug!("s {}", s);
                 let mut asns = vec![];
                 for asn in s.split(',') {
                     if let Ok(asn) = Asn::from_str(asn) {
@@ -534,22 +993,48 @@ async fn process_request(
             }) {
                 Some(asns) => asns,
                 None => {
-                    println!("ASNs parse failure");
+                    debug!("ASNs parse failure");
                     return not_found(Some(
                         "Cannot parse ASN. The ASNs should be comma-separated list of integers 0 < asn < 4,294,967,296"
                             .to_string(),
                     ));
                 }
             };
-            if url.next().as_ref() != Some(&"search") {
-                println!("action parse failure");
-                return not_found(Some(
-                    "Cannot parse action part of the ASNs. Current actions are: `search`."
-                        .to_string(),
-                ));
-            }
+            let task = match url.next() {
+                Some("search") => Task::ByAsnSearch(SearchByAsnRequest {
+                    asns,
+                    search_options: SearchByAsnOptions {
+                        search_type: SearchType::PrefixesByBgpAsn,
+                    },
+                    sources,
+                    at,
+                }),
+                Some(action) => match (AsRelation::from_str(action), asns.as_slice()) {
+                    (Ok(relation), [asn]) => Task::AsRelations(AsRelationsRequest { asn: *asn, relation }),
+                    (Ok(relation), _) => {
+                        return not_found(Some(format!(
+                            "The `{}` action takes a single ASN.",
+                            relation
+                        )));
+                    }
+                    (Err(_), _) => {
+                        debug!("action parse failure");
+                        return not_found(Some(
+                            "Cannot parse action part of the ASNs. Current actions are: `search`, `upstreams`, `downstreams`, `peers`, `customer-cone`."
+                                .to_string(),
+                        ));
+                    }
+                },
+                None => {
+                    debug!("action parse failure");
+                    return not_found(Some(
+                        "Cannot parse action part of the ASNs. Current actions are: `search`, `upstreams`, `downstreams`, `peers`, `customer-cone`."
+                            .to_string(),
+                    ));
+                }
+            };
             if url.next().is_some() {
-                println!("trailing stuff failure");
+                debug!("trailing stuff failure");
                 return not_found(Some(
                     "Found trailing statements beyon the action part. Please remove those."
                         .to_string(),
@@ -557,49 +1042,207 @@ async fn process_request(
             }
 
             let (resp_tx, resp_rx) = oneshot::channel();
-            if tx
-                .send((
-                    Task::ByAsnSearch(SearchByAsnRequest {
-                        asns,
-                        search_options: SearchByAsnOptions {
-                            search_type: SearchType::PrefixesByBgpAsn,
-                        },
-                    }),
-                    resp_tx,
-                ))
-                .await
-                .is_err()
-            {
+            if tx.send((task, resp_tx)).await.is_err() {
                 return Ok(internal_server_error());
             }
             Ok(resp_rx.await.unwrap_or_else(|_| internal_server_error()))
         }
         // 404 Catchall
         _ => not_found(Some(
-            "Cannot parse resource. Current resources are: `prefix`,`status`, `asn`".to_string(),
+            "Cannot parse resource. Current resources are: `prefix`,`status`, `diagnostics`, `asn`, `changes`".to_string(),
         )),
     }
 }
 
-fn not_found(description: Option<String>) -> Result<Response<Body>, Infallible> {
-    Ok(Response::builder()
-        .status(StatusCode::NOT_FOUND)
-        .header(hyper::header::CONTENT_TYPE, "application/json")
-        .header(hyper::header::ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS")
-        .header(
-            hyper::header::ACCESS_CONTROL_ALLOW_HEADERS,
-            "DNT,User-Agent,X-Requested-With,If-Modified-Since,Cache-Control,Content-Type,Range",
-        )
-        .header(
-            hyper::header::ACCESS_CONTROL_EXPOSE_HEADERS,
-            "Content-Length,Content-Range",
-        )
+/// Handles a request to `/rdap/ip/<address>[/<length>]` or
+/// `/rdap/autnum/<asn>`, as defined in RFC 9082.
+async fn process_rdap_request(
+    req: &Request<Body>,
+    mut url: std::str::Split<'_, char>,
+    tx: mpsc::Sender<(Task, oneshot::Sender<Response<Body>>)>,
+) -> Result<Response<Body>, Infallible> {
+    let host = req
+        .headers()
+        .get("Host")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+    let self_url = format!("https://{}{}", host, req.uri().path());
+
+    let task = match url.next() {
+        Some("ip") => {
+            let addr = match url.next().and_then(|s| Addr::from_str(s).ok()) {
+                Some(addr) => addr,
+                None => {
+                    return Ok(rdap_error_response(
+                        StatusCode::BAD_REQUEST,
+                        "Cannot parse the address. The query should be ip/<IP_ADDRESS>[/<LENGTH>]".to_string(),
+                    ))
+                }
+            };
+            let len = match (url.next(), addr) {
+                (None, Addr::V4(_)) => Some(32),
+                (None, Addr::V6(_)) => Some(128),
+                (Some(len), _) => u8::from_str(len).ok(),
+            };
+            let len = match len {
+                Some(len) => len,
+                None => {
+                    return Ok(rdap_error_response(
+                        StatusCode::BAD_REQUEST,
+                        "Cannot parse the prefix length. The query should be ip/<IP_ADDRESS>[/<LENGTH>]".to_string(),
+                    ))
+                }
+            };
+            Task::RdapIp(RdapIpRequest {
+                prefix: Prefix::new(addr, len),
+                self_url,
+            })
+        }
+        Some("autnum") => {
+            let asn = match url.next().and_then(|s| Asn::from_str(s).ok()) {
+                Some(asn) => asn,
+                None => {
+                    return Ok(rdap_error_response(
+                        StatusCode::BAD_REQUEST,
+                        "Cannot parse the ASN. The query should be autnum/<ASN>".to_string(),
+                    ))
+                }
+            };
+            Task::RdapAutnum(RdapAutnumRequest { asn, self_url })
+        }
+        _ => {
+            return Ok(rdap_error_response(
+                StatusCode::BAD_REQUEST,
+                "Unsupported query. Current queries are: `ip`, `autnum`".to_string(),
+            ))
+        }
+    };
+    if url.next().is_some() {
+        return Ok(rdap_error_response(
+            StatusCode::BAD_REQUEST,
+            "Found trailing statements beyond the query. Please remove those.".to_string(),
+        ));
+    }
+
+    let (resp_tx, resp_rx) = oneshot::channel();
+    if tx.send((task, resp_tx)).await.is_err() {
+        return Ok(internal_server_error());
+    }
+    Ok(resp_rx.await.unwrap_or_else(|_| internal_server_error()))
+}
+
+/// Returns the value of a parameter in the query string of a request.
+///
+/// Percent-encoded characters in the name and value are decoded, so both
+/// `include[relations]` and `include%5Brelations%5D` are found.
+fn query_param(req: &Request<Body>, name: &str) -> Option<String> {
+    req.uri().query()?.split('&').find_map(|param| match param.split_once('=') {
+        Some((key, value)) if percent_decode(key).as_deref() == Some(name) => percent_decode(value),
+        _ => None,
+    })
+}
+
+/// Returns the comma-separated values of a query parameter, if it is given.
+fn query_list(req: &Request<Body>, name: &str) -> Option<Vec<String>> {
+    query_param(req, name).map(|value| {
+        value
+            .split(',')
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()
+    })
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Returns the relations a prefix search includes.
+///
+/// `include[relations]` lists the only relations to include,
+/// `exclude[relations]` those to leave out. Without either, all are
+/// included.
+fn relations(req: &Request<Body>) -> Result<Relations, String> {
+    let mut relations = match query_list(req, "include[relations]") {
+        Some(include) => {
+            let mut relations = Relations::NONE;
+            for name in include {
+                relations.set(&name, true)?;
+            }
+            relations
+        }
+        None => Relations::ALL,
+    };
+    for name in query_list(req, "exclude[relations]").unwrap_or_default() {
+        relations.set(&name, false)?;
+    }
+    Ok(relations)
+}
+
+/// Returns the sources a search shows the records of.
+///
+/// These are selected with `include[sourceType]`, `exclude[sourceType]`,
+/// `include[sourceID]` and `exclude[sourceID]`.
+fn source_filter(req: &Request<Body>) -> Result<SourceFilter, String> {
+    let types = |name: &str| -> Result<Option<Vec<SourceType>>, String> {
+        query_list(req, name)
+            .map(|types| {
+                types
+                    .iter()
+                    .map(|source_type| {
+                        SourceType::from_str(source_type)
+                            .map_err(|err| format!("Cannot parse `{}`: {}", name, err))
+                    })
+                    .collect()
+            })
+            .transpose()
+    };
+    Ok(SourceFilter {
+        include_types: types("include[sourceType]")?,
+        exclude_types: types("exclude[sourceType]")?.unwrap_or_default(),
+        include_ids: query_list(req, "include[sourceID]"),
+        exclude_ids: query_list(req, "exclude[sourceID]").unwrap_or_default(),
+    })
+}
+
+fn not_found(description: Option<String>) -> Result<Response<Body>, Infallible> {
+    Ok(not_found_response(
+        description.unwrap_or_else(|| "cannot parse query".to_string()),
+    ))
+}
+
+fn not_found_response(description: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS")
+        .header(
+            hyper::header::ACCESS_CONTROL_ALLOW_HEADERS,
+            "DNT,User-Agent,X-Requested-With,If-Modified-Since,Cache-Control,Content-Type,Range",
+        )
+        .header(
+            hyper::header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            "Content-Length,Content-Range",
+        )
         .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-        .body(Body::from(format!(
-            "{{\"results\": null, \"error\": true, \"error_msg\": \"{}\"}}",
-            description.unwrap_or_else(|| "cannot parse query".to_string())
-        )))
-        .unwrap())
+        .body(Body::from(JsonBuilder::build(|builder| {
+            builder.member_raw("results", "null");
+            builder.member_raw("error", true);
+            builder.member_str("error_msg", description);
+        })))
+        .unwrap()
 }
 
 fn internal_server_error() -> Response<Body> {
@@ -621,9 +1264,13 @@ fn internal_server_error() -> Response<Body> {
 }
 
 fn ok_cors_response(body: String) -> Response<Body> {
+    ok_cors_typed_response("application/json", body)
+}
+
+fn ok_cors_typed_response(content_type: &'static str, body: String) -> Response<Body> {
     Response::builder()
         .status(StatusCode::OK)
-        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(hyper::header::CONTENT_TYPE, content_type)
         .header(hyper::header::ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS")
         .header(
             hyper::header::ACCESS_CONTROL_ALLOW_HEADERS,
@@ -638,6 +1285,790 @@ fn ok_cors_response(body: String) -> Response<Body> {
         .unwrap()
 }
 
+/// Sets the `Access-Control-Allow-Origin` header of a response to a request
+/// from `origin` according to the CORS policy.
+fn apply_cors(cors: &CorsConfig, origin: Option<&str>, mut resp: Response<Body>) -> Response<Body> {
+    let headers = resp.headers_mut();
+    match cors
+        .allow_origin(origin)
+        .and_then(|origin| hyper::header::HeaderValue::from_str(origin).ok())
+    {
+        Some(origin) => headers.insert(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, origin),
+        None => headers.remove(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN),
+    };
+    if let CorsConfig::Origins(_) = cors {
+        headers.insert(hyper::header::VARY, hyper::header::HeaderValue::from_static("Origin"));
+    }
+    resp
+}
+
+fn rdap_response(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/rdap+json")
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS")
+        .header(
+            hyper::header::ACCESS_CONTROL_ALLOW_HEADERS,
+            "DNT,User-Agent,X-Requested-With,If-Modified-Since,Cache-Control,Content-Type,Range",
+        )
+        .header(
+            hyper::header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            "Content-Length,Content-Range",
+        )
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn rdap_error_response(status: StatusCode, description: String) -> Response<Body> {
+    rdap_response(
+        status,
+        JsonBuilder::build(|builder| {
+            builder.member_array("rdapConformance", |builder| builder.array_str("rdap_level_0"));
+            builder.member_raw("errorCode", status.as_u16());
+            builder.member_str("title", status.canonical_reason().unwrap_or(""));
+            builder.member_array("description", |builder| builder.array_str(description));
+        }),
+    )
+}
+
+//------------ DataFiles -----------------------------------------------------
+
+/// The data files the store is built from.
+///
+/// These are kept around so that the store can be rebuilt from the same
+/// files when the api receives a SIGHUP or when a source was updated.
+#[derive(Clone, Debug)]
+struct DataFiles {
+    /// Delegated-extended files.
+    prefixes: Vec<DelegationFile>,
+
+    /// Riswhois files.
+    riswhois: Vec<String>,
+
+    /// MRT RIB dumps.
+    mrt: Vec<MrtFile>,
+
+    /// VRP exports.
+    vrps: Vec<String>,
+
+    /// IRR database dumps.
+    irr: Vec<String>,
+
+    /// IANA address space registries.
+    iana: Vec<String>,
+
+    /// AS name lists.
+    as_names: Vec<String>,
+
+    /// AS relationship files.
+    as_rel: Vec<String>,
+
+    /// The sources fetched by the api itself into a data dir.
+    downloads: Option<(DataDir, Vec<Source>)>,
+
+    /// The snapshot to start from and to write after loading the files.
+    snapshot: Option<PathBuf>,
+
+    /// Where to keep a dated snapshot after loading the files.
+    history: Option<History>,
+
+    strides_v4: Vec<u8>,
+    strides_v6: Vec<u8>,
+}
+
+/// A delegated-extended file with the checks it has to pass.
+#[derive(Clone, Debug)]
+struct DelegationFile {
+    path: String,
+
+    /// The RIR the file is checked for, if it is known.
+    rir: Option<Rir>,
+    verify: Option<Verify>,
+}
+
+impl DelegationFile {
+    fn new(path: String, id: Option<&str>, verify: Option<Verify>) -> Self {
+        DelegationFile {
+            path,
+            rir: id.map(Rir::from).filter(|rir| *rir != Rir::Unknown),
+            verify,
+        }
+    }
+
+    /// Checks the file if it is to be verified and records the outcome
+    /// with its source.
+    ///
+    /// A file that fails is an error, so that the store isn't built without
+    /// the delegations of a RIR.
+    fn check(&self, store: &mut Store) -> Result<(), String> {
+        let (verify, rir) = match (&self.verify, self.rir) {
+            (Some(verify), Some(rir)) => (verify, rir),
+            (Some(_), None) => {
+                return Err(format!("Cannot verify {}: the RIR of the file is unknown", self.path));
+            }
+            (None, _) => return Ok(()),
+        };
+        let verification = verify
+            .check_file(self.path.as_ref())
+            .unwrap_or_else(Verification::Failed);
+        let res = match &verification {
+            Verification::Failed(err) => Err(format!("Rejected {}: {}", self.path, err)),
+            Verification::Passed { .. } => Ok(()),
+        };
+        store
+            .sources_mut()
+            .set_verification(SourceType::RirAlloc, &rir.to_json_id(), verification);
+        res
+    }
+}
+
+/// An MRT RIB dump given with `--mrt <collector>=<path>`.
+#[derive(Clone, Debug)]
+struct MrtFile {
+    collector: String,
+    path: String,
+}
+
+impl FromStr for MrtFile {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((collector, path)) if !collector.is_empty() && !path.is_empty() => Ok(MrtFile {
+                collector: collector.into(),
+                path: path.into(),
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A method of `Store` that loads a data file.
+type LoadFn = fn(&mut Store, &Path) -> Result<(), Box<dyn Error>>;
+
+impl DataFiles {
+    fn from_config(config: &Config) -> Self {
+        let downloads = config.downloads();
+        let mut files = DataFiles {
+            prefixes: Vec::new(),
+            riswhois: Vec::new(),
+            mrt: Vec::new(),
+            vrps: Vec::new(),
+            irr: Vec::new(),
+            iana: Vec::new(),
+            as_names: Vec::new(),
+            as_rel: Vec::new(),
+            downloads: match &config.data_dir {
+                Some(dir) if !downloads.is_empty() => Some((DataDir::new(dir), downloads)),
+                _ => None,
+            },
+            snapshot: config.snapshot.clone(),
+            history: config.history.clone(),
+            strides_v4: config.strides_v4.clone(),
+            strides_v6: config.strides_v6.clone(),
+        };
+        for source in &config.sources {
+            let path = match &source.location {
+                Location::Path(path) => path.clone(),
+                Location::Url(_) => continue,
+            };
+            match source.kind {
+                DataKind::Delegations => {
+                    let file = DelegationFile::new(path, source.id.as_deref(), source.verify.clone());
+                    files.prefixes.push(file);
+                }
+                DataKind::RisWhois => files.riswhois.push(path),
+                DataKind::Mrt => files.mrt.push(MrtFile {
+                    collector: source.id.clone().unwrap_or_default(),
+                    path,
+                }),
+                DataKind::Irr => files.irr.push(path),
+                DataKind::Vrps => files.vrps.push(path),
+                DataKind::IanaAllocations => files.iana.push(path),
+                DataKind::AsNames => files.as_names.push(path),
+                DataKind::AsRelationships => files.as_rel.push(path),
+            }
+        }
+        files
+    }
+
+    /// Returns whether there are any data files besides the snapshot.
+    fn has_files(&self) -> bool {
+        !(self.prefixes.is_empty()
+            && self.riswhois.is_empty()
+            && self.mrt.is_empty()
+            && self.vrps.is_empty()
+            && self.irr.is_empty()
+            && self.iana.is_empty()
+            && self.as_names.is_empty()
+            && self.as_rel.is_empty()
+            && self.downloads.is_none())
+    }
+
+    /// Loads the store from the snapshot, if there is a usable one.
+    fn load_snapshot(&self) -> Option<Store> {
+        let path = self.snapshot.as_ref().filter(|path| path.exists())?;
+        match Store::load_snapshot(path) {
+            Ok(store) => {
+                info!("loaded snapshot {}", path.display());
+                Some(store)
+            }
+            Err(err) => {
+                warn!("Cannot load snapshot {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    /// Loads the store from the data files and writes the snapshot.
+    ///
+    /// Without data files, the store is loaded from the snapshot.
+    fn load_store(&self, mode: LoadMode) -> Result<Store, String> {
+        if let (false, Some(path)) = (self.has_files(), &self.snapshot) {
+            return Store::load_snapshot(path)
+                .map_err(|err| format!("Failed to load snapshot {}: {}", path.display(), err));
+        }
+
+        let mut store = Store::with_strides(self.strides_v4.clone(), self.strides_v6.clone())
+            .map_err(|err| format!("Invalid strides: {}", err))?;
+        store.set_load_mode(mode);
+        store
+            .load_special_purpose()
+            .map_err(|err| format!("Failed to load the special-purpose registries: {}", err))?;
+
+        let mut prefixes = self.prefixes.clone();
+        let mut riswhois = self.riswhois.clone();
+        let mut iana = self.iana.clone();
+        if let Some((data_dir, sources)) = &self.downloads {
+            let display = |paths: Vec<PathBuf>| {
+                paths
+                    .into_iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+            };
+            for source in sources {
+                let path = data_dir.data_path(source);
+                if let (SourceKind::DelExt(rir), true) = (source.kind, path.exists()) {
+                    prefixes.push(DelegationFile {
+                        path: path.display().to_string(),
+                        rir: Some(rir),
+                        verify: source.verify.clone(),
+                    });
+                }
+            }
+            riswhois.extend(display(data_dir.riswhois_paths(sources)));
+            iana.extend(display(data_dir.iana_paths(sources)));
+        }
+
+        let load = |store: &mut Store, paths: &[String], op: LoadFn| {
+            paths.iter().try_for_each(|path| {
+                op(store, path.as_ref()).map_err(|err| format!("Failed to load {}: {}", path, err))
+            })
+        };
+        for file in &prefixes {
+            file.check(&mut store)?;
+            store
+                .load_prefixes(file.path.as_ref())
+                .map_err(|err| format!("Failed to load {}: {}", file.path, err))?;
+        }
+        load(&mut store, &riswhois, Store::load_riswhois)?;
+        for file in &self.mrt {
+            store
+                .load_mrt(file.path.as_ref(), &file.collector)
+                .map_err(|err| format!("Failed to load {}: {}", file.path, err))?;
+        }
+        if let Some((data_dir, sources)) = &self.downloads {
+            data_dir.set_last_updated(sources, store.sources_mut());
+        }
+        load(&mut store, &iana, Store::load_iana_address_space)?;
+        load(&mut store, &self.as_names, Store::load_as_names)?;
+        load(&mut store, &self.as_rel, Store::load_as_relationships)?;
+        load(&mut store, &self.irr, Store::load_irr)?;
+        load(&mut store, &self.vrps, Store::load_vrps)?;
+        for issue in store.load_report().iter() {
+            warn!("Skipped {}", issue);
+        }
+        if let Some(path) = &self.snapshot {
+            match store.save_snapshot(path) {
+                Ok(()) => info!("wrote snapshot {}", path.display()),
+                Err(err) => error!("Failed to write snapshot {}: {}", path.display(), err),
+            }
+        }
+        if let Some(history) = &self.history {
+            if let Err(err) = history.save(&store) {
+                error!("Failed to keep a snapshot in {}: {}", history.dir().display(), err);
+            }
+        }
+        Ok(store)
+    }
+}
+
+//------------ reload_store --------------------------------------------------
+
+/// Rebuilds the store from the data files and swaps it in.
+///
+/// The new store is built on a blocking thread while the current store
+/// keeps answering queries. Only once loading has succeeded it is handed
+/// to `process_tasks` to replace the current store. If loading fails, the
+/// current store is kept.
+async fn reload_store(
+    data_files: &DataFiles,
+    mode: LoadMode,
+    tx: &mpsc::Sender<(Task, oneshot::Sender<Response<Body>>)>,
+) {
+    info!("reloading data files");
+    let files = data_files.clone();
+    let store = match tokio::task::spawn_blocking(move || files.load_store(mode)).await {
+        Ok(Ok(store)) => store,
+        Ok(Err(err)) => {
+            error!("{}. Keeping current data.", err);
+            return;
+        }
+        Err(err) => {
+            error!("Reload failed: {}. Keeping current data.", err);
+            return;
+        }
+    };
+
+    let (resp_tx, resp_rx) = oneshot::channel();
+    if tx
+        .send((Task::ReplaceStore(Box::new(store)), resp_tx))
+        .await
+        .is_err()
+    {
+        return;
+    }
+    if resp_rx.await.is_ok() {
+        info!("reloaded data files");
+    }
+}
+
+//------------ reload_on_hangup ----------------------------------------------
+
+/// Rebuilds the store from the data files on every SIGHUP.
+async fn reload_on_hangup(
+    data_files: DataFiles,
+    mode: LoadMode,
+    tx: mpsc::Sender<(Task, oneshot::Sender<Response<Body>>)>,
+) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!("Cannot listen for SIGHUP, reloading is disabled: {}", err);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        reload_store(&data_files, mode, &tx).await;
+    }
+}
+
+//------------ update_sources ------------------------------------------------
+
+/// Fetches a source if it changed upstream.
+///
+/// Returns whether the source was updated.
+async fn update_source(downloader: &Downloader, source: &Source) -> bool {
+    match downloader.update(source).await {
+        Ok(true) => {
+            info!("{} changed, downloaded {}", source.name, source.url);
+            true
+        }
+        Ok(false) => false,
+        Err(err) => {
+            error!("failed to update {}: {}", source.name, err);
+            false
+        }
+    }
+}
+
+/// Fetches all sources that changed upstream.
+async fn update_sources(downloader: &Downloader, sources: &[Source]) {
+    for source in sources {
+        update_source(downloader, source).await;
+    }
+}
+
+/// A source that is checked for updates every `refresh`.
+struct WatchedSource {
+    kind: WatchedKind,
+    refresh: Duration,
+    next: Instant,
+}
+
+enum WatchedKind {
+    /// A source downloaded into the data dir.
+    Download(Source),
+
+    /// A file that is reloaded when its modification time changes.
+    File {
+        path: PathBuf,
+        modified: Option<SystemTime>,
+    },
+}
+
+impl WatchedSource {
+    /// Returns the sources of the config that have a refresh interval.
+    fn from_config(config: &Config) -> Vec<Self> {
+        config
+            .sources
+            .iter()
+            .filter_map(|source| {
+                let kind = match (source.download(), &source.location) {
+                    (Some(download), _) => WatchedKind::Download(download),
+                    (None, Location::Path(path)) => WatchedKind::File {
+                        path: path.into(),
+                        modified: modified(path.as_ref()),
+                    },
+                    (None, Location::Url(_)) => return None,
+                };
+                source.refresh.map(|refresh| WatchedSource {
+                    kind,
+                    refresh,
+                    next: Instant::now() + refresh,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns whether the source changed since it was last checked.
+    async fn check(&mut self, downloader: Option<&Downloader>) -> bool {
+        self.next = Instant::now() + self.refresh;
+        match &mut self.kind {
+            WatchedKind::Download(source) => match downloader {
+                Some(downloader) => update_source(downloader, source).await,
+                None => false,
+            },
+            WatchedKind::File { path, modified: last } => {
+                let current = modified(path);
+                if current == *last {
+                    return false;
+                }
+                *last = current;
+                info!("{} changed", path.display());
+                true
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Checks every source when its refresh interval has passed and reloads
+/// the store if any of them changed.
+async fn update_sources_periodically(
+    downloader: Option<Downloader>,
+    mut sources: Vec<WatchedSource>,
+    data_files: DataFiles,
+    mode: LoadMode,
+    tx: mpsc::Sender<(Task, oneshot::Sender<Response<Body>>)>,
+) {
+    while let Some(next) = sources.iter().map(|source| source.next).min() {
+        tokio::time::sleep_until(next).await;
+        let mut changed = false;
+        for source in sources.iter_mut().filter(|source| source.next <= next) {
+            changed |= source.check(downloader.as_ref()).await;
+        }
+        if changed {
+            reload_store(&data_files, mode, &tx).await;
+        }
+    }
+}
+
+//------------ listen_bmp ----------------------------------------------------
+
+/// Accepts BMP sessions from routers and applies their updates to the
+/// store.
+async fn listen_bmp(addr: SocketAddr, tx: mpsc::Sender<(Task, oneshot::Sender<Response<Body>>)>) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Cannot listen for BMP on {}: {}", addr, err);
+            process::exit(1);
+        }
+    };
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(handle_bmp_session(stream, peer, tx.clone()));
+            }
+            Err(err) => error!("Failed to accept BMP session: {}", err),
+        }
+    }
+}
+
+async fn handle_bmp_session(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    tx: mpsc::Sender<(Task, oneshot::Sender<Response<Body>>)>,
+) {
+    info!("BMP session from {}", peer);
+    let mut session = BmpSession::new(peer.ip());
+    loop {
+        let msg = match read_message(&mut stream).await {
+            Ok(Some(msg)) => msg,
+            Ok(None) => break,
+            Err(err) => {
+                error!("BMP session from {} failed: {}", peer, err);
+                break;
+            }
+        };
+        match session.process(&msg) {
+            Ok(updates) if updates.is_empty() => {}
+            Ok(updates) => {
+                let (resp_tx, _resp_rx) = oneshot::channel();
+                if tx.send((Task::BgpUpdates(updates), resp_tx)).await.is_err() {
+                    return;
+                }
+            }
+            Err(err) => warn!("Skipped BMP message from {}: {}", peer, err),
+        }
+    }
+
+    // Without the session, the routes of the router can't be kept up to
+    // date anymore.
+    let (resp_tx, _resp_rx) = oneshot::channel();
+    let _ = tx.send((Task::BgpUpdates(session.close()), resp_tx)).await;
+    info!("BMP session from {} closed", peer);
+}
+
+//------------ Logger --------------------------------------------------------
+
+/// Writes log messages with the time to stderr or a file.
+struct Logger {
+    level: log::LevelFilter,
+    file: Option<Mutex<fs::File>>,
+}
+
+impl Logger {
+    fn init(config: &LogConfig) -> Result<(), String> {
+        let file = match &config.file {
+            Some(path) => Some(Mutex::new(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|err| format!("Cannot open log file {}: {}", path.display(), err))?,
+            )),
+            None => None,
+        };
+        let logger = Logger {
+            level: config.level,
+            file,
+        };
+        log::set_logger(Box::leak(Box::new(logger))).map_err(|err| err.to_string())?;
+        log::set_max_level(config.level);
+        Ok(())
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} roto-api [{}] {}\n",
+            Utc::now().to_rfc3339(),
+            record.level(),
+            record.args()
+        );
+        match &self.file {
+            Some(file) => {
+                if let Ok(mut file) = file.lock() {
+                    let _ = file.write_all(line.as_bytes());
+                }
+            }
+            None => eprint!("{}", line),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+//------------ main ----------------------------------------------------------
+
+/// Builds the configuration from the command line without a config file.
+///
+/// The sources given as positional arguments and options become path
+/// sources, a data dir the default sources. Returns `None` if the command
+/// line can't be understood.
+fn config_from_args(args: Vec<String>) -> Option<Config> {
+    let path_source = |kind, id: Option<String>, path: String| SourceConfig {
+        kind,
+        id,
+        location: Location::Path(path),
+        refresh: None,
+        verify: None,
+    };
+
+    // MRT RIB dumps can be added in both modes with `--mrt <collector>=<path>`,
+    // a VRP export with `--vrps <path>`, IRR dumps with `--irr <path>`, the
+    // IANA registries with `--iana <path>`, AS names with `--as-names <path>`,
+    // AS relationships with `--as-rel <path>` and a BMP listener with
+    // `--bmp <listen-addr>`.
+    let mut sources = Vec::new();
+    let mut bmp_listen = Vec::new();
+    let mut snapshot = None;
+    let mut history = None;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let kind = match arg.as_str() {
+            "--mrt" => {
+                let file = MrtFile::from_str(&args.next()?).ok()?;
+                sources.push(path_source(DataKind::Mrt, Some(file.collector), file.path));
+                continue;
+            }
+            "--bmp" => {
+                bmp_listen.push(SocketAddr::from_str(&args.next()?).ok()?);
+                continue;
+            }
+            "--snapshot" => {
+                snapshot = Some(args.next()?.into());
+                continue;
+            }
+            "--history" => {
+                history = Some(History::new(args.next()?, history::DEFAULT_KEEP));
+                continue;
+            }
+            "--vrps" => DataKind::Vrps,
+            "--irr" => DataKind::Irr,
+            "--iana" => DataKind::IanaAllocations,
+            "--as-names" => DataKind::AsNames,
+            "--as-rel" => DataKind::AsRelationships,
+            _ => {
+                positional.push(arg);
+                continue;
+            }
+        };
+        sources.push(path_source(kind, None, args.next()?));
+    }
+
+    let mut args = positional.into_iter();
+    let listen = SocketAddr::from_str(&args.next()?).ok()?;
+    let mut config = Config {
+        listen: vec![listen],
+        bmp_listen,
+        strict: false,
+        data_dir: None,
+        fetch_timeout: DEFAULT_FETCH_TIMEOUT,
+        snapshot,
+        history,
+        strides_v4: vec![4],
+        strides_v6: vec![4],
+        cors: CorsConfig::Any,
+        log: LogConfig::default(),
+        sources: Vec::new(),
+    };
+    match args.next() {
+        // Serve nothing but the snapshot.
+        None if config.snapshot.is_some() => {}
+        None => return None,
+        Some(arg) if arg == "--data-dir" => {
+            config.data_dir = Some(args.next()?.into());
+            let interval = match (args.next(), args.next()) {
+                (None, _) => DEFAULT_REFRESH,
+                (Some(arg), Some(secs)) if arg == "--interval" => {
+                    Duration::from_secs(u64::from_str(&secs).ok().filter(|secs| *secs > 0)?)
+                }
+                _ => return None,
+            };
+            for source in Source::default_sources() {
+                config.sources.push(SourceConfig::from_download(source, interval));
+            }
+        }
+        Some(prefixes) => {
+            config.sources.push(path_source(DataKind::Delegations, None, prefixes));
+            for riswhois in args {
+                config.sources.push(path_source(DataKind::RisWhois, None, riswhois));
+            }
+        }
+    }
+    config.sources.append(&mut sources);
+    Some(config)
+}
+
+/// Checks that the files a configuration refers to are there.
+///
+/// Prints what the api would load and returns whether everything was
+/// found.
+fn check_config(path: &str, config: &Config) -> bool {
+    let mut ok = true;
+    let mut check_path = |what: &str, path: &Path| {
+        if !path.exists() {
+            eprintln!("Error: {} {} does not exist", what, path.display());
+            ok = false;
+        }
+    };
+    if let Some(dir) = &config.data_dir {
+        check_path("data dir", dir);
+    }
+    for source in &config.sources {
+        if let Location::Path(path) = &source.location {
+            check_path("source", path.as_ref());
+        }
+        if let Some(key) = source.verify.as_ref().and_then(|verify| verify.key.as_ref()) {
+            check_path("key", key);
+        }
+    }
+    if let Some(snapshot) = config.snapshot.as_ref().filter(|path| path.exists()) {
+        if let Err(err) = Store::load_snapshot(snapshot) {
+            eprintln!("Error: cannot load snapshot {}: {}", snapshot.display(), err);
+            ok = false;
+        }
+    }
+
+    println!("{}:", path);
+    for addr in &config.listen {
+        println!("  listen on {}", addr);
+    }
+    for addr in &config.bmp_listen {
+        println!("  accept BMP on {}", addr);
+    }
+    println!(
+        "  strides {:?} (IPv4) and {:?} (IPv6)",
+        config.strides_v4, config.strides_v6
+    );
+    match &config.cors {
+        CorsConfig::Any => println!("  allow all origins"),
+        CorsConfig::Origins(origins) => println!("  allow origins {}", origins.join(", ")),
+    }
+    match &config.log.file {
+        Some(file) => println!("  log {} to {}", config.log.level, file.display()),
+        None => println!("  log {} to stderr", config.log.level),
+    }
+    if let Some(snapshot) = &config.snapshot {
+        println!("  snapshot {}", snapshot.display());
+    }
+    if let Some(history) = &config.history {
+        println!("  keep past snapshots in {}", history.dir().display());
+    }
+    for source in &config.sources {
+        match source.refresh {
+            Some(refresh) => println!("  source {}, refreshed every {}s", source, refresh.as_secs()),
+            None => println!("  source {}", source),
+        }
+        if let Some(verify) = &source.verify {
+            if verify.md5 {
+                println!("    check the MD5 checksum");
+            }
+            if let Some(key) = &verify.key {
+                println!("    check the signature with {}", key.display());
+            }
+        }
+    }
+    ok
+}
+
 #[tokio::main]
 async fn main() {
     let mut args = env::args();
@@ -648,70 +2079,213 @@ async fn main() {
             process::exit(1);
         }
     };
-    let listen = match args.next().and_then(|s| SocketAddr::from_str(&s).ok()) {
-        Some(addr) => addr,
-        None => {
-            eprintln!(
-                "Usage: {} <listen-addr> <prefixes-file> <ris-file> \
-                [<ris-file> ...]",
-                cmd
-            );
-            process::exit(1);
-        }
+    let usage = || -> ! {
+        eprintln!(
+            "Usage: {0} [<options>] <listen-addr> <prefixes-file> <ris-file> [<ris-file> ...]\n       \
+            {0} [<options>] <listen-addr> --data-dir <dir> [--interval <seconds>]\n       \
+            {0} [<options>] --snapshot <snapshot-file> <listen-addr>\n       \
+            {0} [--strict] --config <config-file> [--check-config]\n\n\
+            Options:\n    \
+            --strict                      fail on bad lines in the data files\n    \
+            --mrt <collector>=<mrt-file>  load an MRT RIB dump, can be repeated\n    \
+            --vrps <vrp-file>             validate BGP origins against a VRP export\n    \
+            --irr <rpsl-file>             load the route objects of an IRR dump, can be repeated\n    \
+            --iana <csv-file>             load an IANA address space registry, can be repeated\n    \
+            --as-names <file>             load AS names from CAIDA as2org or RIPE asn.txt, can be repeated\n    \
+            --as-rel <file>               load CAIDA AS relationships, can be repeated\n    \
+            --bmp <bmp-listen-addr>       accept BMP sessions from routers, can be repeated\n    \
+            --snapshot <snapshot-file>    start from a snapshot and write it after loading the data files\n    \
+            --history <dir>               keep dated snapshots for `at` and `changes` queries\n    \
+            --config <config-file>        read everything but --strict from a TOML config file\n    \
+            --check-config                only check the config file",
+            cmd
+        );
+        process::exit(1);
     };
-    let prefix_path = match args.next() {
-        Some(path) => path,
-        None => {
-            eprintln!(
-                "Usage: {} <listen-addr> <prefixes-file> <ris-file> \
-                [<ris-file> ...]",
-                cmd
-            );
-            process::exit(1);
-        }
+
+    // Bad lines in the data files are skipped and reported unless
+    // `--strict` is given anywhere on the command line.
+    let (strict, args): (Vec<String>, Vec<String>) = args.partition(|arg| arg == "--strict");
+    let (check, args): (Vec<String>, Vec<String>) = args.into_iter().partition(|arg| arg == "--check-config");
+    let mut config = match args.as_slice() {
+        [arg, path] if arg == "--config" => match Config::from_file(path.as_ref()) {
+            Ok(config) => {
+                if !check.is_empty() {
+                    process::exit(if check_config(path, &config) { 0 } else { 1 });
+                }
+                config
+            }
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                process::exit(1);
+            }
+        },
+        _ if !check.is_empty() => usage(),
+        _ => config_from_args(args).unwrap_or_else(|| usage()),
+    };
+    config.strict |= !strict.is_empty();
+    let mode = if config.strict {
+        LoadMode::Strict
+    } else {
+        LoadMode::Lenient
     };
 
-    let mut store: Store = Default::default();
-    if let Err(err) = store.load_prefixes(prefix_path.as_ref()) {
-        eprintln!("Failed to load {}: {}", prefix_path, err);
+    if let Err(err) = Logger::init(&config.log) {
+        eprintln!("Error: {}", err);
         process::exit(1);
     }
-    for path in args {
-        if let Err(err) = store.load_riswhois(path.as_ref()) {
-            eprintln!("Failed to load {}: {}", path, err);
-            process::exit(1);
+
+    let data_files = DataFiles::from_config(&config);
+    let downloader = config
+        .data_dir
+        .as_ref()
+        .map(|path| Downloader::new(DataDir::new(path), config.fetch_timeout));
+
+    // A snapshot is served right away while the data files are loaded in
+    // the background.
+    let snapshot = if data_files.has_files() {
+        data_files.load_snapshot()
+    } else {
+        None
+    };
+    let from_snapshot = snapshot.is_some();
+    let store = match snapshot {
+        Some(store) => store,
+        None => {
+            if let (Some(downloader), Some((_, sources))) = (&downloader, &data_files.downloads) {
+                update_sources(downloader, sources).await;
+            }
+            match data_files.load_store(mode) {
+                Ok(store) => store,
+                Err(err) => {
+                    error!("{}", err);
+                    process::exit(1);
+                }
+            }
         }
-    }
+    };
+    debug!("{:#?}", store.sources());
 
     let (tx, rx) = mpsc::channel(10);
-    let ts = import_timestamps().unwrap_or_else(|_| {
-        panic!(
-            "{} roto-api Can't handle downloading timestamps. Exiting",
-            chrono::Utc::now().to_rfc3339()
-        )
-    });
-    println!("{:#?}", ts);
-
+    let history = config.history.clone();
+    let runtime = tokio::runtime::Handle::current();
+    let queue_tx = tx.clone();
     thread::spawn(move || {
-        process_tasks(store, rx);
+        process_tasks(store, history, runtime, queue_tx, rx);
     });
 
-    let make_svc = make_service_fn(|_conn| {
+    if from_snapshot && data_files.has_files() {
+        let downloader = downloader.clone();
+        let data_files = data_files.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let (Some(downloader), Some((_, sources))) = (&downloader, &data_files.downloads) {
+                update_sources(downloader, sources).await;
+            }
+            reload_store(&data_files, mode, &tx).await;
+        });
+    }
+
+    let watched = WatchedSource::from_config(&config);
+    if !watched.is_empty() {
+        tokio::spawn(update_sources_periodically(
+            downloader,
+            watched,
+            data_files.clone(),
+            mode,
+            tx.clone(),
+        ));
+    }
+    tokio::spawn(reload_on_hangup(data_files, mode, tx.clone()));
+    for addr in &config.bmp_listen {
+        tokio::spawn(listen_bmp(*addr, tx.clone()));
+    }
+
+    let cors = Arc::new(config.cors);
+    let mut servers = Vec::new();
+    for addr in &config.listen {
         let tx = tx.clone();
-        async move {
-            // service_fn converts our function into a `Service`
-            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
-                let tx = tx.clone();
-                process_request(req, ts, tx)
-            }))
+        let cors = cors.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let tx = tx.clone();
+            let cors = cors.clone();
+            async move {
+                // service_fn converts our function into a `Service`
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let tx = tx.clone();
+                    let cors = cors.clone();
+                    async move {
+                        let origin = req
+                            .headers()
+                            .get(hyper::header::ORIGIN)
+                            .and_then(|origin| origin.to_str().ok())
+                            .map(String::from);
+                        let resp = process_request(req, tx).await?;
+                        Ok::<_, Infallible>(apply_cors(&cors, origin.as_deref(), resp))
+                    }
+                }))
+            }
+        });
+
+        info!("bind server at {}...", addr);
+        match Server::try_bind(addr) {
+            Ok(server) => servers.push(tokio::spawn(server.serve(make_svc))),
+            Err(err) => {
+                error!("Cannot listen on {}: {}", addr, err);
+                process::exit(1);
+            }
         }
-    });
+    }
+
+    // Run the servers for... forever!
+    for server in servers {
+        match server.await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => error!("server error: {}", err),
+            Err(err) => error!("server error: {}", err),
+        }
+    }
+}
 
-    println!("bind server at {}...", listen);
-    let server = Server::bind(&listen).serve(make_svc);
+//============ Tests =========================================================
 
-    // Run this server for... forever!
-    if let Err(e) = server.await {
-        eprintln!("server error: {}", e);
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    async fn error_msg(resp: Response<Body>) -> String {
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        json["error_msg"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn source_filter_types() {
+        let req = request(
+            "/api/v1/prefix/193.0.0.0/21/search?include%5BsourceType%5D=bgp,rpki&exclude[sourceType]=irr",
+        );
+        let filter = source_filter(&req).unwrap();
+        assert_eq!(filter.include_types, Some(vec![SourceType::Bgp, SourceType::Rpki]));
+        assert_eq!(filter.exclude_types, [SourceType::Irr]);
+    }
+
+    #[tokio::test]
+    async fn quote_in_source_type_is_escaped() {
+        for name in ["include", "exclude"] {
+            let req = request(&format!(
+                "/api/v1/prefix/193.0.0.0/21/search?{}[sourceType]=%22x%22:1,%22y",
+                name
+            ));
+            let err = source_filter(&req).unwrap_err();
+            assert_eq!(
+                error_msg(not_found_response(err)).await,
+                format!("Cannot parse `{}[sourceType]`: unknown source type '\"x\":1'", name)
+            );
+        }
     }
 }
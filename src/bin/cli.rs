@@ -1,4 +1,5 @@
-use roto_api::{Addr, MatchOptions, MatchType, Prefix, Store, RecordSet};
+use roto_api::changes::ChangeSet;
+use roto_api::{Addr, JsonBuilder, LoadMode, MatchOptions, MatchType, Prefix, Store, RecordSet};
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use std::str::FromStr;
@@ -8,19 +9,77 @@ fn lookup_related_prefixes_for_lmp_in<'a>(rec: &'a RecordSet) -> Option<(roto_ap
     rec.iter()
         .filter_map(|item| {
             item.1
-                .and_then(|item| item.0.as_ref())
+                .and_then(|item| item.get::<roto_api::RirDelExtRecord>())
                 .map(|some| (item.0, some))
         })
         .max_by_key(|item| item.0.len)
 }
 
+fn load_files(prefix_path: &str, ris_paths: impl Iterator<Item = String>, lenient: bool) -> Store {
+    let mut store: Store = Default::default();
+    if lenient {
+        store.set_load_mode(LoadMode::Lenient);
+    }
+    if let Err(err) = store.load_special_purpose() {
+        eprintln!("Failed to load the special-purpose registries: {}", err);
+        process::exit(1);
+    }
+    if let Err(err) = store.load_prefixes(prefix_path.as_ref()) {
+        eprintln!("Failed to load {}: {}", prefix_path, err);
+        process::exit(1);
+    }
+    for path in ris_paths {
+        if let Err(err) = store.load_riswhois(path.as_ref()) {
+            eprintln!("Failed to load {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+    store
+}
+
+fn load_snapshot(path: &str) -> Store {
+    match Store::load_snapshot(path.as_ref()) {
+        Ok(store) => store,
+        Err(err) => {
+            eprintln!("Failed to load snapshot {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Prints the changes between two snapshots, as JSON or, with `--csv`,
+/// as CSV.
+fn print_changes(cmd: &str, args: impl Iterator<Item = String>) {
+    let (csv, paths): (Vec<String>, Vec<String>) = args.partition(|arg| arg == "--csv");
+    let (old, new) = match paths.as_slice() {
+        [old, new] => (load_snapshot(old), load_snapshot(new)),
+        _ => {
+            eprintln!("Usage: {} changes [--csv] <old-snapshot-file> <new-snapshot-file>", cmd);
+            process::exit(1);
+        }
+    };
+    let changes = ChangeSet::between(&old, &new);
+    if csv.is_empty() {
+        println!("{}", JsonBuilder::build(|builder| changes.to_json_builder(builder)));
+    } else {
+        match changes.to_csv() {
+            Ok(csv) => print!("{}", csv),
+            Err(err) => {
+                eprintln!("Failed to write the changes as CSV: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+}
+
 fn main() {
     let match_options = MatchOptions {
         match_type: MatchType::EmptyMatch,
         include_less_specifics: true,
         include_more_specifics: true,
     };
-    let mut args = env::args();
+    let (strict, args): (Vec<String>, Vec<String>) = env::args().partition(|arg| arg == "--strict");
+    let mut args = args.into_iter();
     let cmd = match args.next() {
         Some(cmd) => cmd,
         None => {
@@ -28,25 +87,50 @@ fn main() {
             process::exit(1);
         }
     };
-    let prefix_path = match args.next() {
-        Some(path) => path,
-        None => {
-            eprintln!("Usage: {} <prefixes-file> <ris-file> [<ris-file> ...]", cmd);
-            process::exit(1);
-        }
-    };
 
-    let mut store: Store = Default::default();
-    if let Err(err) = store.load_prefixes(prefix_path.as_ref()) {
-        eprintln!("Failed to load {}: {}", prefix_path, err);
-        process::exit(1);
+    let mut args = args.peekable();
+    if args.peek().map(String::as_str) == Some("changes") {
+        args.next();
+        print_changes(&cmd, args);
+        return;
     }
-    for path in args {
-        if let Err(err) = store.load_riswhois(path.as_ref()) {
-            eprintln!("Failed to load {}: {}", path, err);
-            process::exit(1);
+
+    // With `--snapshot <file>`, the store is written to a snapshot after
+    // loading the data files, or loaded from it if no files are given.
+    let mut snapshot = None;
+    let mut files = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--snapshot" {
+            snapshot = args.next();
+        } else {
+            files.push(arg);
         }
     }
+    let mut files = files.into_iter();
+
+    let store = match (files.next(), snapshot) {
+        (Some(prefix_path), snapshot) => {
+            let store = load_files(&prefix_path, files, strict.is_empty());
+            if let Some(path) = snapshot {
+                if let Err(err) = store.save_snapshot(path.as_ref()) {
+                    eprintln!("Failed to write snapshot {}: {}", path, err);
+                    process::exit(1);
+                }
+                println!("Wrote snapshot {}", path);
+            }
+            store
+        }
+        (None, Some(path)) => load_snapshot(&path),
+        (None, None) => {
+            eprintln!(
+                "Usage: {0} [--strict] [--snapshot <snapshot-file>] <prefixes-file> <ris-file> [<ris-file> ...]\n       \
+                {0} --snapshot <snapshot-file>\n       \
+                {0} changes [--csv] <old-snapshot-file> <new-snapshot-file>",
+                cmd
+            );
+            process::exit(1);
+        }
+    };
 
     let mut rl = Editor::<()>::new();
     if rl.load_history("/tmp/rotonda-store-history.txt").is_err() {
@@ -54,6 +138,12 @@ fn main() {
     }
 
     store.output_stats();
+    if !store.load_report().is_empty() {
+        println!("Skipped {} lines:", store.load_report().len());
+        for issue in store.load_report().iter() {
+            println!("  {}", issue);
+        }
+    }
 
     loop {
         let readline = rl.readline("(roto-api-cli)> ");
@@ -100,7 +190,7 @@ fn main() {
 
                 // Find longest prefix.
                 let key_pfx = match lmp_pfx.prefix_meta {
-                    Some(meta) => match &meta.0 {
+                    Some(meta) => match meta.get::<roto_api::RirDelExtRecord>() {
                         Some(rir_rec) => Some((lmp_pfx.prefix.unwrap(), rir_rec)),
                         _ => lookup_related_prefixes_for_lmp_in(&lmp_pfx.less_specifics),
                     },
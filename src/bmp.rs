@@ -0,0 +1,486 @@
+//! Reading BGP Monitoring Protocol feeds.
+//!
+//! A router that has a BMP session with the api sends it every route its
+//! peers announce or withdraw. These are turned into `BgpUpdate`s that can
+//! be applied to a `Store`. Every monitored peer of every router is a
+//! source of its own. See RFC 7854.
+//!
+//! Only Route Monitoring and Peer Down messages are used, all other
+//! messages are skipped. Routes with ADD-PATH path identifiers and routes
+//! for other address families than IPv4 and IPv6 unicast aren't
+//! supported.
+
+use crate::bgp::{
+    attributes, AsPath, BgpPeer, BgpUpdate, Parser, ATTR_TYPE_AS4_PATH, ATTR_TYPE_AS_PATH,
+    ATTR_TYPE_MP_REACH_NLRI, ATTR_TYPE_MP_UNREACH_NLRI,
+};
+use crate::Asn;
+use std::collections::HashSet;
+use std::io;
+use std::net::IpAddr;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const BMP_VERSION: u8 = 3;
+const COMMON_HEADER_LEN: usize = 6;
+const PER_PEER_HEADER_LEN: usize = 42;
+const BGP_HEADER_LEN: usize = 19;
+
+/// The longest message accepted: a per-peer header and a BGP message of
+/// the maximum size of RFC 8654. Anything longer ends the session rather
+/// than being buffered.
+const MAX_MESSAGE_LEN: usize = COMMON_HEADER_LEN + PER_PEER_HEADER_LEN + 65535;
+
+const MSG_ROUTE_MONITORING: u8 = 0;
+const MSG_PEER_DOWN: u8 = 2;
+
+const PEER_FLAG_IPV6: u8 = 0x80;
+const PEER_FLAG_POST_POLICY: u8 = 0x40;
+const PEER_FLAG_LEGACY_AS_PATH: u8 = 0x20;
+
+const BGP_MSG_UPDATE: u8 = 2;
+
+const AFI_IPV4: u16 = 1;
+const AFI_IPV6: u16 = 2;
+const SAFI_UNICAST: u8 = 1;
+
+/// Reads the next BMP message from a stream.
+///
+/// Returns `None` if the stream ended cleanly before the message.
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>, io::Error> {
+    let mut header = [0; COMMON_HEADER_LEN];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    if header[0] != BMP_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported BMP version {}", header[0]),
+        ));
+    }
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if !(COMMON_HEADER_LEN..=MAX_MESSAGE_LEN).contains(&len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid BMP message length {}", len),
+        ));
+    }
+    let mut msg = vec![0; len];
+    msg[..COMMON_HEADER_LEN].copy_from_slice(&header);
+    reader.read_exact(&mut msg[COMMON_HEADER_LEN..]).await?;
+    Ok(Some(msg))
+}
+
+//------------ BmpSession ----------------------------------------------------
+
+/// The state of the BMP session with a single router.
+///
+/// This keeps track of the sources the router has sent routes for, so they
+/// can all be withdrawn when the session ends.
+#[derive(Debug)]
+pub struct BmpSession {
+    router: IpAddr,
+    sources: HashSet<String>,
+}
+
+impl BmpSession {
+    pub fn new(router: IpAddr) -> Self {
+        BmpSession {
+            router,
+            sources: HashSet::new(),
+        }
+    }
+
+    /// Turns a BMP message, including its common header, into updates.
+    pub fn process(&mut self, msg: &[u8]) -> Result<Vec<BgpUpdate>, String> {
+        let mut msg = Parser(msg);
+        let _version = msg.u8()?;
+        let _len = msg.u32()?;
+        let msg_type = msg.u8()?;
+        match msg_type {
+            MSG_ROUTE_MONITORING => self.route_monitoring(&mut msg),
+            MSG_PEER_DOWN => self.peer_down(&mut msg),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Withdraws all routes received in this session.
+    pub fn close(self) -> Vec<BgpUpdate> {
+        self.sources
+            .into_iter()
+            .map(|source| BgpUpdate::PeerDown { source })
+            .collect()
+    }
+
+    fn route_monitoring(&mut self, msg: &mut Parser) -> Result<Vec<BgpUpdate>, String> {
+        let header = PeerHeader::parse(msg)?;
+        let source = header.source_id(self.router);
+
+        let bgp_header = msg.take(BGP_HEADER_LEN)?;
+        if bgp_header[18] != BGP_MSG_UPDATE {
+            return Err(format!("unexpected BGP message type {}", bgp_header[18]));
+        }
+
+        let withdrawn_len = msg.u16()?;
+        let mut withdrawn = Parser(msg.take(withdrawn_len.into())?).prefixes(false)?;
+        let attrs_len = msg.u16()?;
+        let attrs = attributes(msg.take(attrs_len.into())?)?;
+        let mut announced = msg.prefixes(false)?;
+
+        let mut as_path = None;
+        let mut as4_path = None;
+        for attr in &attrs {
+            match attr.attr_type {
+                ATTR_TYPE_AS_PATH => {
+                    as_path = Some(AsPath::parse(attr.value, !header.legacy_as_path)?);
+                }
+                ATTR_TYPE_AS4_PATH => as4_path = Some(AsPath::parse(attr.value, true)?),
+                ATTR_TYPE_MP_REACH_NLRI => {
+                    let mut value = Parser(attr.value);
+                    if let Some(ipv6) = unicast_afi(&mut value)? {
+                        let next_hop_len = value.u8()?;
+                        let _next_hop = value.take(next_hop_len.into())?;
+                        let _reserved = value.u8()?;
+                        announced.extend(value.prefixes(ipv6)?);
+                    }
+                }
+                ATTR_TYPE_MP_UNREACH_NLRI => {
+                    let mut value = Parser(attr.value);
+                    if let Some(ipv6) = unicast_afi(&mut value)? {
+                        withdrawn.extend(value.prefixes(ipv6)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let as_path = merge_as4_path(as_path.unwrap_or_default(), as4_path);
+
+        if !announced.is_empty() {
+            self.sources.insert(source.clone());
+        }
+        let mut updates = Vec::with_capacity(withdrawn.len() + announced.len());
+        for (net, len) in withdrawn {
+            updates.push(BgpUpdate::Withdraw {
+                source: source.clone(),
+                net,
+                len,
+            });
+        }
+        for (net, len) in announced {
+            updates.push(BgpUpdate::Announce {
+                source: source.clone(),
+                peer: header.peer,
+                net,
+                len,
+                as_path: as_path.clone(),
+            });
+        }
+        Ok(updates)
+    }
+
+    fn peer_down(&mut self, msg: &mut Parser) -> Result<Vec<BgpUpdate>, String> {
+        let mut header = PeerHeader::parse(msg)?;
+        let mut updates = Vec::new();
+        for post_policy in [false, true] {
+            header.post_policy = post_policy;
+            let source = header.source_id(self.router);
+            if self.sources.remove(&source) {
+                updates.push(BgpUpdate::PeerDown { source });
+            }
+        }
+        Ok(updates)
+    }
+}
+
+//------------ PeerHeader ----------------------------------------------------
+
+/// The per-peer header of a BMP message.
+struct PeerHeader {
+    peer: BgpPeer,
+    post_policy: bool,
+    legacy_as_path: bool,
+}
+
+impl PeerHeader {
+    fn parse(msg: &mut Parser) -> Result<Self, String> {
+        let mut header = Parser(msg.take(PER_PEER_HEADER_LEN)?);
+        let _peer_type = header.u8()?;
+        let flags = header.u8()?;
+        let _distinguisher = header.take(8)?;
+        let addr = if flags & PEER_FLAG_IPV6 != 0 {
+            header.addr(true)?
+        } else {
+            let _padding = header.take(12)?;
+            header.addr(false)?
+        };
+        let asn = header.u32()?;
+        Ok(PeerHeader {
+            peer: BgpPeer {
+                addr,
+                asn: Asn::from(asn),
+            },
+            post_policy: flags & PEER_FLAG_POST_POLICY != 0,
+            legacy_as_path: flags & PEER_FLAG_LEGACY_AS_PATH != 0,
+        })
+    }
+
+    /// Returns the ID of the source for the routes of this peer.
+    ///
+    /// Routers can send both the routes they received (pre-policy) and
+    /// the routes they accepted (post-policy) from a peer. These are kept
+    /// as separate sources.
+    fn source_id(&self, router: IpAddr) -> String {
+        if self.post_policy {
+            format!("bmp/{}/{}/post-policy", router, self.peer.addr)
+        } else {
+            format!("bmp/{}/{}", router, self.peer.addr)
+        }
+    }
+}
+
+/// Reads the AFI and SAFI of an MP_REACH_NLRI or MP_UNREACH_NLRI
+/// attribute.
+///
+/// Returns whether the NLRI are IPv6, or `None` if they aren't unicast
+/// IPv4 or IPv6 prefixes.
+fn unicast_afi(value: &mut Parser) -> Result<Option<bool>, String> {
+    let afi = value.u16()?;
+    let safi = value.u8()?;
+    Ok(match (afi, safi) {
+        (AFI_IPV4, SAFI_UNICAST) => Some(false),
+        (AFI_IPV6, SAFI_UNICAST) => Some(true),
+        _ => None,
+    })
+}
+
+/// Reconstructs the four octet AS path of a route from a peer without
+/// four octet ASN support.
+///
+/// Following RFC 6793, the leading ASNs the AS4_PATH is missing are taken
+/// from the AS_PATH.
+fn merge_as4_path(as_path: AsPath, as4_path: Option<AsPath>) -> AsPath {
+    match as4_path {
+        Some(as4_path) if as4_path.asns.len() <= as_path.asns.len() => {
+            let lead = as_path.asns.len() - as4_path.asns.len();
+            let mut asns = as_path.asns[..lead].to_vec();
+            asns.extend(as4_path.asns);
+            AsPath {
+                asns,
+                origin_asns: as4_path.origin_asns,
+            }
+        }
+        _ => as_path,
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Addr;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::str::FromStr;
+
+    const ROUTER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 254));
+
+    /// Route Monitoring from IPv4 peer 192.0.2.1 in AS64500, withdrawing
+    /// 198.51.100.0/24 and announcing 203.0.113.0/24 with the AS path
+    /// 64500 64501 in the legacy NLRI fields.
+    const ROUTE_MONITORING_V4: &[u8] = &[
+        0x03, 0x00, 0x00, 0x00, 0x60, 0x00, // common header
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // per-peer header
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xc0, 0x00, 0x02, 0x01, 0x00, 0x00, 0xfb, 0xf4, 0xc0, 0x00, 0x02, 0x01,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // BGP header
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x30, 0x02,
+        0x00, 0x04, 0x18, 0xc6, 0x33, 0x64, // withdrawn routes
+        0x00, 0x11, // path attributes
+        0x40, 0x01, 0x01, 0x00, // ORIGIN
+        0x40, 0x02, 0x0a, 0x02, 0x02, 0x00, 0x00, 0xfb, 0xf4, 0x00, 0x00, 0xfb, 0xf5, // AS_PATH
+        0x18, 0xcb, 0x00, 0x71, // NLRI
+    ];
+
+    /// Route Monitoring from IPv6 peer 2001:db8::1 in AS64500 without four
+    /// octet ASN support, announcing 2001:db8:1::/48 in MP_REACH_NLRI and
+    /// withdrawing 2001:db8:2::/48 in MP_UNREACH_NLRI. The AS_PATH is
+    /// 64500 23456 and the AS4_PATH 64500 4200000000.
+    const ROUTE_MONITORING_V6: &[u8] = &[
+        0x03, 0x00, 0x00, 0x00, 0x8d, 0x00, // common header
+        0x00, 0xa0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // per-peer header
+        0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0xfb, 0xf4, 0xc0, 0x00, 0x02, 0x01,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // BGP header
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x5d, 0x02,
+        0x00, 0x00, // withdrawn routes
+        0x00, 0x46, // path attributes
+        0x40, 0x01, 0x01, 0x00, // ORIGIN
+        0x40, 0x02, 0x06, 0x02, 0x02, 0xfb, 0xf4, 0x5b, 0xa0, // AS_PATH
+        0xc0, 0x11, 0x0a, 0x02, 0x02, 0x00, 0x00, 0xfb, 0xf4, 0xfa, 0x56, 0xea, 0x00, // AS4_PATH
+        0x80, 0x0e, 0x1c, 0x00, 0x02, 0x01, 0x10, // MP_REACH_NLRI
+        0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x30, 0x20, 0x01, 0x0d, 0xb8, 0x00, 0x01,
+        0x80, 0x0f, 0x0a, 0x00, 0x02, 0x01, // MP_UNREACH_NLRI
+        0x30, 0x20, 0x01, 0x0d, 0xb8, 0x00, 0x02,
+    ];
+
+    /// Peer Down of peer 192.0.2.1, the remote system closed the session.
+    const PEER_DOWN_V4: &[u8] = &[
+        0x03, 0x00, 0x00, 0x00, 0x31, 0x02, // common header
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // per-peer header
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xc0, 0x00, 0x02, 0x01, 0x00, 0x00, 0xfb, 0xf4, 0xc0, 0x00, 0x02, 0x01,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x04, // reason
+    ];
+
+    fn asns(asns: &[u32]) -> Vec<Asn> {
+        asns.iter().copied().map(Asn::from).collect()
+    }
+
+    fn v4(s: &str) -> Addr {
+        Addr::V4(Ipv4Addr::from_str(s).unwrap().into())
+    }
+
+    fn v6(s: &str) -> Addr {
+        Addr::V6(Ipv6Addr::from_str(s).unwrap().into())
+    }
+
+    #[tokio::test]
+    async fn read_messages() {
+        let mut data = ROUTE_MONITORING_V4.to_vec();
+        data.extend_from_slice(PEER_DOWN_V4);
+        let mut reader = data.as_slice();
+        assert_eq!(read_message(&mut reader).await.unwrap().unwrap(), ROUTE_MONITORING_V4);
+        assert_eq!(read_message(&mut reader).await.unwrap().unwrap(), PEER_DOWN_V4);
+        assert!(read_message(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn invalid_length_is_an_error() {
+        let mut reader: &[u8] = &[0x03, 0x00, 0x01, 0x00, 0x36, 0x00];
+        let err = read_message(&mut reader).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut reader: &[u8] = &[0x03, 0x00, 0x00, 0x00, 0x05, 0x00];
+        let err = read_message(&mut reader).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn per_peer_header() {
+        let mut msg = Parser(&ROUTE_MONITORING_V4[COMMON_HEADER_LEN..]);
+        let header = PeerHeader::parse(&mut msg).unwrap();
+        assert_eq!(header.peer.addr, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(header.peer.asn, Asn::from(64500));
+        assert!(!header.post_policy);
+        assert!(!header.legacy_as_path);
+        assert_eq!(header.source_id(ROUTER), "bmp/192.0.2.254/192.0.2.1");
+        assert_eq!(msg.0[..16], [0xff; 16]);
+
+        let mut msg = Parser(&ROUTE_MONITORING_V6[COMMON_HEADER_LEN..]);
+        let mut header = PeerHeader::parse(&mut msg).unwrap();
+        assert_eq!(header.peer.addr, IpAddr::from_str("2001:db8::1").unwrap());
+        assert!(header.legacy_as_path);
+        header.post_policy = true;
+        assert_eq!(header.source_id(ROUTER), "bmp/192.0.2.254/2001:db8::1/post-policy");
+    }
+
+    #[test]
+    fn route_monitoring_legacy_nlri() {
+        let mut session = BmpSession::new(ROUTER);
+        let updates = session.process(ROUTE_MONITORING_V4).unwrap();
+        assert_eq!(updates.len(), 2);
+        match &updates[0] {
+            BgpUpdate::Withdraw { source, net, len } => {
+                assert_eq!(source, "bmp/192.0.2.254/192.0.2.1");
+                assert_eq!((*net, *len), (v4("198.51.100.0"), 24));
+            }
+            update => panic!("unexpected {:?}", update),
+        }
+        match &updates[1] {
+            BgpUpdate::Announce { source, peer, net, len, as_path } => {
+                assert_eq!(source, "bmp/192.0.2.254/192.0.2.1");
+                assert_eq!(peer.asn, Asn::from(64500));
+                assert_eq!((*net, *len), (v4("203.0.113.0"), 24));
+                assert_eq!(as_path.asns, asns(&[64500, 64501]));
+                assert_eq!(as_path.origin_asns, asns(&[64501]));
+            }
+            update => panic!("unexpected {:?}", update),
+        }
+    }
+
+    #[test]
+    fn route_monitoring_mp_nlri() {
+        let mut session = BmpSession::new(ROUTER);
+        let updates = session.process(ROUTE_MONITORING_V6).unwrap();
+        assert_eq!(updates.len(), 2);
+        match &updates[0] {
+            BgpUpdate::Withdraw { net, len, .. } => {
+                assert_eq!((*net, *len), (v6("2001:db8:2::"), 48));
+            }
+            update => panic!("unexpected {:?}", update),
+        }
+        match &updates[1] {
+            BgpUpdate::Announce { net, len, as_path, .. } => {
+                assert_eq!((*net, *len), (v6("2001:db8:1::"), 48));
+                assert_eq!(as_path.asns, asns(&[64500, 4200000000]));
+                assert_eq!(as_path.origin_asns, asns(&[4200000000]));
+            }
+            update => panic!("unexpected {:?}", update),
+        }
+    }
+
+    #[test]
+    fn merge_as4_paths() {
+        let path = |path: &[u32]| AsPath {
+            asns: asns(path),
+            origin_asns: asns(&path[path.len() - 1..]),
+        };
+
+        // The AS4_PATH replaces the tail of the AS_PATH.
+        let merged = merge_as4_path(
+            path(&[64496, 23456, 23456]),
+            Some(path(&[4200000000, 4200000001])),
+        );
+        assert_eq!(merged.asns, asns(&[64496, 4200000000, 4200000001]));
+        assert_eq!(merged.origin_asns, asns(&[4200000001]));
+
+        // A longer AS4_PATH is ignored.
+        let merged = merge_as4_path(path(&[64496]), Some(path(&[64497, 4200000000])));
+        assert_eq!(merged.asns, asns(&[64496]));
+
+        let merged = merge_as4_path(path(&[64496, 64497]), None);
+        assert_eq!(merged.asns, asns(&[64496, 64497]));
+    }
+
+    #[test]
+    fn peer_down() {
+        let mut session = BmpSession::new(ROUTER);
+
+        // Nothing was announced yet, so there is nothing to withdraw.
+        assert!(session.process(PEER_DOWN_V4).unwrap().is_empty());
+
+        session.process(ROUTE_MONITORING_V4).unwrap();
+        session.process(ROUTE_MONITORING_V6).unwrap();
+        let updates = session.process(PEER_DOWN_V4).unwrap();
+        assert_eq!(updates.len(), 1);
+        match &updates[0] {
+            BgpUpdate::PeerDown { source } => assert_eq!(source, "bmp/192.0.2.254/192.0.2.1"),
+            update => panic!("unexpected {:?}", update),
+        }
+
+        // Closing the session takes down the remaining peer.
+        let updates = session.close();
+        assert_eq!(updates.len(), 1);
+        match &updates[0] {
+            BgpUpdate::PeerDown { source } => assert_eq!(source, "bmp/192.0.2.254/2001:db8::1"),
+            update => panic!("unexpected {:?}", update),
+        }
+    }
+}
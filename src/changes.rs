@@ -0,0 +1,603 @@
+//! Changes between two stores.
+//!
+//! Comparing the store of an earlier load, e.g. a snapshot kept in the
+//! history, with a later one shows what the refreshed data files changed:
+//! delegations that were added, removed or moved to another organisation
+//! or RIR, and prefixes that were announced, withdrawn or got other origin
+//! ASNs in BGP.
+//!
+//! The stores are told apart by the time they were loaded, which is what
+//! the history is keyed by. The serials of the data they were loaded from
+//! are kept with the changes per source.
+
+use crate::source::SourceType;
+use crate::{Addr, Asn, ExtPrefixRecord, JsonBuilder, Prefix, Rir, RirDelExtRecord, Store};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+//------------ Holder --------------------------------------------------------
+
+/// The RIR and organisation a resource is delegated to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Holder {
+    pub rir: Rir,
+    pub org_handle: String,
+}
+
+impl Holder {
+    fn from_record(record: &RirDelExtRecord) -> Self {
+        Holder {
+            rir: record.rir,
+            org_handle: record.org_handle().to_string(),
+        }
+    }
+
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("rir", self.rir.to_json_id());
+        builder.member_str("orgHandle", &self.org_handle);
+    }
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.rir.to_json_id(), self.org_handle)
+    }
+}
+
+//------------ Resource ------------------------------------------------------
+
+/// What changed: a prefix or a range of ASNs.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Resource {
+    Prefix(Prefix),
+    Asns(Asn, Asn),
+}
+
+impl Resource {
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        match self {
+            Resource::Prefix(prefix) => builder.member_str("prefix", prefix),
+            Resource::Asns(first, last) => {
+                builder.member_str("firstASN", first);
+                builder.member_str("lastASN", last);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resource::Prefix(prefix) => write!(f, "{}", prefix),
+            Resource::Asns(first, last) if first == last => write!(f, "{}", first),
+            Resource::Asns(first, last) => write!(f, "{}-{}", first, last),
+        }
+    }
+}
+
+//------------ Change --------------------------------------------------------
+
+/// A change of a single resource.
+#[derive(Clone, Debug)]
+pub enum Change {
+    /// The resource was delegated.
+    NewDelegation(Holder),
+
+    /// The delegation of the resource was removed.
+    RemovedDelegation(Holder),
+
+    /// The resource was delegated to another organisation or by another
+    /// RIR.
+    Transfer { from: Holder, to: Holder },
+
+    /// The prefix is seen in BGP now, by these origin ASNs.
+    Announcement(Vec<Asn>),
+
+    /// The prefix isn't seen in BGP anymore. It was by these origin ASNs.
+    Withdrawal(Vec<Asn>),
+
+    /// The prefix is seen in BGP with other origin ASNs.
+    OriginChange { from: Vec<Asn>, to: Vec<Asn> },
+}
+
+impl Change {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Change::NewDelegation(_) => "new-delegation",
+            Change::RemovedDelegation(_) => "removed-delegation",
+            Change::Transfer { .. } => "transfer",
+            Change::Announcement(_) => "announcement",
+            Change::Withdrawal(_) => "withdrawal",
+            Change::OriginChange { .. } => "origin-change",
+        }
+    }
+
+    /// Adds the `before` and `after` members, `null` for a side that
+    /// didn't have anything.
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        fn holder(builder: &mut JsonBuilder, key: &str, holder: Option<&Holder>) {
+            match holder {
+                Some(holder) => builder.member_object(key, |builder| holder.to_json_builder(builder)),
+                None => builder.member_raw(key, "null"),
+            }
+        }
+        fn origins(builder: &mut JsonBuilder, key: &str, asns: Option<&[Asn]>) {
+            match asns {
+                Some(asns) => builder.member_object(key, |builder| {
+                    builder.member_array("originASNs", |builder| {
+                        for asn in asns {
+                            builder.array_str(asn);
+                        }
+                    })
+                }),
+                None => builder.member_raw(key, "null"),
+            }
+        }
+
+        match self {
+            Change::NewDelegation(to) => {
+                holder(builder, "before", None);
+                holder(builder, "after", Some(to));
+            }
+            Change::RemovedDelegation(from) => {
+                holder(builder, "before", Some(from));
+                holder(builder, "after", None);
+            }
+            Change::Transfer { from, to } => {
+                holder(builder, "before", Some(from));
+                holder(builder, "after", Some(to));
+            }
+            Change::Announcement(to) => {
+                origins(builder, "before", None);
+                origins(builder, "after", Some(to));
+            }
+            Change::Withdrawal(from) => {
+                origins(builder, "before", Some(from));
+                origins(builder, "after", None);
+            }
+            Change::OriginChange { from, to } => {
+                origins(builder, "before", Some(from));
+                origins(builder, "after", Some(to));
+            }
+        }
+    }
+
+    /// Returns the `before` and `after` columns of the CSV output.
+    ///
+    /// A holder is written as `<rir>/<org-handle>`, origin ASNs are
+    /// separated by spaces.
+    fn csv_columns(&self) -> (String, String) {
+        fn asns(asns: &[Asn]) -> String {
+            asns.iter().map(Asn::to_string).collect::<Vec<_>>().join(" ")
+        }
+
+        match self {
+            Change::NewDelegation(to) => (String::new(), to.to_string()),
+            Change::RemovedDelegation(from) => (from.to_string(), String::new()),
+            Change::Transfer { from, to } => (from.to_string(), to.to_string()),
+            Change::Announcement(to) => (String::new(), asns(to)),
+            Change::Withdrawal(from) => (asns(from), String::new()),
+            Change::OriginChange { from, to } => (asns(from), asns(to)),
+        }
+    }
+}
+
+//------------ SourceSerials -------------------------------------------------
+
+/// The serials of the data of a source in the earlier and the later store.
+///
+/// A serial is `None` if the store didn't have the source or the serial of
+/// its data isn't known.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceSerials {
+    pub source_type: SourceType,
+    pub id: String,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+impl SourceSerials {
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        fn serial(builder: &mut JsonBuilder, key: &str, serial: Option<u64>) {
+            match serial {
+                Some(serial) => builder.member_raw(key, serial),
+                None => builder.member_raw(key, "null"),
+            }
+        }
+
+        builder.member_str("type", self.source_type);
+        builder.member_str("id", &self.id);
+        serial(builder, "since", self.since);
+        serial(builder, "until", self.until);
+    }
+}
+
+//------------ ChangeSet -----------------------------------------------------
+
+/// The changes from one store to a later one.
+#[derive(Clone, Debug)]
+pub struct ChangeSet {
+    /// The time the earlier store was loaded.
+    ///
+    /// This is not the serial of its data, see `sources` for these.
+    pub since: DateTime<Utc>,
+
+    /// The time the later store was loaded.
+    pub until: DateTime<Utc>,
+
+    /// The serials of the data of the sources of both stores, the sources
+    /// of the later store first.
+    pub sources: Vec<SourceSerials>,
+
+    /// The changes, ordered by resource.
+    changes: Vec<(Resource, Change)>,
+}
+
+impl ChangeSet {
+    /// Returns what changed from the `old` store to the `new` one.
+    pub fn between(old: &Store, new: &Store) -> Self {
+        let mut changes = Vec::new();
+
+        let mut old_prefixes: HashMap<Prefix, &ExtPrefixRecord> = prefixes(old).collect();
+        for (prefix, after) in prefixes(new) {
+            let before = old_prefixes.remove(&prefix);
+            prefix_changes(prefix, before, Some(after), &mut changes);
+        }
+        for (prefix, before) in old_prefixes {
+            prefix_changes(prefix, Some(before), None, &mut changes);
+        }
+
+        let mut old_asns: HashMap<(Asn, Asn), Holder> = old
+            .asns
+            .iter()
+            .map(|delegation| ((delegation.first, delegation.last), Holder::from_record(&delegation.record)))
+            .collect();
+        for delegation in new.asns.iter() {
+            let range = (delegation.first, delegation.last);
+            let before = old_asns.remove(&range);
+            let after = Holder::from_record(&delegation.record);
+            if let Some(change) = delegation_change(before, Some(after)) {
+                changes.push((Resource::Asns(range.0, range.1), change));
+            }
+        }
+        for ((first, last), before) in old_asns {
+            if let Some(change) = delegation_change(Some(before), None) {
+                changes.push((Resource::Asns(first, last), change));
+            }
+        }
+
+        changes.sort_by_key(|(resource, _)| *resource);
+        ChangeSet {
+            since: old.updated(),
+            until: new.updated(),
+            sources: source_serials(old, new),
+            changes,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Resource, Change)> {
+        self.changes.iter()
+    }
+
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("since", self.since.format("%+"));
+        builder.member_str("until", self.until.format("%+"));
+        builder.member_array("sources", |builder| {
+            for source in &self.sources {
+                builder.array_object(|builder| source.to_json_builder(builder));
+            }
+        });
+        builder.member_array("changes", |builder| {
+            for (resource, change) in &self.changes {
+                builder.array_object(|builder| {
+                    builder.member_str("type", change.type_name());
+                    resource.to_json_builder(builder);
+                    change.to_json_builder(builder);
+                });
+            }
+        });
+    }
+
+    /// Returns the changes as CSV with a header line and the columns
+    /// `type`, `resource`, `before` and `after`.
+    pub fn to_csv(&self) -> Result<String, Box<dyn Error>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(["type", "resource", "before", "after"])?;
+        for (resource, change) in &self.changes {
+            let (before, after) = change.csv_columns();
+            writer.write_record([change.type_name(), &resource.to_string(), &before, &after])?;
+        }
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+}
+
+/// Returns the serials of the sources of the `new` store, followed by
+/// those only the `old` store has.
+fn source_serials(old: &Store, new: &Store) -> Vec<SourceSerials> {
+    let serial = |store: &Store, source_type, id: &str| {
+        let source = store.sources().find(source_type, id)?;
+        store.sources().get(source).serial()
+    };
+    let mut serials: Vec<_> = new
+        .sources()
+        .iter()
+        .map(|(_, info)| SourceSerials {
+            source_type: info.source_type,
+            id: info.id.clone(),
+            since: serial(old, info.source_type, &info.id),
+            until: info.serial(),
+        })
+        .collect();
+    serials.extend(
+        old.sources()
+            .iter()
+            .filter(|(_, info)| new.sources().find(info.source_type, &info.id).is_none())
+            .map(|(_, info)| SourceSerials {
+                source_type: info.source_type,
+                id: info.id.clone(),
+                since: info.serial(),
+                until: None,
+            }),
+    );
+    serials
+}
+
+/// Returns the prefixes of a store that any source has a record for.
+///
+/// Prefixes withdrawn by all their sources are still in the trees with an
+/// empty record. They are treated as if they weren't.
+fn prefixes(store: &Store) -> impl Iterator<Item = (Prefix, &ExtPrefixRecord)> {
+    let v4 = store
+        .v4
+        .store
+        .prefixes
+        .iter()
+        .filter_map(|p| Some((Prefix::new(Addr::V4(p.net), p.len), p.meta.as_ref()?)));
+    let v6 = store
+        .v6
+        .store
+        .prefixes
+        .iter()
+        .filter_map(|p| Some((Prefix::new(Addr::V6(p.net), p.len), p.meta.as_ref()?)));
+    v4.chain(v6).filter(|(_, meta)| !meta.is_empty())
+}
+
+fn prefix_changes(
+    prefix: Prefix,
+    before: Option<&ExtPrefixRecord>,
+    after: Option<&ExtPrefixRecord>,
+    changes: &mut Vec<(Resource, Change)>,
+) {
+    let holder = |rec: Option<&ExtPrefixRecord>| {
+        rec.and_then(|rec| rec.get::<RirDelExtRecord>())
+            .map(Holder::from_record)
+    };
+    if let Some(change) = delegation_change(holder(before), holder(after)) {
+        changes.push((Resource::Prefix(prefix), change));
+    }
+
+    let origins = |rec: Option<&ExtPrefixRecord>| rec.map(ExtPrefixRecord::origin_asns).unwrap_or_default();
+    let (before, after) = (origins(before), origins(after));
+    let change = match (before.is_empty(), after.is_empty()) {
+        (true, true) => None,
+        (true, false) => Some(Change::Announcement(after)),
+        (false, true) => Some(Change::Withdrawal(before)),
+        (false, false) if before != after => Some(Change::OriginChange { from: before, to: after }),
+        (false, false) => None,
+    };
+    if let Some(change) = change {
+        changes.push((Resource::Prefix(prefix), change));
+    }
+}
+
+fn delegation_change(before: Option<Holder>, after: Option<Holder>) -> Option<Change> {
+    match (before, after) {
+        (None, None) => None,
+        (None, Some(to)) => Some(Change::NewDelegation(to)),
+        (Some(from), None) => Some(Change::RemovedDelegation(from)),
+        (Some(from), Some(to)) if from != to => Some(Change::Transfer { from, to }),
+        (Some(_), Some(_)) => None,
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::{AsPath, BgpPeer, BgpUpdate};
+    use crate::{AsnArray, DelegationStatus, RisWhoisRecord};
+    use chrono::NaiveDate;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::str::FromStr;
+
+    fn addr(s: &str) -> Addr {
+        Addr::from_str(s).unwrap()
+    }
+
+    fn delegation(rir: Rir, org: &str) -> RirDelExtRecord {
+        RirDelExtRecord {
+            group_id: org.into(),
+            rir,
+            country: Some("NL".into()),
+            date: NaiveDate::from_ymd_opt(1993, 9, 1),
+            status: DelegationStatus::Allocated,
+            range: None,
+        }
+    }
+
+    /// Returns a store with the given prefix delegations, ASN delegations
+    /// and prefixes seen in BGP with their origin ASNs.
+    fn store(
+        serial: u64,
+        prefixes: &[(&str, u8, &str)],
+        asns: &[(u32, u32, Rir, &str)],
+        routes: &[(&str, u8, &[u32])],
+    ) -> Store {
+        let mut store = Store::default();
+        let ripe = store.sources.register(SourceType::RirAlloc, "ripencc");
+        store.sources.set_serial(SourceType::RirAlloc, "ripencc", serial);
+        for (net, len, org) in prefixes {
+            let rec = ExtPrefixRecord::new(ripe, delegation(Rir::RipeNcc, org));
+            store.insert_record(addr(net), *len, rec).unwrap();
+        }
+        for (first, count, rir, org) in asns {
+            store.asns.insert(*first, *count, delegation(*rir, org)).unwrap();
+        }
+        let riswhois = store.sources.register(SourceType::Bgp, "riswhois-v4");
+        for (net, len, origins) in routes {
+            let rec = RisWhoisRecord {
+                origin_asns: AsnArray(origins.iter().copied().map(Asn).collect()),
+                seen_by_peers: vec![Some(100)],
+            };
+            store.insert_record(addr(net), *len, ExtPrefixRecord::new(riswhois, rec)).unwrap();
+        }
+        store
+    }
+
+    fn old_store() -> Store {
+        let mut store = store(
+            20240501,
+            &[("193.0.16.0", 21, "ORG-A1"), ("193.0.24.0", 21, "ORG-A1"), ("193.0.32.0", 21, "ORG-A1")],
+            &[(3333, 1, Rir::RipeNcc, "ORG-A1"), (64000, 10, Rir::Arin, "ORG-B2")],
+            &[("193.0.16.0", 21, &[3333]), ("193.0.32.0", 21, &[3333]), ("193.0.40.0", 24, &[3333])],
+        );
+        store.sources.register(SourceType::Irr, "ripe");
+        store
+    }
+
+    fn new_store() -> Store {
+        store(
+            20240502,
+            &[("193.0.16.0", 21, "ORG-C3"), ("193.0.32.0", 21, "ORG-A1"), ("193.0.48.0", 21, "ORG-C3")],
+            &[(3333, 1, Rir::RipeNcc, "ORG-A1"), (64000, 20, Rir::Arin, "ORG-B2")],
+            &[("193.0.16.0", 21, &[3333, 1103]), ("193.0.32.0", 21, &[3333]), ("194.0.0.0", 24, &[65001])],
+        )
+    }
+
+    #[test]
+    fn between() {
+        let changes = ChangeSet::between(&old_store(), &new_store());
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|(resource, change)| (resource.to_string(), change))
+            .collect();
+        assert_eq!(changes.len(), 8);
+
+        let holder = |rir, org: &str| Holder {
+            rir,
+            org_handle: org.into(),
+        };
+        let (org_a1, org_b2, org_c3) = (
+            holder(Rir::RipeNcc, "ORG-A1"),
+            holder(Rir::Arin, "ORG-B2"),
+            holder(Rir::RipeNcc, "ORG-C3"),
+        );
+        assert_eq!(changes[0].0, "193.0.16.0/21");
+        assert!(matches!(
+            changes[0].1,
+            Change::Transfer { from, to } if *from == org_a1 && *to == org_c3
+        ));
+        assert_eq!(changes[1].0, "193.0.16.0/21");
+        assert!(matches!(
+            changes[1].1,
+            Change::OriginChange { from, to } if *from == [Asn(3333)] && *to == [Asn(1103), Asn(3333)]
+        ));
+        assert_eq!(changes[2].0, "193.0.24.0/21");
+        assert!(matches!(changes[2].1, Change::RemovedDelegation(from) if *from == org_a1));
+        assert_eq!(changes[3].0, "193.0.40.0/24");
+        assert!(matches!(changes[3].1, Change::Withdrawal(from) if *from == [Asn(3333)]));
+        assert_eq!(changes[4].0, "193.0.48.0/21");
+        assert!(matches!(changes[4].1, Change::NewDelegation(to) if *to == org_c3));
+        assert_eq!(changes[5].0, "194.0.0.0/24");
+        assert!(matches!(changes[5].1, Change::Announcement(to) if *to == [Asn(65001)]));
+
+        // A changed ASN range is a removed and a new delegation.
+        assert_eq!(changes[6].0, "AS64000-AS64009");
+        assert!(matches!(changes[6].1, Change::RemovedDelegation(from) if *from == org_b2));
+        assert_eq!(changes[7].0, "AS64000-AS64019");
+        assert!(matches!(changes[7].1, Change::NewDelegation(to) if *to == org_b2));
+    }
+
+    #[test]
+    fn no_changes() {
+        assert!(ChangeSet::between(&new_store(), &new_store()).is_empty());
+    }
+
+    #[test]
+    fn withdrawn_prefixes_are_left_out() {
+        let mut old = new_store();
+        let source = "bmp/192.0.2.254/192.0.2.1".to_string();
+        let peer = BgpPeer {
+            addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            asn: Asn(64500),
+        };
+        let as_path = AsPath {
+            asns: vec![Asn(64500)],
+            origin_asns: vec![Asn(64500)],
+        };
+        old.apply_bgp_updates(vec![
+            BgpUpdate::Announce {
+                source: source.clone(),
+                peer,
+                net: addr("198.51.100.0"),
+                len: 24,
+                as_path,
+            },
+            BgpUpdate::Withdraw {
+                source,
+                net: addr("198.51.100.0"),
+                len: 24,
+            },
+        ])
+        .unwrap();
+        assert_eq!(prefixes(&old).count(), prefixes(&new_store()).count());
+        assert!(ChangeSet::between(&old, &new_store()).is_empty());
+    }
+
+    #[test]
+    fn source_serials() {
+        let changes = ChangeSet::between(&old_store(), &new_store());
+        let serials = |source_type, id: &str, since, until| SourceSerials {
+            source_type,
+            id: id.into(),
+            since,
+            until,
+        };
+        assert_eq!(
+            changes.sources,
+            [
+                serials(SourceType::RirAlloc, "ripencc", Some(20240501), Some(20240502)),
+                serials(SourceType::Bgp, "riswhois-v4", None, None),
+                serials(SourceType::Irr, "ripe", None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn to_csv() {
+        let changes = ChangeSet::between(&old_store(), &new_store());
+        assert_eq!(
+            changes.to_csv().unwrap(),
+            "type,resource,before,after\n\
+             transfer,193.0.16.0/21,ripe/ORG-A1,ripe/ORG-C3\n\
+             origin-change,193.0.16.0/21,AS3333,AS1103 AS3333\n\
+             removed-delegation,193.0.24.0/21,ripe/ORG-A1,\n\
+             withdrawal,193.0.40.0/24,AS3333,\n\
+             new-delegation,193.0.48.0/21,,ripe/ORG-C3\n\
+             announcement,194.0.0.0/24,,AS65001\n\
+             removed-delegation,AS64000-AS64009,arin/ORG-B2,\n\
+             new-delegation,AS64000-AS64019,,arin/ORG-B2\n"
+        );
+    }
+}
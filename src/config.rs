@@ -0,0 +1,789 @@
+//! The configuration file of the api server.
+//!
+//! The file is TOML. It has the addresses to listen on, the data sources
+//! with where to find them and how often to look for updates, the strides
+//! of the trees, the CORS policy, where to log to and how delegated files
+//! are verified. Every setting but the sources and keys can be overridden
+//! by an environment variable named after its key, e.g. `ROTO_API_LISTEN`
+//! or `ROTO_API_LOG_LEVEL`. See the README for an example.
+
+use crate::download::{Source, SourceKind, DEFAULT_FETCH_TIMEOUT};
+use crate::history::{self, History};
+use crate::source::SourceType;
+use crate::verify::Verify;
+use crate::{check_strides, Rir};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use std::{fmt, fs};
+use toml::{Table, Value};
+
+/// How often sources with a URL are checked for updates by default.
+pub const DEFAULT_REFRESH: Duration = Duration::from_secs(3600);
+
+/// The settings that can be overridden by environment variables.
+///
+/// The variable is the key in upper case, with dots and dashes replaced
+/// by underscores and prefixed with `ROTO_API_`.
+const OVERRIDES: &[(&str, ValueKind)] = &[
+    ("listen", ValueKind::StrList),
+    ("bmp-listen", ValueKind::StrList),
+    ("strict", ValueKind::Bool),
+    ("data-dir", ValueKind::Str),
+    ("snapshot", ValueKind::Str),
+    ("history.dir", ValueKind::Str),
+    ("history.keep", ValueKind::Int),
+    ("default-sources", ValueKind::Bool),
+    ("refresh", ValueKind::Int),
+    ("fetch-timeout", ValueKind::Int),
+    ("strides.v4", ValueKind::IntList),
+    ("strides.v6", ValueKind::IntList),
+    ("cors.allow-origin", ValueKind::Origins),
+    ("log.level", ValueKind::Str),
+    ("log.file", ValueKind::Str),
+    ("verify.md5", ValueKind::Bool),
+];
+
+//------------ Config --------------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The addresses to serve the HTTP API on.
+    pub listen: Vec<SocketAddr>,
+
+    /// The addresses to accept BMP sessions on.
+    pub bmp_listen: Vec<SocketAddr>,
+
+    /// Whether loading fails on the first bad line of a data file.
+    pub strict: bool,
+
+    /// Where sources with a URL are downloaded to.
+    pub data_dir: Option<PathBuf>,
+
+    /// How long downloading a source with a URL may take.
+    pub fetch_timeout: Duration,
+
+    /// The snapshot the api starts from and writes after loading the data
+    /// files.
+    pub snapshot: Option<PathBuf>,
+
+    /// Where dated snapshots are kept for queries of past data.
+    pub history: Option<History>,
+
+    pub strides_v4: Vec<u8>,
+    pub strides_v6: Vec<u8>,
+    pub cors: CorsConfig,
+    pub log: LogConfig,
+    pub sources: Vec<SourceConfig>,
+}
+
+impl Config {
+    /// Reads a configuration file, with the overrides from the environment.
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+        Self::parse(&data, std::env::vars())
+            .map_err(|err| format!("Invalid config {}: {}", path.display(), err).into())
+    }
+
+    /// Parses a configuration, with overrides from `(name, value)` pairs of
+    /// environment variables.
+    pub fn parse(
+        data: &str,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut table = data.parse::<Table>()?;
+        for (name, value) in env {
+            let (key, kind) = match OVERRIDES.iter().find(|(key, _)| env_name(key) == name) {
+                Some(&(key, kind)) => (key, kind),
+                None => continue,
+            };
+            let value = kind
+                .parse(&value)
+                .map_err(|err| format!("invalid {}: {}", name, err))?;
+            set_value(&mut table, key, value);
+        }
+
+        let mut config = Config {
+            listen: addrs(&table, "listen")?,
+            bmp_listen: addrs(&table, "bmp-listen")?,
+            strict: get_bool(&table, "strict")?.unwrap_or(false),
+            data_dir: get_str(&table, "data-dir")?.map(Into::into),
+            fetch_timeout: get_secs(&table, "fetch-timeout")?.unwrap_or(DEFAULT_FETCH_TIMEOUT),
+            snapshot: get_str(&table, "snapshot")?.map(Into::into),
+            history: match get_str(&table, "history.dir")? {
+                Some(dir) => Some(History::new(
+                    dir,
+                    get_int(&table, "history.keep")?.map_or(history::DEFAULT_KEEP, |keep| keep as usize),
+                )),
+                None => None,
+            },
+            strides_v4: strides(&table, "strides.v4", 32)?,
+            strides_v6: strides(&table, "strides.v6", 128)?,
+            cors: CorsConfig::from_table(&table)?,
+            log: LogConfig::from_table(&table)?,
+            sources: Vec::new(),
+        };
+        if config.listen.is_empty() {
+            return Err("missing 'listen'".into());
+        }
+
+        let refresh = get_secs(&table, "refresh")?.unwrap_or(DEFAULT_REFRESH);
+        let verify = VerifyConfig::from_table(&table)?;
+        if get_bool(&table, "default-sources")?.unwrap_or(false) {
+            for source in Source::default_sources() {
+                let mut source = SourceConfig::from_download(source, refresh);
+                if source.kind == DataKind::Delegations {
+                    source.verify = verify.for_source(source.id.as_deref())?;
+                }
+                config.sources.push(source);
+            }
+        }
+        let sources = match table.get("source") {
+            Some(Value::Array(sources)) => sources.as_slice(),
+            Some(_) => return Err("'source' must be an array of tables".into()),
+            None => &[],
+        };
+        for (idx, source) in sources.iter().enumerate() {
+            let source = source
+                .as_table()
+                .ok_or_else(|| format!("source {} must be a table", idx + 1))
+                .and_then(|source| SourceConfig::from_table(source, refresh, &verify))
+                .map_err(|err| format!("source {}: {}", idx + 1, err))?;
+            config.sources.push(source);
+        }
+        if config.data_dir.is_none() {
+            if let Some(source) = config.sources.iter().find(|source| source.download().is_some()) {
+                return Err(format!("source {} has a URL but there is no 'data-dir'", source).into());
+            }
+        }
+        Ok(config)
+    }
+
+    /// Returns the sources that are downloaded into the data dir.
+    pub fn downloads(&self) -> Vec<Source> {
+        self.sources.iter().filter_map(SourceConfig::download).collect()
+    }
+}
+
+//------------ CorsConfig ----------------------------------------------------
+
+/// Which origins may read the responses of the API in a browser.
+#[derive(Clone, Debug)]
+pub enum CorsConfig {
+    /// Every origin, the `Access-Control-Allow-Origin: *` header.
+    Any,
+
+    /// Only these origins. Responses to other origins have no CORS headers.
+    Origins(Vec<String>),
+}
+
+impl CorsConfig {
+    fn from_table(table: &Table) -> Result<Self, String> {
+        match lookup(table, "cors.allow-origin") {
+            None => Ok(CorsConfig::Any),
+            Some(Value::String(origin)) if origin == "*" => Ok(CorsConfig::Any),
+            Some(Value::String(origin)) => Ok(CorsConfig::Origins(vec![origin.clone()])),
+            Some(Value::Array(origins)) => origins
+                .iter()
+                .map(|origin| origin.as_str().map(String::from))
+                .collect::<Option<Vec<_>>>()
+                .map(CorsConfig::Origins)
+                .ok_or_else(|| "'cors.allow-origin' must be a list of strings".into()),
+            Some(_) => Err("'cors.allow-origin' must be \"*\" or a list of origins".into()),
+        }
+    }
+
+    /// Returns the `Access-Control-Allow-Origin` of the response to a
+    /// request from `origin`, if it may be read there.
+    pub fn allow_origin<'a>(&'a self, origin: Option<&'a str>) -> Option<&'a str> {
+        match self {
+            CorsConfig::Any => Some("*"),
+            CorsConfig::Origins(origins) => origin.filter(|origin| origins.iter().any(|o| o == origin)),
+        }
+    }
+}
+
+//------------ LogConfig -----------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    /// The most detailed level that is logged.
+    pub level: log::LevelFilter,
+
+    /// The file to append to. Without one, messages go to stderr.
+    pub file: Option<PathBuf>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: log::LevelFilter::Info,
+            file: None,
+        }
+    }
+}
+
+impl LogConfig {
+    fn from_table(table: &Table) -> Result<Self, String> {
+        let level = match get_str(table, "log.level")? {
+            Some(level) => log::LevelFilter::from_str(level)
+                .map_err(|_| format!("invalid 'log.level' '{}'", level))?,
+            None => log::LevelFilter::Info,
+        };
+        Ok(LogConfig {
+            level,
+            file: get_str(table, "log.file")?.map(Into::into),
+        })
+    }
+}
+
+//------------ VerifyConfig --------------------------------------------------
+
+/// The `[verify]` table: how delegated-extended files are checked.
+#[derive(Clone, Debug, Default)]
+struct VerifyConfig {
+    /// Whether every file must match its `.md5` file.
+    md5: bool,
+
+    /// The OpenPGP keys of the RIRs, by the name of their delegated file.
+    keys: HashMap<String, PathBuf>,
+}
+
+impl VerifyConfig {
+    fn from_table(table: &Table) -> Result<Self, String> {
+        let keys = match lookup(table, "verify.keys") {
+            None => HashMap::new(),
+            Some(Value::Table(keys)) => keys
+                .iter()
+                .map(|(rir, path)| match (Rir::from(rir.as_str()), path.as_str()) {
+                    (Rir::Unknown, _) => Err(format!("unknown RIR '{}' in 'verify.keys'", rir)),
+                    (_, Some(path)) => Ok((rir.clone(), path.into())),
+                    (_, None) => Err(format!("'verify.keys.{}' must be a string", rir)),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err("'verify.keys' must be a table".into()),
+        };
+        Ok(VerifyConfig {
+            md5: get_bool(table, "verify.md5")?.unwrap_or(false),
+            keys,
+        })
+    }
+
+    /// Returns the checks for a rir-alloc source with the given ID.
+    ///
+    /// The ID has to name the RIR to know which key applies.
+    fn for_source(&self, id: Option<&str>) -> Result<Option<Verify>, String> {
+        let verify = Verify {
+            md5: self.md5,
+            key: id.and_then(|id| self.keys.get(id)).cloned(),
+        };
+        if !verify.is_enabled() {
+            return Ok(None);
+        }
+        match id {
+            Some(id) if Rir::from(id) != Rir::Unknown => Ok(Some(verify)),
+            _ => Err("a verified rir-alloc source needs the name of its RIR as 'id', or 'verify = false'".into()),
+        }
+    }
+}
+
+//------------ SourceConfig --------------------------------------------------
+
+/// A data source of the configuration.
+#[derive(Clone, Debug)]
+pub struct SourceConfig {
+    pub kind: DataKind,
+
+    /// The name of the source. This is the RIR, collector or file name in
+    /// the data dir where that is needed.
+    pub id: Option<String>,
+    pub location: Location,
+
+    /// How often the source is checked for updates. Files are only checked
+    /// if this is set.
+    pub refresh: Option<Duration>,
+
+    /// The checks a delegated-extended file has to pass before it is
+    /// loaded.
+    pub verify: Option<Verify>,
+}
+
+/// What a source has and how it is loaded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DataKind {
+    /// A delegated-extended file, `Store::load_prefixes`.
+    Delegations,
+
+    /// A riswhois file, `Store::load_riswhois`.
+    RisWhois,
+
+    /// An MRT RIB dump, `Store::load_mrt`.
+    Mrt,
+
+    /// An RPSL dump of an IRR database, `Store::load_irr`.
+    Irr,
+
+    /// A VRP export, `Store::load_vrps`.
+    Vrps,
+
+    /// An IANA address space registry, `Store::load_iana_address_space`.
+    IanaAllocations,
+
+    /// AS names, `Store::load_as_names`.
+    AsNames,
+
+    /// AS relationships, `Store::load_as_relationships`.
+    AsRelationships,
+}
+
+#[derive(Clone, Debug)]
+pub enum Location {
+    Path(String),
+    Url(String),
+}
+
+impl SourceConfig {
+    /// Returns a source downloaded from a URL into the data dir.
+    pub fn from_download(source: Source, refresh: Duration) -> Self {
+        let kind = match source.kind {
+            SourceKind::DelExt(_) => DataKind::Delegations,
+            SourceKind::RisWhois => DataKind::RisWhois,
+            SourceKind::IanaAddressSpace => DataKind::IanaAllocations,
+        };
+        SourceConfig {
+            kind,
+            id: Some(source.name),
+            location: Location::Url(source.url),
+            refresh: Some(refresh),
+            verify: source.verify,
+        }
+    }
+
+    fn from_table(table: &Table, default_refresh: Duration, verify: &VerifyConfig) -> Result<Self, String> {
+        let source_type = get_str(table, "type")?.ok_or("missing 'type'")?;
+        let source_type = SourceType::from_str(source_type)?;
+        let format = get_str(table, "format")?;
+        let kind = match (source_type, format) {
+            (SourceType::RirAlloc, None) => DataKind::Delegations,
+            (SourceType::Bgp, None | Some("riswhois")) => DataKind::RisWhois,
+            (SourceType::Bgp, Some("mrt")) => DataKind::Mrt,
+            (SourceType::Irr, None) => DataKind::Irr,
+            (SourceType::Rpki, None) => DataKind::Vrps,
+            (SourceType::IanaAlloc, None) => DataKind::IanaAllocations,
+            (SourceType::AsName, None) => DataKind::AsNames,
+            (SourceType::AsRel, None) => DataKind::AsRelationships,
+            (SourceType::IanaSpecial, _) => return Err("the iana-special registries are built in".into()),
+            (_, Some(format)) => return Err(format!("unknown format '{}' for {}", format, source_type)),
+        };
+        let id = get_str(table, "id")?.map(String::from);
+        let location = match (get_str(table, "path")?, get_str(table, "url")?) {
+            (Some(path), None) => Location::Path(path.into()),
+            (None, Some(url)) => Location::Url(url.into()),
+            _ => return Err("needs either 'path' or 'url'".into()),
+        };
+        let refresh = get_secs(table, "refresh")?;
+        let verify = match (kind, get_bool(table, "verify")?) {
+            (DataKind::Delegations, None | Some(true)) => verify.for_source(id.as_deref())?,
+            (_, None | Some(false)) => None,
+            (_, Some(true)) => return Err("only rir-alloc sources can be verified".into()),
+        };
+        let source = SourceConfig {
+            kind,
+            id,
+            refresh: match location {
+                Location::Url(_) => Some(refresh.unwrap_or(default_refresh)),
+                Location::Path(_) => refresh,
+            },
+            location,
+            verify,
+        };
+
+        if kind == DataKind::Mrt && source.id.is_none() {
+            return Err("an MRT source needs the collector as 'id'".into());
+        }
+        if let Location::Url(_) = source.location {
+            match (kind, source.id.as_deref()) {
+                (DataKind::Delegations | DataKind::RisWhois | DataKind::IanaAllocations, None) => {
+                    return Err("a source with a URL needs an 'id'".into())
+                }
+                (DataKind::Delegations, Some(id)) if Rir::from(id) == Rir::Unknown => {
+                    return Err(format!("unknown RIR '{}', expected the name of its delegated file", id))
+                }
+                (DataKind::Delegations | DataKind::RisWhois | DataKind::IanaAllocations, _) => {}
+                _ => return Err("only rir-alloc, bgp riswhois and iana-alloc sources can have a URL".into()),
+            }
+        }
+        Ok(source)
+    }
+
+    /// Returns the download of a source with a URL.
+    pub fn download(&self) -> Option<Source> {
+        let url = match &self.location {
+            Location::Url(url) => url,
+            Location::Path(_) => return None,
+        };
+        let name = self.id.clone()?;
+        let kind = match self.kind {
+            DataKind::Delegations => SourceKind::DelExt(Rir::from(name.as_str())),
+            DataKind::RisWhois => SourceKind::RisWhois,
+            DataKind::IanaAllocations => SourceKind::IanaAddressSpace,
+            _ => return None,
+        };
+        let mut source = Source::new(name, url.clone(), kind);
+        source.verify = self.verify.clone();
+        Some(source)
+    }
+}
+
+impl fmt::Display for SourceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let location = match &self.location {
+            Location::Path(path) => path,
+            Location::Url(url) => url,
+        };
+        match &self.id {
+            Some(id) => write!(f, "{:?} '{}' ({})", self.kind, id, location),
+            None => write!(f, "{:?} ({})", self.kind, location),
+        }
+    }
+}
+
+//------------ Helpers -------------------------------------------------------
+
+/// The kind of value of a setting that can be overridden.
+#[derive(Clone, Copy, Debug)]
+enum ValueKind {
+    Str,
+    Bool,
+    Int,
+
+    /// A comma-separated list of strings.
+    StrList,
+
+    /// A comma-separated list of integers.
+    IntList,
+
+    /// "*" or a comma-separated list of origins.
+    Origins,
+}
+
+impl ValueKind {
+    fn parse(self, s: &str) -> Result<Value, String> {
+        let list = || s.split(',').map(str::trim).filter(|item| !item.is_empty());
+        match self {
+            ValueKind::Str => Ok(Value::String(s.into())),
+            ValueKind::Bool => bool::from_str(s).map(Value::Boolean).map_err(|err| err.to_string()),
+            ValueKind::Int => i64::from_str(s).map(Value::Integer).map_err(|err| err.to_string()),
+            ValueKind::StrList => Ok(Value::Array(list().map(|item| Value::String(item.into())).collect())),
+            ValueKind::IntList => list()
+                .map(|item| i64::from_str(item).map(Value::Integer))
+                .collect::<Result<_, _>>()
+                .map(Value::Array)
+                .map_err(|err| err.to_string()),
+            ValueKind::Origins if s.trim() == "*" => Ok(Value::String("*".into())),
+            ValueKind::Origins => ValueKind::StrList.parse(s),
+        }
+    }
+}
+
+fn env_name(key: &str) -> String {
+    format!("ROTO_API_{}", key.replace(['.', '-'], "_").to_ascii_uppercase())
+}
+
+/// Sets the value of a dotted key, creating the tables on the way.
+fn set_value(table: &mut Table, key: &str, value: Value) {
+    match key.split_once('.') {
+        Some((head, rest)) => {
+            let entry = table
+                .entry(head)
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            if let Value::Table(table) = entry {
+                set_value(table, rest, value);
+            }
+        }
+        None => {
+            table.insert(key.into(), value);
+        }
+    }
+}
+
+/// Returns the value of a dotted key.
+fn lookup<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    match key.split_once('.') {
+        Some((head, rest)) => lookup(table.get(head)?.as_table()?, rest),
+        None => table.get(key),
+    }
+}
+
+fn get_str<'a>(table: &'a Table, key: &str) -> Result<Option<&'a str>, String> {
+    match lookup(table, key) {
+        None => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(_) => Err(format!("'{}' must be a string", key)),
+    }
+}
+
+fn get_bool(table: &Table, key: &str) -> Result<Option<bool>, String> {
+    match lookup(table, key) {
+        None => Ok(None),
+        Some(Value::Boolean(b)) => Ok(Some(*b)),
+        Some(_) => Err(format!("'{}' must be true or false", key)),
+    }
+}
+
+fn get_int(table: &Table, key: &str) -> Result<Option<u64>, String> {
+    match lookup(table, key) {
+        None => Ok(None),
+        Some(Value::Integer(n)) if *n >= 0 => Ok(Some(*n as u64)),
+        Some(_) => Err(format!("'{}' must be a positive integer", key)),
+    }
+}
+
+/// Returns an interval given in seconds.
+fn get_secs(table: &Table, key: &str) -> Result<Option<Duration>, String> {
+    match get_int(table, key)? {
+        Some(0) => Err(format!("'{}' must be at least one second", key)),
+        secs => Ok(secs.map(Duration::from_secs)),
+    }
+}
+
+/// Returns a list of socket addresses, which may also be a single one.
+fn addrs(table: &Table, key: &str) -> Result<Vec<SocketAddr>, String> {
+    let values = match lookup(table, key) {
+        None => return Ok(Vec::new()),
+        Some(Value::Array(values)) => values.iter().collect(),
+        Some(value) => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| {
+            value
+                .as_str()
+                .and_then(|addr| SocketAddr::from_str(addr).ok())
+                .ok_or_else(|| format!("invalid address {} in '{}'", value, key))
+        })
+        .collect()
+}
+
+fn strides(table: &Table, key: &str, bits: u8) -> Result<Vec<u8>, String> {
+    let strides = match lookup(table, key) {
+        None => return Ok(vec![4]),
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| value.as_integer().and_then(|n| u8::try_from(n).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| format!("'{}' must be a list of strides", key))?,
+        Some(_) => return Err(format!("'{}' must be a list of strides", key)),
+    };
+    check_strides(&strides, bits).map_err(|err| format!("'{}': {}", key, err))?;
+    Ok(strides)
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(data: &str) -> Result<Config, String> {
+        parse_with_env(data, &[])
+    }
+
+    fn parse_with_env(data: &str, env: &[(&str, &str)]) -> Result<Config, String> {
+        let env = env.iter().map(|(name, value)| (name.to_string(), value.to_string()));
+        Config::parse(data, env).map_err(|err| err.to_string())
+    }
+
+    /// Returns the error of a configuration with a single source.
+    fn source_err(source: &str) -> String {
+        parse(&format!("listen = \"127.0.0.1:8100\"\n[[source]]\n{}", source)).unwrap_err()
+    }
+
+    #[test]
+    fn defaults() {
+        let config = parse("listen = \"127.0.0.1:8100\"").unwrap();
+        assert_eq!(config.listen, [SocketAddr::from(([127, 0, 0, 1], 8100))]);
+        assert!(config.bmp_listen.is_empty());
+        assert!(!config.strict);
+        assert!(config.data_dir.is_none());
+        assert_eq!(config.fetch_timeout, DEFAULT_FETCH_TIMEOUT);
+        assert!(config.snapshot.is_none());
+        assert!(config.history.is_none());
+        assert_eq!(config.strides_v4, [4]);
+        assert_eq!(config.strides_v6, [4]);
+        assert!(matches!(config.cors, CorsConfig::Any));
+        assert_eq!(config.log.level, log::LevelFilter::Info);
+        assert!(config.log.file.is_none());
+        assert!(config.sources.is_empty());
+    }
+
+    #[test]
+    fn listen_is_required() {
+        assert_eq!(parse("").unwrap_err(), "missing 'listen'");
+    }
+
+    #[test]
+    fn default_sources() {
+        let config = parse(
+            "listen = \"127.0.0.1:8100\"\ndata-dir = \"./data\"\ndefault-sources = true\nrefresh = 600",
+        )
+        .unwrap();
+        let downloads = config.downloads();
+        assert_eq!(downloads.len(), Source::default_sources().len());
+        assert!(config.sources.iter().all(|source| source.refresh == Some(Duration::from_secs(600))));
+
+        let err = parse("listen = \"127.0.0.1:8100\"\ndefault-sources = true").unwrap_err();
+        assert!(err.contains("there is no 'data-dir'"), "{}", err);
+    }
+
+    #[test]
+    fn overrides() {
+        let data = "listen = \"127.0.0.1:8100\"\nstrict = false\n[history]\ndir = \"./history\"";
+        let config = parse_with_env(
+            data,
+            &[
+                ("ROTO_API_LISTEN", "0.0.0.0:8200, [::1]:8200"),
+                ("ROTO_API_BMP_LISTEN", "0.0.0.0:11019"),
+                ("ROTO_API_STRICT", "true"),
+                ("ROTO_API_DATA_DIR", "/srv/data"),
+                ("ROTO_API_FETCH_TIMEOUT", "30"),
+                ("ROTO_API_HISTORY_KEEP", "5"),
+                ("ROTO_API_STRIDES_V4", "8,8,8,8"),
+                ("ROTO_API_CORS_ALLOW_ORIGIN", "https://example.org,https://example.net"),
+                ("ROTO_API_LOG_LEVEL", "debug"),
+                ("ROTO_API_UNKNOWN", "ignored"),
+                ("PATH", "/usr/bin"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            config.listen,
+            [
+                SocketAddr::from_str("0.0.0.0:8200").unwrap(),
+                SocketAddr::from_str("[::1]:8200").unwrap()
+            ]
+        );
+        assert_eq!(config.bmp_listen, [SocketAddr::from_str("0.0.0.0:11019").unwrap()]);
+        assert!(config.strict);
+        assert_eq!(config.data_dir, Some(PathBuf::from("/srv/data")));
+        assert_eq!(config.fetch_timeout, Duration::from_secs(30));
+        let history = config.history.unwrap();
+        assert_eq!(history.dir(), Path::new("./history"));
+        assert!(format!("{:?}", history).contains("keep: 5"));
+        assert_eq!(config.strides_v4, [8, 8, 8, 8]);
+        match config.cors {
+            CorsConfig::Origins(origins) => {
+                assert_eq!(origins, ["https://example.org", "https://example.net"])
+            }
+            CorsConfig::Any => panic!("expected origins"),
+        }
+        assert_eq!(config.log.level, log::LevelFilter::Debug);
+
+        let config = parse_with_env(data, &[("ROTO_API_CORS_ALLOW_ORIGIN", "*")]).unwrap();
+        assert!(matches!(config.cors, CorsConfig::Any));
+    }
+
+    #[test]
+    fn invalid_overrides() {
+        let data = "listen = \"127.0.0.1:8100\"";
+        let err = parse_with_env(data, &[("ROTO_API_STRICT", "yes")]).unwrap_err();
+        assert!(err.starts_with("invalid ROTO_API_STRICT"), "{}", err);
+        let err = parse_with_env(data, &[("ROTO_API_LISTEN", "localhost")]).unwrap_err();
+        assert!(err.starts_with("invalid address"), "{}", err);
+        let err = parse_with_env(data, &[("ROTO_API_STRIDES_V4", "9")]).unwrap_err();
+        assert!(err.starts_with("'strides.v4'"), "{}", err);
+        let err = parse_with_env(data, &[("ROTO_API_FETCH_TIMEOUT", "0")]).unwrap_err();
+        assert_eq!(err, "'fetch-timeout' must be at least one second");
+    }
+
+    #[test]
+    fn sources() {
+        let config = parse(
+            "listen = \"127.0.0.1:8100\"\n\
+             data-dir = \"./data\"\n\
+             [[source]]\n\
+             type = \"bgp\"\nformat = \"mrt\"\nid = \"rrc00\"\npath = \"bview.gz\"\n\
+             [[source]]\n\
+             type = \"rir-alloc\"\nid = \"ripencc\"\nurl = \"https://example.org/ripencc\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.sources.len(), 2);
+        assert_eq!(config.sources[0].kind, DataKind::Mrt);
+        assert_eq!(config.sources[0].refresh, None);
+        assert_eq!(config.sources[1].kind, DataKind::Delegations);
+        assert_eq!(config.sources[1].refresh, Some(DEFAULT_REFRESH));
+        assert_eq!(config.downloads().len(), 1);
+    }
+
+    #[test]
+    fn source_errors() {
+        assert_eq!(source_err("path = \"x\""), "source 1: missing 'type'");
+        assert_eq!(source_err("type = \"foo\"\npath = \"x\""), "source 1: unknown source type 'foo'");
+        assert_eq!(
+            source_err("type = \"iana-special\"\npath = \"x\""),
+            "source 1: the iana-special registries are built in"
+        );
+        assert_eq!(
+            source_err("type = \"rpki\"\nformat = \"mrt\"\npath = \"x\""),
+            "source 1: unknown format 'mrt' for rpki"
+        );
+        assert_eq!(source_err("type = \"rpki\""), "source 1: needs either 'path' or 'url'");
+        assert_eq!(
+            source_err("type = \"rpki\"\npath = \"x\"\nurl = \"https://example.org\""),
+            "source 1: needs either 'path' or 'url'"
+        );
+        assert_eq!(
+            source_err("type = \"irr\"\npath = \"x\"\nverify = true"),
+            "source 1: only rir-alloc sources can be verified"
+        );
+        assert_eq!(
+            source_err("type = \"bgp\"\nformat = \"mrt\"\npath = \"x\""),
+            "source 1: an MRT source needs the collector as 'id'"
+        );
+        assert_eq!(
+            source_err("type = \"rir-alloc\"\nurl = \"https://example.org\""),
+            "source 1: a source with a URL needs an 'id'"
+        );
+        assert_eq!(
+            source_err("type = \"rir-alloc\"\nid = \"foo\"\nurl = \"https://example.org\""),
+            "source 1: unknown RIR 'foo', expected the name of its delegated file"
+        );
+        assert_eq!(
+            source_err("type = \"rpki\"\nurl = \"https://example.org\""),
+            "source 1: only rir-alloc, bgp riswhois and iana-alloc sources can have a URL"
+        );
+        assert_eq!(
+            source_err("type = \"rpki\"\npath = \"x\"\nrefresh = 0"),
+            "source 1: 'refresh' must be at least one second"
+        );
+    }
+
+    #[test]
+    fn verified_sources() {
+        let data = "listen = \"127.0.0.1:8100\"\n\
+                    [verify]\nmd5 = true\n\
+                    [verify.keys]\nripencc = \"ripencc.asc\"\n\
+                    [[source]]\ntype = \"rir-alloc\"\npath = \"x\"\n";
+        let config = parse(&format!("{}id = \"ripencc\"", data)).unwrap();
+        let verify = config.sources[0].verify.as_ref().unwrap();
+        assert!(verify.md5);
+        assert_eq!(verify.key.as_deref(), Some(Path::new("ripencc.asc")));
+
+        let config = parse(&format!("{}id = \"ripencc\"\nverify = false", data)).unwrap();
+        assert!(config.sources[0].verify.is_none());
+
+        let err = parse(data).unwrap_err();
+        assert!(err.contains("needs the name of its RIR"), "{}", err);
+
+        let err = parse("listen = \"127.0.0.1:8100\"\n[verify.keys]\nfoo = \"foo.asc\"").unwrap_err();
+        assert_eq!(err, "unknown RIR 'foo' in 'verify.keys'");
+    }
+}
@@ -0,0 +1,566 @@
+//! Fetching the data sets from the RIRs, RIS and IANA.
+//!
+//! This replaces the `download-*` and `update-status-*` scripts. Every
+//! source is fetched with a conditional GET that carries the Last-Modified
+//! and ETag headers of the previous download. The files end up in a data
+//! directory, from where they can be handed to `Store::load_prefixes`,
+//! `Store::load_riswhois` and `Store::load_iana_address_space`.
+//!
+//! Delegated-extended files that are to be verified are only stored if
+//! they pass, together with their `.md5` and `.asc` files.
+
+use crate::source::{SourceRegistry, SourceType};
+use crate::verify::{sidecar_path, Verify};
+use crate::Rir;
+use chrono::{DateTime, FixedOffset};
+use hyper::client::HttpConnector;
+use hyper::{header, Body, Client, Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{fmt, fs};
+
+/// How long fetching a source, with its checksum and signature, may take.
+pub const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(300);
+
+//------------ SourceKind ----------------------------------------------------
+
+#[derive(Clone, Copy, Debug)]
+pub enum SourceKind {
+    /// A delegated-extended file published by a RIR.
+    DelExt(Rir),
+
+    /// A gzipped riswhoisdump file.
+    RisWhois,
+
+    /// The CSV version of an IANA address space registry.
+    IanaAddressSpace,
+}
+
+//------------ Source --------------------------------------------------------
+
+/// A remote data set and the name it is stored under in the data dir.
+#[derive(Clone, Debug)]
+pub struct Source {
+    pub name: String,
+    pub url: String,
+    pub kind: SourceKind,
+
+    /// The checks a downloaded delegated-extended file has to pass.
+    pub verify: Option<Verify>,
+}
+
+impl Source {
+    pub fn new(name: impl Into<String>, url: impl Into<String>, kind: SourceKind) -> Self {
+        Source {
+            name: name.into(),
+            url: url.into(),
+            kind,
+            verify: None,
+        }
+    }
+
+    /// The sources the download scripts used to fetch.
+    pub fn default_sources() -> Vec<Source> {
+        vec![
+            Source::new(
+                "afrinic",
+                "https://ftp.afrinic.net/pub/stats/afrinic/delegated-afrinic-extended-latest",
+                SourceKind::DelExt(Rir::Afrinic),
+            ),
+            Source::new(
+                "apnic",
+                "https://ftp.apnic.net/stats/apnic/delegated-apnic-extended-latest",
+                SourceKind::DelExt(Rir::Apnic),
+            ),
+            Source::new(
+                "arin",
+                "https://ftp.arin.net/pub/stats/arin/delegated-arin-extended-latest",
+                SourceKind::DelExt(Rir::Arin),
+            ),
+            Source::new(
+                "lacnic",
+                "https://ftp.lacnic.net/pub/stats/lacnic/delegated-lacnic-extended-latest",
+                SourceKind::DelExt(Rir::Lacnic),
+            ),
+            Source::new(
+                "ripencc",
+                "https://ftp.ripe.net/pub/stats/ripencc/delegated-ripencc-extended-latest",
+                SourceKind::DelExt(Rir::RipeNcc),
+            ),
+            Source::new(
+                "riswhois4",
+                "https://www.ris.ripe.net/dumps/riswhoisdump.IPv4.gz",
+                SourceKind::RisWhois,
+            ),
+            Source::new(
+                "riswhois6",
+                "https://www.ris.ripe.net/dumps/riswhoisdump.IPv6.gz",
+                SourceKind::RisWhois,
+            ),
+            Source::new(
+                "iana-ipv4",
+                "https://www.iana.org/assignments/ipv4-address-space/ipv4-address-space.csv",
+                SourceKind::IanaAddressSpace,
+            ),
+            Source::new(
+                "iana-ipv6",
+                "https://www.iana.org/assignments/ipv6-unicast-address-assignments/ipv6-unicast-address-assignments.csv",
+                SourceKind::IanaAddressSpace,
+            ),
+        ]
+    }
+
+    /// Returns the type and ID of the store source the data ends up in.
+    ///
+    /// Both IANA registries make up one source. A riswhois file is a source
+    /// of its own, which `Store::load_riswhois` takes care of.
+    fn store_source(&self) -> Option<(SourceType, String)> {
+        match self.kind {
+            SourceKind::DelExt(rir) => Some((SourceType::RirAlloc, rir.to_json_id())),
+            SourceKind::RisWhois => None,
+            SourceKind::IanaAddressSpace => Some((SourceType::IanaAlloc, "iana".into())),
+        }
+    }
+}
+
+//------------ SourceState ---------------------------------------------------
+
+/// The validators of the last successful download of a source.
+#[derive(Clone, Debug, Default)]
+pub struct SourceState {
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+}
+
+impl SourceState {
+    fn from_response(resp: &hyper::Response<Body>) -> Self {
+        let get = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToString::to_string)
+        };
+        SourceState {
+            last_modified: get(header::LAST_MODIFIED),
+            etag: get(header::ETAG),
+        }
+    }
+
+    pub fn last_modified(&self) -> Option<DateTime<FixedOffset>> {
+        self.last_modified
+            .as_ref()
+            .and_then(|lm| DateTime::parse_from_rfc2822(lm).ok())
+    }
+
+    /// Returns whether `next` describes a newer file than `self`.
+    ///
+    /// "Not equal" isn't enough: ARIN moves the Last-Modified header
+    /// backwards every now and then, and the ETag changes along with it.
+    /// So if both downloads carry a Last-Modified header, only a later
+    /// date counts as a change. The ETag is only used as a fallback.
+    fn is_superseded_by(&self, next: &SourceState) -> bool {
+        if let (Some(cur), Some(next)) = (self.last_modified(), next.last_modified()) {
+            return cur < next;
+        }
+        match (&self.etag, &next.etag) {
+            (Some(cur), Some(next)) => cur != next,
+            _ => true,
+        }
+    }
+
+    fn parse(content: &str) -> Self {
+        let mut state = SourceState::default();
+        for line in content.lines() {
+            if let Some((key, value)) = line.split_once(':') {
+                let value = Some(value.trim().to_string());
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "last-modified" => state.last_modified = value,
+                    "etag" => state.etag = value,
+                    _ => {}
+                }
+            }
+        }
+        state
+    }
+}
+
+impl fmt::Display for SourceState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(lm) = &self.last_modified {
+            writeln!(f, "Last-Modified: {}", lm)?;
+        }
+        if let Some(etag) = &self.etag {
+            writeln!(f, "ETag: {}", etag)?;
+        }
+        Ok(())
+    }
+}
+
+//------------ DataDir -------------------------------------------------------
+
+/// The directory downloaded files and their validators are kept in.
+///
+/// For every source there is a data file, stored as it was downloaded,
+/// and a `.headers` file with the validators of the download it
+/// came from.
+#[derive(Clone, Debug)]
+pub struct DataDir {
+    path: PathBuf,
+}
+
+impl DataDir {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        DataDir { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn data_path(&self, source: &Source) -> PathBuf {
+        match source.kind {
+            SourceKind::DelExt(_) => self.path.join(format!("{}.txt", source.name)),
+            SourceKind::RisWhois => self.path.join(format!("{}.gz", source.name)),
+            SourceKind::IanaAddressSpace => self.path.join(format!("{}.csv", source.name)),
+        }
+    }
+
+    fn state_path(&self, source: &Source) -> PathBuf {
+        self.path.join(format!("{}.headers", source.name))
+    }
+
+    /// Returns the state of the last download of a source.
+    ///
+    /// If there is no data file for the source, an empty state is
+    /// returned, so the next download is unconditional.
+    pub fn state(&self, source: &Source) -> SourceState {
+        if !self.data_path(source).exists() {
+            return SourceState::default();
+        }
+        fs::read_to_string(self.state_path(source))
+            .map(|content| SourceState::parse(&content))
+            .unwrap_or_default()
+    }
+
+    /// Replaces the data file, the files next to it and the state of a
+    /// source.
+    ///
+    /// The files next to the data file are given by their extension, e.g.
+    /// `md5`. Every file is written to a temporary file first, so a store
+    /// that is being loaded never sees a partially written file. Like
+    /// `curl -R`, the data file gets the Last-Modified date of the download
+    /// as its modification time.
+    fn store(
+        &self,
+        source: &Source,
+        data: &[u8],
+        sidecars: &[(&str, Vec<u8>)],
+        state: &SourceState,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.path)?;
+        let data_path = self.data_path(source);
+        let mut files = vec![(data_path.clone(), data)];
+        for (ext, content) in sidecars {
+            files.push((sidecar_path(&data_path, ext), content));
+        }
+        for (path, content) in &files {
+            fs::write(sidecar_path(path, "tmp"), content)?;
+        }
+        if let Some(last_modified) = state.last_modified() {
+            fs::File::options()
+                .write(true)
+                .open(sidecar_path(&data_path, "tmp"))?
+                .set_modified(last_modified.into())?;
+        }
+        for (path, _) in &files {
+            fs::rename(sidecar_path(path, "tmp"), path)?;
+        }
+        fs::write(self.state_path(source), state.to_string())?;
+        Ok(())
+    }
+
+    /// Returns the paths of the riswhois files that are present.
+    pub fn riswhois_paths(&self, sources: &[Source]) -> Vec<PathBuf> {
+        self.present_paths(sources, |kind| matches!(kind, SourceKind::RisWhois))
+    }
+
+    /// Returns the paths of the IANA address space files that are present.
+    pub fn iana_paths(&self, sources: &[Source]) -> Vec<PathBuf> {
+        self.present_paths(sources, |kind| matches!(kind, SourceKind::IanaAddressSpace))
+    }
+
+    fn present_paths(&self, sources: &[Source], op: impl Fn(SourceKind) -> bool) -> Vec<PathBuf> {
+        sources
+            .iter()
+            .filter(|source| op(source.kind))
+            .map(|source| self.data_path(source))
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    /// Sets the time the downloaded sources were last updated.
+    ///
+    /// This is the Last-Modified date of the download. The serial is the
+    /// same as a UNIX timestamp, like the `stat -c %Y` of a file fetched
+    /// with `curl -R`.
+    pub fn set_last_updated(&self, sources: &[Source], registry: &mut SourceRegistry) {
+        for source in sources {
+            if let (Some((source_type, id)), Some(last_modified)) =
+                (source.store_source(), self.state(source).last_modified())
+            {
+                registry.set_last_updated(source_type, &id, last_modified);
+            }
+        }
+    }
+}
+
+//------------ Downloader ----------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub struct Downloader {
+    client: Client<HttpsConnector<HttpConnector>>,
+    data_dir: DataDir,
+
+    /// How long an update of a single source may take.
+    timeout: Duration,
+}
+
+impl Downloader {
+    pub fn new(data_dir: DataDir, timeout: Duration) -> Self {
+        let https = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Downloader {
+            client: Client::builder().build(https),
+            data_dir,
+            timeout,
+        }
+    }
+
+    pub fn data_dir(&self) -> &DataDir {
+        &self.data_dir
+    }
+
+    /// Fetches a source if it changed since the last download.
+    ///
+    /// Returns whether a new file was stored in the data dir. A server that
+    /// doesn't finish within the timeout fails the update, so a stalled
+    /// connection can't hold up the other sources.
+    pub async fn update(&self, source: &Source) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        match tokio::time::timeout(self.timeout, self.fetch_update(source)).await {
+            Ok(res) => res,
+            Err(_) => Err(format!(
+                "{}: timed out after {} seconds",
+                source.url,
+                self.timeout.as_secs_f64()
+            )
+            .into()),
+        }
+    }
+
+    async fn fetch_update(&self, source: &Source) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let cur_state = self.data_dir.state(source);
+
+        let mut req = Request::get(&source.url);
+        if let Some(lm) = &cur_state.last_modified {
+            req = req.header(header::IF_MODIFIED_SINCE, lm);
+        }
+        if let Some(etag) = &cur_state.etag {
+            req = req.header(header::IF_NONE_MATCH, etag);
+        }
+        let resp = self.client.request(req.body(Body::empty())?).await?;
+
+        match resp.status() {
+            StatusCode::NOT_MODIFIED => return Ok(false),
+            StatusCode::OK => {}
+            status => return Err(format!("{}: unexpected status {}", source.url, status).into()),
+        }
+
+        let next_state = SourceState::from_response(&resp);
+        if !cur_state.is_superseded_by(&next_state) {
+            return Ok(false);
+        }
+
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let mut sidecars = Vec::new();
+        if let Some(verify) = &source.verify {
+            if verify.md5 {
+                sidecars.push(("md5", self.fetch(&format!("{}.md5", source.url)).await?));
+            }
+            if verify.key.is_some() {
+                sidecars.push(("asc", self.fetch(&format!("{}.asc", source.url)).await?));
+            }
+            let file = |ext| {
+                sidecars
+                    .iter()
+                    .find(|(sidecar, _)| *sidecar == ext)
+                    .map(|(_, content)| content.as_slice())
+            };
+            verify
+                .check(&body, file("md5"), file("asc"))
+                .map_err(|err| format!("{} rejected: {}", source.url, err))?;
+        }
+        self.data_dir
+            .store(source, &body, &sidecars, &next_state)
+            .map_err(|err| err.to_string())?;
+        Ok(true)
+    }
+
+    /// Fetches a file unconditionally.
+    async fn fetch(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let resp = self.client.get(url.parse()?).await?;
+        if resp.status() != StatusCode::OK {
+            return Err(format!("{}: unexpected status {}", url, resp.status()).into());
+        }
+        Ok(hyper::body::to_bytes(resp.into_body()).await?.to_vec())
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const LAST_MODIFIED: &str = "Tue, 01 Aug 2023 10:00:00 GMT";
+    const ETAG: &str = "\"abc\"";
+
+    fn state(last_modified: Option<&str>, etag: Option<&str>) -> SourceState {
+        SourceState {
+            last_modified: last_modified.map(Into::into),
+            etag: etag.map(Into::into),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("roto-api-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    /// Serves a file with fixed validators, like the RIR FTP servers do.
+    ///
+    /// Requests that carry the validators get a 304, everything else the
+    /// file. With a delay, every response is held back that long. Returns
+    /// the address and the number of 304 responses sent.
+    async fn serve(delay: Option<Duration>) -> (SocketAddr, Arc<AtomicUsize>) {
+        let not_modified = Arc::new(AtomicUsize::new(0));
+        let count = not_modified.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let count = count.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let count = count.clone();
+                    async move {
+                        if let Some(delay) = delay {
+                            tokio::time::sleep(delay).await;
+                        }
+                        let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+                        let resp = if header(header::IF_MODIFIED_SINCE) == Some(LAST_MODIFIED)
+                            && header(header::IF_NONE_MATCH) == Some(ETAG)
+                        {
+                            count.fetch_add(1, Ordering::SeqCst);
+                            Response::builder().status(StatusCode::NOT_MODIFIED).body(Body::empty())
+                        } else {
+                            Response::builder()
+                                .header(header::LAST_MODIFIED, LAST_MODIFIED)
+                                .header(header::ETAG, ETAG)
+                                .body(Body::from("riswhois"))
+                        };
+                        Ok::<_, Infallible>(resp.unwrap())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, not_modified)
+    }
+
+    #[test]
+    fn later_last_modified_supersedes() {
+        let cur = state(Some("Tue, 01 Aug 2023 10:00:00 GMT"), None);
+        assert!(cur.is_superseded_by(&state(Some("Wed, 02 Aug 2023 10:00:00 GMT"), None)));
+        assert!(!cur.is_superseded_by(&state(Some("Tue, 01 Aug 2023 10:00:00 GMT"), None)));
+    }
+
+    #[test]
+    fn earlier_last_modified_does_not_supersede() {
+        // ARIN moves Last-Modified backwards with a new ETag.
+        let cur = state(Some("Wed, 02 Aug 2023 10:00:00 GMT"), Some("\"b\""));
+        let next = state(Some("Tue, 01 Aug 2023 10:00:00 GMT"), Some("\"a\""));
+        assert!(!cur.is_superseded_by(&next));
+    }
+
+    #[test]
+    fn etag_is_the_fallback() {
+        let cur = state(None, Some("\"a\""));
+        assert!(cur.is_superseded_by(&state(None, Some("\"b\""))));
+        assert!(!cur.is_superseded_by(&state(None, Some("\"a\""))));
+        assert!(cur.is_superseded_by(&state(None, None)));
+        assert!(SourceState::default().is_superseded_by(&state(None, Some("\"a\""))));
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let cur = state(Some(LAST_MODIFIED), Some(ETAG));
+        let parsed = SourceState::parse(&cur.to_string());
+        assert_eq!(parsed.last_modified.as_deref(), Some(LAST_MODIFIED));
+        assert_eq!(parsed.etag.as_deref(), Some(ETAG));
+    }
+
+    #[tokio::test]
+    async fn conditional_get() {
+        let (addr, not_modified) = serve(None).await;
+        let dir = temp_dir("conditional-get");
+        let downloader = Downloader::new(DataDir::new(&dir), DEFAULT_FETCH_TIMEOUT);
+        let source = Source::new("riswhois4", format!("http://{}/riswhois", addr), SourceKind::RisWhois);
+
+        // The first download is unconditional and stores the file.
+        assert!(downloader.update(&source).await.unwrap());
+        let data_path = downloader.data_dir().data_path(&source);
+        assert_eq!(fs::read(&data_path).unwrap(), b"riswhois");
+        let stored = downloader.data_dir().state(&source);
+        assert_eq!(stored.last_modified.as_deref(), Some(LAST_MODIFIED));
+        assert_eq!(stored.etag.as_deref(), Some(ETAG));
+        let modified = fs::metadata(&data_path).unwrap().modified().unwrap();
+        assert_eq!(Some(modified), stored.last_modified().map(Into::into));
+
+        // The second one sends the validators and gets a 304.
+        fs::write(&data_path, b"unchanged").unwrap();
+        assert!(!downloader.update(&source).await.unwrap());
+        assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+        assert_eq!(fs::read(&data_path).unwrap(), b"unchanged");
+
+        // Without a data file, the state is ignored.
+        fs::remove_file(&data_path).unwrap();
+        assert!(downloader.update(&source).await.unwrap());
+        assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+        assert_eq!(fs::read(&data_path).unwrap(), b"riswhois");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stalled_fetch_times_out() {
+        let (addr, _) = serve(Some(Duration::from_secs(10))).await;
+        let dir = temp_dir("timeout");
+        let downloader = Downloader::new(DataDir::new(&dir), Duration::from_millis(100));
+        let source = Source::new("riswhois4", format!("http://{}/riswhois", addr), SourceKind::RisWhois);
+
+        let err = downloader.update(&source).await.unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        assert!(!downloader.data_dir().data_path(&source).exists());
+    }
+}
@@ -0,0 +1,219 @@
+//! Dated snapshots of past stores.
+//!
+//! Every time the store is loaded from the data files, a snapshot of it is
+//! kept in the history directory, named after the time it was loaded, e.g.
+//! `20240501T120000Z.snapshot`. Only the latest snapshots are kept. A
+//! snapshot is in effect from the time it was loaded until the next one.
+
+use crate::Store;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FILE_NAME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const FILE_NAME_SUFFIX: &str = ".snapshot";
+
+/// A kept snapshot with the time its store was loaded.
+type Entry = (DateTime<Utc>, PathBuf);
+
+/// How many snapshots are kept by default.
+pub const DEFAULT_KEEP: usize = 30;
+
+//------------ History -------------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub struct History {
+    dir: PathBuf,
+
+    /// The number of snapshots to keep.
+    keep: usize,
+}
+
+impl History {
+    pub fn new(dir: impl Into<PathBuf>, keep: usize) -> Self {
+        History {
+            dir: dir.into(),
+            keep,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Keeps a snapshot of a store and removes the oldest ones beyond
+    /// the number to keep.
+    pub fn save(&self, store: &Store) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.dir)?;
+        store.save_snapshot(&self.path(store.updated()))?;
+        let snapshots = self.snapshots()?;
+        let excess = snapshots.len().saturating_sub(self.keep);
+        for (_, path) in &snapshots[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the times of the snapshots that are kept, oldest first.
+    pub fn dates(&self) -> Vec<DateTime<Utc>> {
+        self.snapshots()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(time, _)| time)
+            .collect()
+    }
+
+    /// Returns the snapshot in effect at `at`, if any.
+    pub fn find(&self, at: DateTime<Utc>) -> Result<Option<Entry>, Box<dyn Error>> {
+        Ok(self.snapshots()?.into_iter().rev().find(|(time, _)| *time <= at))
+    }
+
+    fn path(&self, time: DateTime<Utc>) -> PathBuf {
+        self.dir
+            .join(format!("{}{}", time.format(FILE_NAME_FORMAT), FILE_NAME_SUFFIX))
+    }
+
+    /// Returns the snapshots in the directory, oldest first.
+    ///
+    /// Other files in the directory are ignored.
+    fn snapshots(&self) -> Result<Vec<Entry>, Box<dyn Error>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let time = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(FILE_NAME_SUFFIX))
+                .and_then(|name| NaiveDateTime::parse_from_str(name, FILE_NAME_FORMAT).ok());
+            if let Some(time) = time {
+                snapshots.push((Utc.from_utc_datetime(&time), path));
+            }
+        }
+        snapshots.sort();
+        Ok(snapshots)
+    }
+}
+
+/// Parses the `at` of a query, either a date or an RFC 3339 time.
+///
+/// A date means the end of that day, UTC, so the last data loaded on that
+/// day is used.
+pub fn parse_at(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let end = date.and_hms_milli_opt(23, 59, 59, 999).ok_or("invalid date")?;
+        return Ok(Utc.from_utc_datetime(&end));
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| format!("cannot parse '{}', expected YYYY-MM-DD or an RFC 3339 time", s))
+}
+
+/// Parses the `since` of a changes query, either a serial or a date or time
+/// like `at`.
+///
+/// A serial is a UNIX timestamp, like the `serial` of the changes.
+pub fn parse_since(s: &str) -> Result<DateTime<Utc>, String> {
+    match s.parse::<i64>() {
+        Ok(serial) => Utc
+            .timestamp_opt(serial, 0)
+            .single()
+            .ok_or_else(|| format!("invalid serial {}", serial)),
+        Err(_) => parse_at(s).map_err(|_| {
+            format!(
+                "cannot parse '{}', expected a serial, YYYY-MM-DD or an RFC 3339 time",
+                s
+            )
+        }),
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("roto-api-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn save_at(history: &History, s: &str) {
+        let store = Store {
+            updated: time(s),
+            ..Default::default()
+        };
+        history.save(&store).unwrap();
+    }
+
+    #[test]
+    fn save_keeps_the_latest() {
+        let dir = temp_dir("save");
+        let history = History::new(&dir, 2);
+        assert!(history.dates().is_empty());
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("README"), "not a snapshot").unwrap();
+        save_at(&history, "2024-05-01T12:00:00Z");
+        save_at(&history, "2024-05-02T12:00:00Z");
+        assert!(dir.join("20240501T120000Z.snapshot").exists());
+        save_at(&history, "2024-05-03T12:00:00Z");
+        assert_eq!(
+            history.dates(),
+            [time("2024-05-02T12:00:00Z"), time("2024-05-03T12:00:00Z")]
+        );
+        assert!(!dir.join("20240501T120000Z.snapshot").exists());
+        assert!(dir.join("README").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn find() {
+        let dir = temp_dir("find");
+        let history = History::new(&dir, DEFAULT_KEEP);
+        assert_eq!(history.find(time("2024-05-01T12:00:00Z")).unwrap(), None);
+
+        save_at(&history, "2024-05-01T12:00:00Z");
+        save_at(&history, "2024-05-02T12:00:00Z");
+        let found = |s| history.find(time(s)).unwrap().map(|(time, _)| time);
+        assert_eq!(found("2024-05-01T11:59:59Z"), None);
+        assert_eq!(found("2024-05-01T12:00:00Z"), Some(time("2024-05-01T12:00:00Z")));
+        assert_eq!(found("2024-05-02T11:59:59Z"), Some(time("2024-05-01T12:00:00Z")));
+        assert_eq!(found("2024-06-01T00:00:00Z"), Some(time("2024-05-02T12:00:00Z")));
+
+        let (_, path) = history.find(time("2024-05-02T12:00:00Z")).unwrap().unwrap();
+        assert_eq!(Store::load_snapshot(&path).unwrap().updated(), time("2024-05-02T12:00:00Z"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_at_date_is_end_of_day() {
+        assert_eq!(parse_at("2024-05-01").unwrap(), time("2024-05-01T23:59:59.999Z"));
+        assert_eq!(parse_at("2024-05-01T12:00:00+02:00").unwrap(), time("2024-05-01T10:00:00Z"));
+        assert!(parse_at("2024-05-32").is_err());
+        assert!(parse_at("1714564800").is_err());
+        assert!(parse_at("yesterday").is_err());
+    }
+
+    #[test]
+    fn parse_since_serial_or_time() {
+        assert_eq!(parse_since("1714564800").unwrap(), time("2024-05-01T12:00:00Z"));
+        assert_eq!(parse_since("2024-05-01").unwrap(), time("2024-05-01T23:59:59.999Z"));
+        assert_eq!(parse_since("2024-05-01T12:00:00Z").unwrap(), time("2024-05-01T12:00:00Z"));
+        assert!(parse_since("99999999999999999").is_err());
+        assert!(parse_since("yesterday").is_err());
+    }
+}
@@ -0,0 +1,549 @@
+//! The IANA address and AS number registries.
+//!
+//! The special-purpose registries are built in rather than loaded from a
+//! file, since they rarely change. The tables follow the IPv4 and IPv6
+//! Special-Purpose Address Registries and the Special-Purpose AS Numbers
+//! registry, see RFC 6890 and RFC 7249.
+//!
+//! The top-level allocations of the IPv4 address space and IPv6 unicast
+//! address assignments registries are loaded from their CSV versions.
+
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::source::{PrefixRecord, SourceType};
+use crate::{Addr, Asn, JsonBuilder, PrefixMeta, Rir};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// The special-purpose address blocks.
+///
+/// Every row has the address block, the name, the RFCs, the allocation
+/// date and the Source, Destination, Forwardable, Globally Reachable and
+/// Reserved-by-Protocol flags as "T", "F" or "-" for N/A.
+const SPECIAL_PURPOSE_BLOCKS: &[(&str, &str, &str, &str, &str)] = &[
+    ("0.0.0.0/8", "This network", "RFC791", "1981-09", "TFFFT"),
+    ("0.0.0.0/32", "This host on this network", "RFC1122", "1981-09", "TFFFT"),
+    ("10.0.0.0/8", "Private-Use", "RFC1918", "1996-02", "TTTFF"),
+    ("100.64.0.0/10", "Shared Address Space", "RFC6598", "2012-04", "TTTFF"),
+    ("127.0.0.0/8", "Loopback", "RFC1122", "1981-09", "FFFFT"),
+    ("169.254.0.0/16", "Link Local", "RFC3927", "2005-05", "TTFFT"),
+    ("172.16.0.0/12", "Private-Use", "RFC1918", "1996-02", "TTTFF"),
+    ("192.0.0.0/24", "IETF Protocol Assignments", "RFC6890", "2010-01", "FFFFF"),
+    ("192.0.0.0/29", "IPv4 Service Continuity Prefix", "RFC7335", "2011-06", "TTTFF"),
+    ("192.0.0.8/32", "IPv4 dummy address", "RFC7600", "2015-03", "TFFFF"),
+    ("192.0.0.9/32", "Port Control Protocol Anycast", "RFC7723", "2015-10", "TTTTF"),
+    ("192.0.0.10/32", "Traversal Using Relays around NAT Anycast", "RFC8155", "2017-02", "TTTTF"),
+    ("192.0.0.170/32", "NAT64/DNS64 Discovery", "RFC8880,RFC7050", "2013-02", "FFFFT"),
+    ("192.0.0.171/32", "NAT64/DNS64 Discovery", "RFC8880,RFC7050", "2013-02", "FFFFT"),
+    ("192.0.2.0/24", "Documentation (TEST-NET-1)", "RFC5737", "2010-01", "FFFFF"),
+    ("192.31.196.0/24", "AS112-v4", "RFC7535", "2014-12", "TTTTF"),
+    ("192.52.193.0/24", "AMT", "RFC7450", "2014-12", "TTTTF"),
+    ("192.88.99.0/24", "Deprecated (6to4 Relay Anycast)", "RFC7526", "2001-06", "-----"),
+    ("192.168.0.0/16", "Private-Use", "RFC1918", "1996-02", "TTTFF"),
+    ("192.175.48.0/24", "Direct Delegation AS112 Service", "RFC7534", "1996-01", "TTTTF"),
+    ("198.18.0.0/15", "Benchmarking", "RFC2544", "1999-03", "TTTFF"),
+    ("198.51.100.0/24", "Documentation (TEST-NET-2)", "RFC5737", "2010-01", "FFFFF"),
+    ("203.0.113.0/24", "Documentation (TEST-NET-3)", "RFC5737", "2010-01", "FFFFF"),
+    ("240.0.0.0/4", "Reserved", "RFC1112", "1989-08", "FFFFT"),
+    ("255.255.255.255/32", "Limited Broadcast", "RFC8190,RFC919", "1984-10", "FTFFT"),
+    ("::1/128", "Loopback Address", "RFC4291", "2006-02", "FFFFT"),
+    ("::/128", "Unspecified Address", "RFC4291", "2006-02", "TFFFT"),
+    ("::ffff:0:0/96", "IPv4-mapped Address", "RFC4291", "2006-02", "FFFFT"),
+    ("64:ff9b::/96", "IPv4-IPv6 Translat.", "RFC6052", "2010-10", "TTTTF"),
+    ("64:ff9b:1::/48", "IPv4-IPv6 Translat.", "RFC8215", "2017-06", "TTTFF"),
+    ("100::/64", "Discard-Only Address Block", "RFC6666", "2012-06", "TTTFF"),
+    ("2001::/23", "IETF Protocol Assignments", "RFC2928", "2000-09", "FFFFF"),
+    ("2001::/32", "TEREDO", "RFC4380,RFC8190", "2006-01", "TTT-F"),
+    ("2001:1::1/128", "Port Control Protocol Anycast", "RFC7723", "2015-10", "TTTTF"),
+    ("2001:1::2/128", "Traversal Using Relays around NAT Anycast", "RFC8155", "2017-02", "TTTTF"),
+    ("2001:2::/48", "Benchmarking", "RFC5180", "2008-04", "TTTFF"),
+    ("2001:3::/32", "AMT", "RFC7450", "2014-12", "TTTTF"),
+    ("2001:4:112::/48", "AS112-v6", "RFC7535", "2014-12", "TTTTF"),
+    ("2001:10::/28", "Deprecated (previously ORCHID)", "RFC4843", "2007-03", "-----"),
+    ("2001:20::/28", "ORCHIDv2", "RFC7343", "2014-07", "TTTTF"),
+    ("2001:db8::/32", "Documentation", "RFC3849", "2004-07", "FFFFF"),
+    ("2002::/16", "6to4", "RFC3056", "2001-02", "TTT-F"),
+    ("2620:4f:8000::/48", "Direct Delegation AS112 Service", "RFC7534", "2011-05", "TTTTF"),
+    ("fc00::/7", "Unique-Local", "RFC4193,RFC8190", "2005-10", "TTTFF"),
+    ("fe80::/10", "Link-Local Unicast", "RFC4291", "2006-02", "TTFFT"),
+];
+
+/// The special-purpose AS numbers.
+///
+/// Every row has the first and last ASN of the range, the reason and the
+/// RFCs.
+const SPECIAL_PURPOSE_ASNS: &[(u32, u32, &str, &str)] = &[
+    (0, 0, "Reserved", "RFC7607"),
+    (112, 112, "Used by the AS112 project to sink misdirected DNS queries", "RFC7534"),
+    (23456, 23456, "AS_TRANS", "RFC6793"),
+    (64496, 64511, "For documentation and sample code", "RFC5398"),
+    (64512, 65534, "For private use", "RFC6996"),
+    (65535, 65535, "Reserved", "RFC7300"),
+    (65536, 65551, "For documentation and sample code", "RFC5398"),
+    (4200000000, 4294967294, "For private use", "RFC6996"),
+    (4294967295, 4294967295, "Reserved", "RFC7300"),
+];
+
+//------------ SpecialPurposeRecord ------------------------------------------
+
+/// An entry of the IPv4 or IPv6 special-purpose address registry.
+///
+/// The flags are `None` where the registry has "N/A".
+#[derive(Clone, Debug)]
+pub struct SpecialPurposeRecord {
+    pub name: &'static str,
+    pub rfcs: Vec<&'static str>,
+    pub allocated: &'static str,
+    pub source: Option<bool>,
+    pub destination: Option<bool>,
+    pub forwardable: Option<bool>,
+    pub globally_reachable: Option<bool>,
+    pub reserved_by_protocol: Option<bool>,
+}
+
+impl PrefixRecord for SpecialPurposeRecord {
+    const SOURCE_TYPE: SourceType = SourceType::IanaSpecial;
+
+    fn merge(&mut self, other: Self) {
+        *self = other;
+    }
+
+    fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("purpose", self.name);
+        builder.member_array("rfcs", |builder| {
+            for rfc in &self.rfcs {
+                builder.array_str(rfc);
+            }
+        });
+        builder.member_str("allocationDate", self.allocated);
+        let flags = [
+            ("source", self.source),
+            ("destination", self.destination),
+            ("forwardable", self.forwardable),
+            ("globallyReachable", self.globally_reachable),
+            ("reservedByProtocol", self.reserved_by_protocol),
+        ];
+        for (name, flag) in flags {
+            match flag {
+                Some(flag) => builder.member_raw(name, flag),
+                None => builder.member_raw(name, "null"),
+            }
+        }
+    }
+
+    fn into_meta(self) -> PrefixMeta {
+        PrefixMeta::SpecialPurpose(self)
+    }
+
+    fn from_meta(meta: &PrefixMeta) -> Option<&Self> {
+        match meta {
+            PrefixMeta::SpecialPurpose(rec) => Some(rec),
+            _ => None,
+        }
+    }
+}
+
+/// Returns all special-purpose address blocks with their record.
+#[allow(clippy::type_complexity)]
+pub(crate) fn special_purpose_blocks() -> Result<Vec<(Addr, u8, SpecialPurposeRecord)>, Box<dyn Error>> {
+    let flag = |flags: &str, idx: usize| match flags.as_bytes()[idx] {
+        b'T' => Some(true),
+        b'F' => Some(false),
+        _ => None,
+    };
+    SPECIAL_PURPOSE_BLOCKS
+        .iter()
+        .map(|&(prefix, name, rfcs, allocated, flags)| {
+            let (net, len) = prefix.split_once('/').ok_or("missing prefix length")?;
+            let rec = SpecialPurposeRecord {
+                name,
+                rfcs: rfcs.split(',').collect(),
+                allocated,
+                source: flag(flags, 0),
+                destination: flag(flags, 1),
+                forwardable: flag(flags, 2),
+                globally_reachable: flag(flags, 3),
+                reserved_by_protocol: flag(flags, 4),
+            };
+            Ok((Addr::from_str(net)?, u8::from_str(len)?, rec))
+        })
+        .collect()
+}
+
+//------------ SpecialPurposeAsn ---------------------------------------------
+
+/// An entry of the special-purpose AS numbers registry.
+#[derive(Clone, Copy, Debug)]
+pub struct SpecialPurposeAsn {
+    pub first: Asn,
+    pub last: Asn,
+    pub reason: &'static str,
+    pub rfc: &'static str,
+}
+
+impl SpecialPurposeAsn {
+    /// Returns the special-purpose range an ASN falls into, if any.
+    pub fn find(asn: Asn) -> Option<Self> {
+        SPECIAL_PURPOSE_ASNS
+            .iter()
+            .find(|&&(first, last, _, _)| (first..=last).contains(&asn.0))
+            .map(|&(first, last, reason, rfc)| SpecialPurposeAsn {
+                first: Asn(first),
+                last: Asn(last),
+                reason,
+                rfc,
+            })
+    }
+
+    /// Returns whether the ASNs are for private use.
+    pub fn is_private(&self) -> bool {
+        self.reason == "For private use"
+    }
+
+    /// Returns whether the ASNs are reserved and must not be used.
+    pub fn is_reserved(&self) -> bool {
+        self.reason == "Reserved"
+    }
+
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("sourceType", "iana-special");
+        builder.member_str("sourceID", "iana");
+        builder.member_str("purpose", self.reason);
+        builder.member_array("rfcs", |builder| builder.array_str(self.rfc));
+        builder.member_raw("private", self.is_private());
+        builder.member_raw("reserved", self.is_reserved());
+        builder.member_str("firstASN", self.first);
+        builder.member_str("lastASN", self.last);
+    }
+}
+
+//------------ IanaStatus ----------------------------------------------------
+
+/// The status of a block in the IANA address space registries.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IanaStatus {
+    /// Allocated to a RIR, or by IANA for a special purpose.
+    Allocated,
+
+    /// Assigned before the RIRs existed. Such blocks may be administered
+    /// by a RIR, but aren't delegated by it.
+    Legacy,
+
+    /// Reserved by IANA, e.g. for future use or multicast.
+    Reserved,
+}
+
+impl FromStr for IanaStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The status may carry a footnote, e.g. "RESERVED [2]".
+        match s.split_whitespace().next().unwrap_or_default().to_ascii_lowercase().as_str() {
+            "allocated" => Ok(IanaStatus::Allocated),
+            "legacy" => Ok(IanaStatus::Legacy),
+            "reserved" => Ok(IanaStatus::Reserved),
+            _ => Err(format!("unknown status {}", s)),
+        }
+    }
+}
+
+impl Snapshot for IanaStatus {
+    fn write(&self, w: &mut Writer) {
+        let tag: u8 = match self {
+            IanaStatus::Allocated => 0,
+            IanaStatus::Legacy => 1,
+            IanaStatus::Reserved => 2,
+        };
+        tag.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        match u8::read(r)? {
+            0 => Ok(IanaStatus::Allocated),
+            1 => Ok(IanaStatus::Legacy),
+            2 => Ok(IanaStatus::Reserved),
+            tag => Reader::bad_tag("IANA status", tag),
+        }
+    }
+}
+
+impl fmt::Display for IanaStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IanaStatus::Allocated => write!(f, "allocated"),
+            IanaStatus::Legacy => write!(f, "legacy"),
+            IanaStatus::Reserved => write!(f, "reserved"),
+        }
+    }
+}
+
+//------------ IanaAllocationRecord ------------------------------------------
+
+/// An entry of the IANA IPv4 address space or IPv6 unicast address
+/// assignments registry.
+#[derive(Clone, Debug)]
+pub struct IanaAllocationRecord {
+    /// Who the block is designated to, e.g. "RIPE NCC" or "Administered
+    /// by ARIN".
+    pub designation: String,
+    pub date: String,
+    pub whois: Option<String>,
+    pub status: IanaStatus,
+
+    /// The RIR responsible for the block, if any.
+    pub rir: Option<Rir>,
+}
+
+impl IanaAllocationRecord {
+    /// Parses a record of the CSV version of either registry.
+    ///
+    /// Both have the Prefix, Designation, Date, WHOIS, RDAP, Status and
+    /// Note columns. IPv4 blocks are written as "003/8".
+    pub(crate) fn from_csv_record(record: &csv::StringRecord) -> Result<(Addr, u8, Self), Box<dyn Error>> {
+        let field = |idx| record.get(idx).map(str::trim).ok_or("missing field");
+        let (net, len) = field(0)?.split_once('/').ok_or("missing prefix length")?;
+        let net = if net.contains(':') || net.contains('.') {
+            Addr::from_str(net)?
+        } else {
+            Addr::V4(u32::from(u8::from_str(net)?) << 24)
+        };
+        let whois = match field(3)? {
+            "" => None,
+            whois => Some(whois.to_string()),
+        };
+        let rir = whois.as_deref().and_then(|whois| match whois {
+            "whois.afrinic.net" => Some(Rir::Afrinic),
+            "whois.apnic.net" => Some(Rir::Apnic),
+            "whois.arin.net" => Some(Rir::Arin),
+            "whois.lacnic.net" => Some(Rir::Lacnic),
+            "whois.ripe.net" => Some(Rir::RipeNcc),
+            _ => None,
+        });
+        let rec = IanaAllocationRecord {
+            designation: field(1)?.to_string(),
+            date: field(2)?.to_string(),
+            whois,
+            status: IanaStatus::from_str(field(5)?)?,
+            rir,
+        };
+        Ok((net, u8::from_str(len)?, rec))
+    }
+}
+
+impl PrefixRecord for IanaAllocationRecord {
+    const SOURCE_TYPE: SourceType = SourceType::IanaAlloc;
+
+    fn merge(&mut self, other: Self) {
+        *self = other;
+    }
+
+    fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("designation", &self.designation);
+        builder.member_str("date", &self.date);
+        builder.member_str("status", self.status);
+        match self.rir {
+            Some(rir) => builder.member_str("rir", rir.to_json_id()),
+            None => builder.member_raw("rir", "null"),
+        }
+        match &self.whois {
+            Some(whois) => builder.member_str("whois", whois),
+            None => builder.member_raw("whois", "null"),
+        }
+    }
+
+    fn into_meta(self) -> PrefixMeta {
+        PrefixMeta::IanaAllocation(self)
+    }
+
+    fn from_meta(meta: &PrefixMeta) -> Option<&Self> {
+        match meta {
+            PrefixMeta::IanaAllocation(rec) => Some(rec),
+            _ => None,
+        }
+    }
+}
+
+impl Snapshot for IanaAllocationRecord {
+    fn write(&self, w: &mut Writer) {
+        self.designation.write(w);
+        self.date.write(w);
+        self.whois.write(w);
+        self.status.write(w);
+        self.rir.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(IanaAllocationRecord {
+            designation: Snapshot::read(r)?,
+            date: Snapshot::read(r)?,
+            whois: Snapshot::read(r)?,
+            status: Snapshot::read(r)?,
+            rir: Snapshot::read(r)?,
+        })
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Prefix;
+
+    fn addr(s: &str) -> Addr {
+        Addr::from_str(s).unwrap()
+    }
+
+    fn flags(rec: &SpecialPurposeRecord) -> [Option<bool>; 5] {
+        [
+            rec.source,
+            rec.destination,
+            rec.forwardable,
+            rec.globally_reachable,
+            rec.reserved_by_protocol,
+        ]
+    }
+
+    #[test]
+    fn special_purpose_blocks_parse() {
+        let blocks = special_purpose_blocks().unwrap();
+        assert_eq!(blocks.len(), SPECIAL_PURPOSE_BLOCKS.len());
+        for (net, len, _) in &blocks {
+            let (first, _) = Prefix::new(*net, *len).range();
+            assert_eq!(first, *net, "{:?}/{} has host bits set", net, len);
+        }
+
+        let find = |net: &str, len| {
+            blocks
+                .iter()
+                .find(|(n, l, _)| *n == addr(net) && *l == len)
+                .map(|(_, _, rec)| rec)
+                .unwrap()
+        };
+        let private = find("10.0.0.0", 8);
+        assert_eq!(private.name, "Private-Use");
+        assert_eq!(private.rfcs, ["RFC1918"]);
+        assert_eq!(private.allocated, "1996-02");
+        assert_eq!(flags(private), [Some(true), Some(true), Some(true), Some(false), Some(false)]);
+
+        let nat64 = find("192.0.0.170", 32);
+        assert_eq!(nat64.rfcs, ["RFC8880", "RFC7050"]);
+        assert_eq!(flags(find("192.88.99.0", 24)), [None; 5]);
+        assert_eq!(
+            flags(find("2001::", 32)),
+            [Some(true), Some(true), Some(true), None, Some(false)]
+        );
+        assert_eq!(find("2001:db8::", 32).name, "Documentation");
+    }
+
+    #[test]
+    fn special_purpose_asns() {
+        let reason = |asn| SpecialPurposeAsn::find(Asn(asn)).map(|special| special.reason);
+        assert_eq!(reason(0), Some("Reserved"));
+        assert_eq!(reason(112), Some("Used by the AS112 project to sink misdirected DNS queries"));
+        assert_eq!(reason(23456), Some("AS_TRANS"));
+        assert_eq!(reason(64511), Some("For documentation and sample code"));
+        assert_eq!(reason(65551), Some("For documentation and sample code"));
+        assert_eq!(reason(3333), None);
+        assert_eq!(reason(65552), None);
+        assert_eq!(reason(4199999999), None);
+
+        let private = SpecialPurposeAsn::find(Asn(65000)).unwrap();
+        assert_eq!((private.first, private.last), (Asn(64512), Asn(65534)));
+        assert!(private.is_private() && !private.is_reserved());
+        assert!(SpecialPurposeAsn::find(Asn(4200000000)).unwrap().is_private());
+        let reserved = SpecialPurposeAsn::find(Asn(4294967295)).unwrap();
+        assert!(reserved.is_reserved() && !reserved.is_private());
+    }
+
+    /// Parses the records of a CSV registry like the store loads them.
+    fn parse(data: &str) -> Vec<Result<(Addr, u8, IanaAllocationRecord), String>> {
+        csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(data.as_bytes())
+            .records()
+            .map(|record| {
+                IanaAllocationRecord::from_csv_record(&record.unwrap()).map_err(|err| err.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ipv4_address_space() {
+        let data = "\
+Prefix,Designation,Date,WHOIS,RDAP,Status [1],Note
+000/8,IANA - Local Identification,1981-09,,,RESERVED,[2]
+003/8,Administered by ARIN,1994-05,whois.arin.net,\"https://rdap.arin.net/registry
+http://rdap.arin.net/registry\",LEGACY,
+041/8,AFRINIC,2005-04,whois.afrinic.net,\"https://rdap.afrinic.net/rdap/
+http://rdap.afrinic.net/rdap/\",ALLOCATED,
+193/8,RIPE NCC,1993-05,whois.ripe.net,https://rdap.db.ripe.net/,ALLOCATED,
+224/8,Multicast,1981-09,,,RESERVED,[10]
+256/8,Nowhere,2024-01,,,RESERVED,
+010/8,IANA - Private Use,1995-06,,,UNKNOWN,
+";
+        let records = parse(data);
+        assert_eq!(records.len(), 7);
+        let ok = |idx: usize| records[idx].as_ref().unwrap();
+
+        let (net, len, rec) = ok(0);
+        assert_eq!((*net, *len), (addr("0.0.0.0"), 8));
+        assert_eq!(rec.designation, "IANA - Local Identification");
+        assert_eq!((rec.status, rec.rir, rec.whois.as_deref()), (IanaStatus::Reserved, None, None));
+
+        let (net, len, rec) = ok(1);
+        assert_eq!((*net, *len), (addr("3.0.0.0"), 8));
+        assert_eq!(rec.designation, "Administered by ARIN");
+        assert_eq!(rec.date, "1994-05");
+        assert_eq!(rec.whois.as_deref(), Some("whois.arin.net"));
+        assert_eq!((rec.status, rec.rir), (IanaStatus::Legacy, Some(Rir::Arin)));
+
+        let (net, _, rec) = ok(2);
+        assert_eq!(*net, addr("41.0.0.0"));
+        assert_eq!((rec.status, rec.rir), (IanaStatus::Allocated, Some(Rir::Afrinic)));
+
+        let (net, _, rec) = ok(3);
+        assert_eq!(*net, addr("193.0.0.0"));
+        assert_eq!((rec.status, rec.rir), (IanaStatus::Allocated, Some(Rir::RipeNcc)));
+
+        let (net, _, rec) = ok(4);
+        assert_eq!(*net, addr("224.0.0.0"));
+        assert_eq!((rec.status, rec.rir), (IanaStatus::Reserved, None));
+
+        assert!(records[5].is_err());
+        assert_eq!(records[6].as_ref().unwrap_err(), "unknown status UNKNOWN");
+    }
+
+    #[test]
+    fn ipv6_unicast_address_assignments() {
+        let data = "\
+Prefix,Designation,Date,WHOIS,RDAP,Status,Note
+2001:0000::/23,IANA,,whois.iana.org,,ALLOCATED,\"2001:0000::/23 is reserved for IETF Protocol Assignments [RFC2928].\"
+2001:0600::/23,RIPE NCC,1999-08-01,whois.ripe.net,https://rdap.db.ripe.net/,ALLOCATED,
+2c00:0000::/12,AFRINIC,2006-10-03,whois.afrinic.net,\"https://rdap.afrinic.net/rdap/
+http://rdap.afrinic.net/rdap/\",ALLOCATED,
+3ffe::/16,6bone,,,,RESERVED [5],Returned to IANA
+";
+        let records = parse(data);
+        assert_eq!(records.len(), 4);
+        let ok = |idx: usize| records[idx].as_ref().unwrap();
+
+        let (net, len, rec) = ok(0);
+        assert_eq!((*net, *len), (addr("2001::"), 23));
+        assert_eq!(rec.whois.as_deref(), Some("whois.iana.org"));
+        assert_eq!((rec.status, rec.rir), (IanaStatus::Allocated, None));
+
+        let (net, len, rec) = ok(1);
+        assert_eq!((*net, *len), (addr("2001:600::"), 23));
+        assert_eq!(rec.date, "1999-08-01");
+        assert_eq!(rec.rir, Some(Rir::RipeNcc));
+
+        let (net, len, rec) = ok(2);
+        assert_eq!((*net, *len), (addr("2c00::"), 12));
+        assert_eq!(rec.rir, Some(Rir::Afrinic));
+
+        let (net, len, rec) = ok(3);
+        assert_eq!((*net, *len), (addr("3ffe::"), 16));
+        assert_eq!((rec.status, rec.rir), (IanaStatus::Reserved, None));
+    }
+}
@@ -0,0 +1,261 @@
+//! Reading route objects from IRR databases.
+//!
+//! The databases are published as RPSL dumps, e.g. `ripe.db.route.gz` or
+//! the `radb.db.gz` export of RADb. Only the `route` and `route6` objects
+//! are used, and of those only the prefix, the `origin` and the `source`
+//! attributes. All other objects are skipped. See RFC 2622 and RFC 4012.
+
+use crate::{Addr, Asn};
+use std::error::Error;
+use std::io::BufRead;
+use std::str::FromStr;
+
+//------------ RouteObject ---------------------------------------------------
+
+/// A route or route6 object.
+#[derive(Clone, Debug)]
+pub struct RouteObject {
+    pub net: Addr,
+    pub len: u8,
+    pub origin: Asn,
+
+    /// The database the object is registered in, e.g. "RIPE" or "RADB".
+    pub source: String,
+}
+
+impl RouteObject {
+    /// Builds a route object from the attributes of an RPSL object.
+    ///
+    /// Returns `None` if the object is neither a route nor a route6
+    /// object.
+    fn from_attributes(attrs: &[(String, String)]) -> Option<Result<Self, String>> {
+        let (class, prefix) = attrs.first()?;
+        if class != "route" && class != "route6" {
+            return None;
+        }
+        Some(Self::parse(prefix, attrs))
+    }
+
+    fn parse(prefix: &str, attrs: &[(String, String)]) -> Result<Self, String> {
+        let attr = |name: &str| {
+            attrs
+                .iter()
+                .find(|(attr, _)| attr == name)
+                .map(|(_, value)| value.as_str())
+                .ok_or_else(|| format!("missing {} attribute", name))
+        };
+        let (net, len) = prefix.split_once('/').ok_or("missing prefix length")?;
+        let net = Addr::from_str(net).map_err(|err| err.to_string())?;
+        let len = u8::from_str(len).map_err(|err| err.to_string())?;
+        let origin = attr("origin")?;
+        let origin = match origin.get(..2) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("AS") => &origin[2..],
+            _ => return Err(format!("invalid origin {}", origin)),
+        };
+        let origin = Asn::from_str(origin).map_err(|err| err.to_string())?;
+        Ok(RouteObject {
+            net,
+            len,
+            origin,
+            source: attr("source")?.to_string(),
+        })
+    }
+}
+
+//------------ RpslReader ----------------------------------------------------
+
+/// Reads the route objects from an RPSL dump.
+///
+/// Objects are separated by empty lines. The dumps aren't necessarily
+/// UTF-8, e.g. the RIPE database is Latin-1, so invalid characters are
+/// replaced rather than rejected.
+pub struct RpslReader<R> {
+    reader: R,
+    line: u64,
+}
+
+impl<R: BufRead> RpslReader<R> {
+    pub fn new(reader: R) -> Self {
+        RpslReader { reader, line: 0 }
+    }
+
+    /// Returns the next route object, skipping other objects.
+    ///
+    /// Every object comes with the number of its first line. The outer
+    /// error is for the file itself, which can't be read any further. The
+    /// inner error is for a single object that was skipped.
+    #[allow(clippy::type_complexity)]
+    pub fn next_object(&mut self) -> Result<Option<(u64, Result<RouteObject, String>)>, Box<dyn Error>> {
+        loop {
+            let (start, attrs) = match self.read_attributes()? {
+                Some(object) => object,
+                None => return Ok(None),
+            };
+            if let Some(res) = RouteObject::from_attributes(&attrs) {
+                return Ok(Some((start, res)));
+            }
+        }
+    }
+
+    /// Reads the attributes of the next object, with the number of its
+    /// first line.
+    ///
+    /// Attribute names are lowercased. Continuation lines are joined to
+    /// their attribute and end-of-line comments are removed.
+    #[allow(clippy::type_complexity)]
+    fn read_attributes(&mut self) -> Result<Option<(u64, Vec<(String, String)>)>, Box<dyn Error>> {
+        let mut start = 0;
+        let mut attrs: Vec<(String, String)> = Vec::new();
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            if self.reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            self.line += 1;
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end();
+            if line.is_empty() {
+                if attrs.is_empty() {
+                    continue;
+                }
+                break;
+            }
+            if line.starts_with('%') || line.starts_with('#') {
+                continue;
+            }
+            let value = line.split('#').next().unwrap_or_default();
+            if value.starts_with([' ', '\t', '+']) {
+                if let Some((_, prev)) = attrs.last_mut() {
+                    prev.push(' ');
+                    prev.push_str(value[1..].trim());
+                }
+                continue;
+            }
+            if attrs.is_empty() {
+                start = self.line;
+            }
+            match value.split_once(':') {
+                Some((name, value)) => attrs.push((name.trim().to_ascii_lowercase(), value.trim().to_string())),
+                None => attrs.push((value.trim().to_ascii_lowercase(), String::new())),
+            }
+        }
+        if attrs.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some((start, attrs)))
+        }
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A few objects as the RIPE database dumps them, in Latin-1.
+    const DUMP: &[u8] = b"\
+% This is the RIPE Database dump.
+% The objects are in RPSL format.
+
+mntner:         RIPE-NCC-MNT
+descr:          RIPE NCC Maintainer
+source:         RIPE
+
+route:          193.0.0.0/21
+descr:          RIPE-NCC
+                Amsterdam, Caf\xe9 district
++               second line
+\tthird line
+origin:         AS3333 # the RIPE NCC
+# a comment between attributes
+mnt-by:         RIPE-NCC-MNT
+source:         RIPE
+
+
+
+route6:         2001:67c:2e8::/48
+Origin:         as3333
+source:         RIPE # Filtered
+
+route:          192.0.2.0/24
+descr:          no origin
+source:         RIPE
+
+route:          198.51.100.0/24
+origin:         AS64500
+source:         RADB";
+
+    fn objects() -> Vec<(u64, Result<RouteObject, String>)> {
+        let mut reader = RpslReader::new(DUMP);
+        let mut objects = Vec::new();
+        while let Some(object) = reader.next_object().unwrap() {
+            objects.push(object);
+        }
+        objects
+    }
+
+    #[test]
+    fn route_objects() {
+        let objects = objects();
+        assert_eq!(objects.len(), 4);
+
+        let (line, route) = &objects[0];
+        let route = route.as_ref().unwrap();
+        assert_eq!(*line, 8);
+        assert_eq!((route.net, route.len), (Addr::from_str("193.0.0.0").unwrap(), 21));
+        assert_eq!(route.origin, Asn::from(3333));
+        assert_eq!(route.source, "RIPE");
+
+        let (line, route6) = &objects[1];
+        let route6 = route6.as_ref().unwrap();
+        assert_eq!(*line, 20);
+        assert_eq!((route6.net, route6.len), (Addr::from_str("2001:67c:2e8::").unwrap(), 48));
+        assert_eq!(route6.origin, Asn::from(3333));
+        assert_eq!(route6.source, "RIPE");
+
+        let (line, route) = &objects[2];
+        assert_eq!(*line, 24);
+        assert_eq!(route.as_ref().unwrap_err(), "missing origin attribute");
+
+        // The last object doesn't end with a newline.
+        let (line, route) = &objects[3];
+        assert_eq!(*line, 28);
+        assert_eq!(route.as_ref().unwrap().source, "RADB");
+    }
+
+    #[test]
+    fn continuation_lines_and_comments() {
+        let mut reader = RpslReader::new(DUMP);
+        let (line, mntner) = reader.read_attributes().unwrap().unwrap();
+        assert_eq!(line, 4);
+        assert_eq!(mntner[0], ("mntner".to_string(), "RIPE-NCC-MNT".to_string()));
+
+        let (_, route) = reader.read_attributes().unwrap().unwrap();
+        let names: Vec<_> = route.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["route", "descr", "origin", "mnt-by", "source"]);
+        assert_eq!(route[1].1, "RIPE-NCC Amsterdam, Caf\u{fffd} district second line third line");
+        assert_eq!(route[2].1, "AS3333");
+
+        let (_, route6) = reader.read_attributes().unwrap().unwrap();
+        assert_eq!(route6[0], ("route6".to_string(), "2001:67c:2e8::/48".to_string()));
+        assert_eq!(route6[1], ("origin".to_string(), "as3333".to_string()));
+    }
+
+    #[test]
+    fn invalid_objects() {
+        let parse = |prefix: &str, origin: &str| {
+            let attrs = [
+                ("origin".to_string(), origin.to_string()),
+                ("source".to_string(), "RIPE".to_string()),
+            ];
+            RouteObject::parse(prefix, &attrs).map(|route| route.origin)
+        };
+        assert_eq!(parse("193.0.0.0/21", "AS3333"), Ok(Asn::from(3333)));
+        assert_eq!(parse("193.0.0.0/21", "3333"), Err("invalid origin 3333".to_string()));
+        assert!(parse("193.0.0.0/21", "ASx").is_err());
+        assert_eq!(parse("193.0.0.0", "AS3333"), Err("missing prefix length".to_string()));
+        assert!(parse("193.0.0.0/x", "AS3333").is_err());
+    }
+}
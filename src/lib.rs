@@ -1,15 +1,43 @@
 include!(concat!(env!("OUT_DIR"), "/version.rs"));
 
+pub mod asnames;
+pub mod asrel;
+pub mod bgp;
+pub mod bmp;
+pub mod changes;
+pub mod config;
+pub mod download;
+pub mod history;
+pub mod iana;
+pub mod irr;
+pub mod mrt;
+pub mod openpgp;
+pub mod rpki;
+pub mod snapshot;
+pub mod source;
+pub mod verify;
+
 use ansi_term::Colour;
-use chrono::{DateTime, Utc};
+use asnames::AsDirectory;
+use asrel::AsGraph;
+use bgp::{BgpPeer, BgpUpdate};
+use chrono::{DateTime, FixedOffset, NaiveDate, SubsecRound, Utc};
+use flate2::read::MultiGzDecoder;
+use iana::{IanaAllocationRecord, IanaStatus, SpecialPurposeRecord};
+use irr::RpslReader;
+use mrt::{is_table_dump_v2, RibEntry, TableDumpReader};
 use num::PrimInt;
 use rotonda_store::common::{AddressFamily, MergeUpdate, Prefix as RotondaPrefix};
 pub use rotonda_store::{
     InMemNodeId, InMemStorage, MatchOptions, MatchType, SizedStrideNode, TreeBitMap,
 };
+use rpki::VrpSet;
+use source::{PrefixRecord, SourceFilter, SourceId, SourceRegistry, SourceType};
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::Write;
 use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::Path;
 use std::str::FromStr;
@@ -17,7 +45,7 @@ use std::{fmt, slice};
 
 //------------ Addr ----------------------------------------------------------
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Addr {
     V4(u32),
     V6(u128),
@@ -45,7 +73,7 @@ impl From<IpAddr> for Addr {
 }
 impl From<u32> for Addr {
     fn from(addr: u32) -> Self {
-        addr.into()
+        Self::V4(addr)
     }
 }
 
@@ -68,7 +96,7 @@ impl fmt::Display for Addr {
 
 //------------ Prefix --------------------------------------------------------
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Prefix {
     pub addr: Addr,
     pub len: u8,
@@ -78,6 +106,20 @@ impl Prefix {
     pub fn new(addr: Addr, len: u8) -> Self {
         Prefix { addr, len }
     }
+
+    /// Returns the first and the last address of the prefix.
+    pub fn range(&self) -> (Addr, Addr) {
+        match self.addr {
+            Addr::V4(addr) => {
+                let host = u32::MAX.checked_shr(self.len.into()).unwrap_or(0);
+                (Addr::V4(addr & !host), Addr::V4(addr | host))
+            }
+            Addr::V6(addr) => {
+                let host = u128::MAX.checked_shr(self.len.into()).unwrap_or(0);
+                (Addr::V6(addr & !host), Addr::V6(addr | host))
+            }
+        }
+    }
 }
 
 impl fmt::Display for Prefix {
@@ -106,7 +148,7 @@ impl<'a> From<rotonda_store::QueryResult<'a, InMemStorage<u32, ExtPrefixRecord>>
         match result.prefix {
             Some(prefix) => match prefix.net.into_ipaddr() {
                 std::net::IpAddr::V4(net) => {
-                    return QueryResult {
+                    QueryResult {
                         match_type: result.match_type,
                         prefix: result.prefix.map(|pfx| Prefix {
                             addr: Addr::from(net),
@@ -117,12 +159,12 @@ impl<'a> From<rotonda_store::QueryResult<'a, InMemStorage<u32, ExtPrefixRecord>>
                         } else {
                             None
                         },
-                        less_specifics: RecordSet::from(result.less_specifics.unwrap()),
-                        more_specifics: RecordSet::from(result.more_specifics.unwrap()),
+                        less_specifics: RecordSet::from(result.less_specifics),
+                        more_specifics: RecordSet::from(result.more_specifics),
                     }
                 }
                 std::net::IpAddr::V6(net) => {
-                    return QueryResult {
+                    QueryResult {
                         match_type: result.match_type,
                         prefix: result.prefix.map(|pfx| Prefix {
                             addr: Addr::from(net),
@@ -133,8 +175,8 @@ impl<'a> From<rotonda_store::QueryResult<'a, InMemStorage<u32, ExtPrefixRecord>>
                         } else {
                             None
                         },
-                        less_specifics: RecordSet::from(result.less_specifics.unwrap()),
-                        more_specifics: RecordSet::from(result.more_specifics.unwrap()),
+                        less_specifics: RecordSet::from(result.less_specifics),
+                        more_specifics: RecordSet::from(result.more_specifics),
                     }
                 }
             },
@@ -142,8 +184,8 @@ impl<'a> From<rotonda_store::QueryResult<'a, InMemStorage<u32, ExtPrefixRecord>>
                 match_type: MatchType::EmptyMatch,
                 prefix: None,
                 prefix_meta: None,
-                less_specifics: RecordSet::from(result.less_specifics.unwrap()),
-                more_specifics: RecordSet::from(result.more_specifics.unwrap()),
+                less_specifics: RecordSet::from(result.less_specifics),
+                more_specifics: RecordSet::from(result.more_specifics),
             },
         }
     }
@@ -158,7 +200,7 @@ impl<'a> From<rotonda_store::QueryResult<'a, InMemStorage<u128, ExtPrefixRecord>
         match result.prefix {
             Some(prefix) => match prefix.net.into_ipaddr() {
                 std::net::IpAddr::V4(net) => {
-                    return QueryResult {
+                    QueryResult {
                         match_type: result.match_type,
                         prefix: result.prefix.map(|pfx| Prefix {
                             addr: Addr::from(net),
@@ -169,12 +211,12 @@ impl<'a> From<rotonda_store::QueryResult<'a, InMemStorage<u128, ExtPrefixRecord>
                         } else {
                             None
                         },
-                        less_specifics: RecordSet::from(result.less_specifics.unwrap()),
-                        more_specifics: RecordSet::from(result.more_specifics.unwrap()),
+                        less_specifics: RecordSet::from(result.less_specifics),
+                        more_specifics: RecordSet::from(result.more_specifics),
                     }
                 }
                 std::net::IpAddr::V6(net) => {
-                    return QueryResult {
+                    QueryResult {
                         match_type: result.match_type,
                         prefix: result.prefix.map(|pfx| Prefix {
                             addr: Addr::from(net),
@@ -185,8 +227,8 @@ impl<'a> From<rotonda_store::QueryResult<'a, InMemStorage<u128, ExtPrefixRecord>
                         } else {
                             None
                         },
-                        less_specifics: RecordSet::from(result.less_specifics.unwrap()),
-                        more_specifics: RecordSet::from(result.more_specifics.unwrap()),
+                        less_specifics: RecordSet::from(result.less_specifics),
+                        more_specifics: RecordSet::from(result.more_specifics),
                     }
                 }
             },
@@ -194,28 +236,28 @@ impl<'a> From<rotonda_store::QueryResult<'a, InMemStorage<u128, ExtPrefixRecord>
                 match_type: MatchType::EmptyMatch,
                 prefix: None,
                 prefix_meta: None,
-                less_specifics: RecordSet::from(result.less_specifics.unwrap()),
-                more_specifics: RecordSet::from(result.more_specifics.unwrap()),
+                less_specifics: RecordSet::from(result.less_specifics),
+                more_specifics: RecordSet::from(result.more_specifics),
             },
         }
     }
 }
 
+// Prefixes withdrawn by all their sources are left out. All less- and
+// more-specifics of queries come through here, so they never list them.
 impl<'a> From<Option<Vec<&'a RotondaPrefix<u32, ExtPrefixRecord>>>> for RecordSet<'a> {
     fn from(result: Option<Vec<&'a RotondaPrefix<u32, ExtPrefixRecord>>>) -> Self {
-        RecordSet {
-            v4: result.unwrap(),
-            v6: Vec::new(),
-        }
+        let mut v4 = result.unwrap_or_default();
+        v4.retain(|p| !p.meta.as_ref().is_some_and(ExtPrefixRecord::is_empty));
+        RecordSet { v4, v6: Vec::new() }
     }
 }
 
 impl<'a> From<Option<Vec<&'a RotondaPrefix<u128, ExtPrefixRecord>>>> for RecordSet<'a> {
     fn from(result: Option<Vec<&'a RotondaPrefix<u128, ExtPrefixRecord>>>) -> Self {
-        RecordSet {
-            v6: result.unwrap(),
-            v4: Vec::new(),
-        }
+        let mut v6 = result.unwrap_or_default();
+        v6.retain(|p| !p.meta.as_ref().is_some_and(ExtPrefixRecord::is_empty));
+        RecordSet { v4: Vec::new(), v6 }
     }
 }
 
@@ -242,7 +284,7 @@ pub struct SearchByAsnOptions {
 
 //------------ RecordSet -----------------------------------------------------
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct RecordSet<'a> {
     v4: Vec<&'a RotondaPrefix<u32, ExtPrefixRecord>>,
     v6: Vec<&'a RotondaPrefix<u128, ExtPrefixRecord>>,
@@ -253,7 +295,7 @@ impl<'a> RecordSet<'a> {
         self.v4.is_empty() && self.v6.is_empty()
     }
 
-    pub fn iter(&self) -> RecordSetIter {
+    pub fn iter(&self) -> RecordSetIter<'_, 'a> {
         RecordSetIter {
             v4: if self.v4.is_empty() {
                 None
@@ -269,6 +311,16 @@ impl<'a> RecordSet<'a> {
         self.v6.reverse();
         self
     }
+
+    /// Returns the last record of the set.
+    fn last(&self) -> Option<(Prefix, Option<&'a ExtPrefixRecord>)> {
+        if let Some(res) = self.v6.last() {
+            return Some((Prefix::new(Addr::V6(res.net), res.len), res.meta.as_ref()));
+        }
+        self.v4
+            .last()
+            .map(|res| (Prefix::new(Addr::V4(res.net), res.len), res.meta.as_ref()))
+    }
 }
 
 impl<'a> From<Vec<&'a RotondaPrefix<u32, ExtPrefixRecord>>> for RecordSet<'a> {
@@ -330,7 +382,7 @@ impl<'a, 'b> Iterator for RecordSetIter<'a, 'b> {
 
 //------------ Rir -----------------------------------------------------------
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Rir {
     Afrinic,
     Apnic,
@@ -340,7 +392,7 @@ pub enum Rir {
     Unknown,
 }
 
-impl<'a> From<&'a str> for Rir {
+impl From<&str> for Rir {
     fn from(str: &str) -> Self {
         match str {
             "afrinic" => Self::Afrinic,
@@ -361,12 +413,24 @@ impl Rir {
             Rir::Arin => "arin".to_string(),
             Rir::Lacnic => "lacnic".to_string(),
             Rir::RipeNcc => "ripe".to_string(),
-            Rir::Unknown => "riswhois".to_string(),
+            Rir::Unknown => "unknown".to_string(),
+        }
+    }
+
+    /// Returns the base URL of the RDAP service of the RIR.
+    pub fn rdap_url(self) -> Option<&'static str> {
+        match self {
+            Rir::Afrinic => Some("https://rdap.afrinic.net/rdap/"),
+            Rir::Apnic => Some("https://rdap.apnic.net/"),
+            Rir::Arin => Some("https://rdap.arin.net/registry/"),
+            Rir::Lacnic => Some("https://rdap.lacnic.net/rdap/"),
+            Rir::RipeNcc => Some("https://rdap.db.ripe.net/"),
+            Rir::Unknown => None,
         }
     }
 }
 
-impl<'a> fmt::Display for Rir {
+impl fmt::Display for Rir {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Rir::Afrinic => write!(f, "AFRINIC"),
@@ -381,42 +445,390 @@ impl<'a> fmt::Display for Rir {
 
 //------------ ExtPrefixRecord -----------------------------------------------
 
+/// What the sources have for a prefix.
+///
+/// The records are kept in the order of the variants of `PrefixMeta`, and
+/// in the order they were added within each variant, so the meta objects
+/// of a prefix come out grouped by kind.
 #[derive(Clone, Debug, Default)]
-pub struct ExtPrefixRecord(pub Option<RirDelExtRecord>, pub Option<RisWhoisRecord>);
+pub struct ExtPrefixRecord(Vec<SourceRecord>);
+
+impl ExtPrefixRecord {
+    /// Creates the record of a prefix with the record of a single source.
+    pub fn new(source: SourceId, rec: impl PrefixRecord) -> Self {
+        ExtPrefixRecord(vec![SourceRecord {
+            source,
+            meta: rec.into_meta(),
+        }])
+    }
+
+    /// Returns whether no source has anything for the prefix anymore.
+    ///
+    /// This happens when all live BGP sources withdrew the prefix. Such a
+    /// prefix is treated as if it wasn't in the store.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, SourceRecord> {
+        self.0.iter()
+    }
+
+    /// Returns the records of one kind, with their source.
+    pub fn records<'a, T: PrefixRecord + 'a>(&'a self) -> impl Iterator<Item = (SourceId, &'a T)> {
+        self.0
+            .iter()
+            .filter_map(|rec| T::from_meta(&rec.meta).map(|meta| (rec.source, meta)))
+    }
+
+    /// Returns the first record of one kind.
+    pub fn get<T: PrefixRecord>(&self) -> Option<&T> {
+        self.records().next().map(|(_, rec)| rec)
+    }
+
+    /// Returns whether a source has a record for the prefix.
+    pub fn has_source(&self, source: SourceId) -> bool {
+        self.0.iter().any(|rec| rec.source == source)
+    }
+
+    /// Removes the records of a source. Returns whether it had any.
+    pub fn remove_source(&mut self, source: SourceId) -> bool {
+        let len = self.0.len();
+        self.0.retain(|rec| rec.source != source);
+        self.0.len() != len
+    }
+
+    fn bgp_origins(&self) -> impl Iterator<Item = &Asn> {
+        self.0
+            .iter()
+            .filter(|rec| rec.meta.source_type() == SourceType::Bgp)
+            .flat_map(|rec| rec.meta.origin_asns())
+    }
+
+    /// Returns whether any of the BGP sources sees the prefix originated
+    /// by any of `asns`.
+    pub fn has_origin_asn(&self, asns: &[Asn]) -> bool {
+        self.bgp_origins().any(|origin| asns.contains(origin))
+    }
+
+    /// Returns the origin ASNs of all BGP sources, without duplicates.
+    pub fn origin_asns(&self) -> Vec<Asn> {
+        let mut origins: Vec<Asn> = self.bgp_origins().copied().collect();
+        origins.sort_unstable();
+        origins.dedup();
+        origins
+    }
+
+    /// Adds a meta object for every source of this record that `sources`
+    /// matches to a JSON array.
+    pub fn to_json_builder(
+        &self,
+        prefix: Prefix,
+        store: &Store,
+        sources: &SourceFilter,
+        builder: &mut JsonBuilder,
+    ) {
+        let unallocated = self.unallocated(prefix, store);
+        for rec in &self.0 {
+            let source = store.sources().get(rec.source);
+            if sources.matches(source.source_type, &source.id) {
+                builder.array_object(|builder| {
+                    store.source_record_to_json_builder(prefix, self, rec, unallocated, builder)
+                });
+            }
+        }
+    }
+
+    /// Returns whether `prefix` is unallocated if this record has BGP
+    /// sources, for which it is checked.
+    pub fn unallocated(&self, prefix: Prefix, store: &Store) -> Option<bool> {
+        let has_bgp = self.0.iter().any(|rec| rec.meta.source_type() == SourceType::Bgp);
+        if has_bgp {
+            store.is_unallocated(prefix)
+        } else {
+            None
+        }
+    }
+}
 
 impl MergeUpdate for ExtPrefixRecord {
     fn merge_update(
         &mut self,
         update_record: ExtPrefixRecord,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        if update_record.0.is_some() {
-            self.0 = update_record.0
-        }
-
-        if update_record.1.is_some() {
-            match &mut self.1 {
-                Some(ris_whois_rec) => {
-                    if let Some(update_ris_rec) = update_record.1 {
-                        ris_whois_rec
-                            .origin_asns
-                            .0
-                            .push(update_ris_rec.origin_asns.0[0]);
-                    }
-                }
+        for update in update_record.0 {
+            let kind = update.meta.kind();
+            let existing = self
+                .0
+                .iter_mut()
+                .find(|rec| rec.source == update.source && rec.meta.kind() == kind);
+            match existing {
+                Some(rec) => rec.meta.merge(update.meta),
                 None => {
-                    self.1 = update_record.1;
+                    let idx = self.0.partition_point(|rec| rec.meta.kind() <= kind);
+                    self.0.insert(idx, update);
                 }
             }
         }
 
+        // A live BGP record without paths is a withdrawal by its source.
+        self.0.retain(|rec| !rec.meta.is_empty());
         Ok(())
     }
 }
 
+//------------ SourceRecord --------------------------------------------------
+
+/// The record of a single source for a prefix.
+#[derive(Clone, Debug)]
+pub struct SourceRecord {
+    pub source: SourceId,
+    pub meta: PrefixMeta,
+}
+
+//------------ PrefixMeta ----------------------------------------------------
+
+/// The record of a source for a prefix, by kind.
+#[derive(Clone, Debug)]
+pub enum PrefixMeta {
+    Delegation(RirDelExtRecord),
+    RisWhois(RisWhoisRecord),
+    Rib(BgpRibRecord),
+    Irr(IrrRecord),
+    SpecialPurpose(SpecialPurposeRecord),
+    IanaAllocation(IanaAllocationRecord),
+}
+
+impl PrefixMeta {
+    /// Returns the position of the variant, which orders the records.
+    fn kind(&self) -> u8 {
+        match self {
+            PrefixMeta::Delegation(_) => 0,
+            PrefixMeta::RisWhois(_) => 1,
+            PrefixMeta::Rib(_) => 2,
+            PrefixMeta::Irr(_) => 3,
+            PrefixMeta::SpecialPurpose(_) => 4,
+            PrefixMeta::IanaAllocation(_) => 5,
+        }
+    }
+
+    pub fn source_type(&self) -> SourceType {
+        match self {
+            PrefixMeta::Delegation(_) => RirDelExtRecord::SOURCE_TYPE,
+            PrefixMeta::RisWhois(_) => RisWhoisRecord::SOURCE_TYPE,
+            PrefixMeta::Rib(_) => BgpRibRecord::SOURCE_TYPE,
+            PrefixMeta::Irr(_) => IrrRecord::SOURCE_TYPE,
+            PrefixMeta::SpecialPurpose(_) => SpecialPurposeRecord::SOURCE_TYPE,
+            PrefixMeta::IanaAllocation(_) => IanaAllocationRecord::SOURCE_TYPE,
+        }
+    }
+
+    /// Merges a record of the same kind.
+    fn merge(&mut self, other: PrefixMeta) {
+        match (self, other) {
+            (PrefixMeta::Delegation(rec), PrefixMeta::Delegation(other)) => rec.merge(other),
+            (PrefixMeta::RisWhois(rec), PrefixMeta::RisWhois(other)) => rec.merge(other),
+            (PrefixMeta::Rib(rec), PrefixMeta::Rib(other)) => rec.merge(other),
+            (PrefixMeta::Irr(rec), PrefixMeta::Irr(other)) => rec.merge(other),
+            (PrefixMeta::SpecialPurpose(rec), PrefixMeta::SpecialPurpose(other)) => rec.merge(other),
+            (PrefixMeta::IanaAllocation(rec), PrefixMeta::IanaAllocation(other)) => rec.merge(other),
+            (this, other) => *this = other,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            PrefixMeta::Delegation(rec) => rec.is_empty(),
+            PrefixMeta::RisWhois(rec) => rec.is_empty(),
+            PrefixMeta::Rib(rec) => rec.is_empty(),
+            PrefixMeta::Irr(rec) => rec.is_empty(),
+            PrefixMeta::SpecialPurpose(rec) => rec.is_empty(),
+            PrefixMeta::IanaAllocation(rec) => rec.is_empty(),
+        }
+    }
+
+    pub fn origin_asns(&self) -> &[Asn] {
+        match self {
+            PrefixMeta::Delegation(rec) => rec.origin_asns(),
+            PrefixMeta::RisWhois(rec) => rec.origin_asns(),
+            PrefixMeta::Rib(rec) => rec.origin_asns(),
+            PrefixMeta::Irr(rec) => rec.origin_asns(),
+            PrefixMeta::SpecialPurpose(rec) => rec.origin_asns(),
+            PrefixMeta::IanaAllocation(rec) => rec.origin_asns(),
+        }
+    }
+
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        match self {
+            PrefixMeta::Delegation(rec) => rec.to_json_builder(builder),
+            PrefixMeta::RisWhois(rec) => rec.to_json_builder(builder),
+            PrefixMeta::Rib(rec) => rec.to_json_builder(builder),
+            PrefixMeta::Irr(rec) => rec.to_json_builder(builder),
+            PrefixMeta::SpecialPurpose(rec) => rec.to_json_builder(builder),
+            PrefixMeta::IanaAllocation(rec) => rec.to_json_builder(builder),
+        }
+    }
+}
+
+//------------ RirDelExtRecord -----------------------------------------------
+
 #[derive(Clone, Debug)]
 pub struct RirDelExtRecord {
     group_id: String,
     pub rir: Rir,
+    pub country: Option<String>,
+    pub date: Option<NaiveDate>,
+    pub status: DelegationStatus,
+
+    /// The first and the last address of the delegation.
+    ///
+    /// An IPv4 delegation that isn't a single prefix is split into several
+    /// and this is the range of all of them. ASN delegations have none.
+    pub range: Option<(Addr, Addr)>,
+}
+
+impl RirDelExtRecord {
+    /// Returns the opaque-id of the delegation.
+    ///
+    /// The RIRs use the same opaque-id for all resources delegated to the
+    /// same organisation and keep it stable across files, so it can be used
+    /// as a handle for that organisation.
+    pub fn org_handle(&self) -> &str {
+        &self.group_id
+    }
+}
+
+impl PrefixRecord for RirDelExtRecord {
+    const SOURCE_TYPE: SourceType = SourceType::RirAlloc;
+
+    fn merge(&mut self, other: Self) {
+        *self = other;
+    }
+
+    fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("orgHandle", &self.group_id);
+        match &self.country {
+            Some(country) => builder.member_str("country", country),
+            None => builder.member_raw("country", "null"),
+        }
+        match &self.date {
+            Some(date) => builder.member_str("date", date.format("%Y-%m-%d")),
+            None => builder.member_raw("date", "null"),
+        }
+        builder.member_str("status", self.status);
+    }
+
+    fn into_meta(self) -> PrefixMeta {
+        PrefixMeta::Delegation(self)
+    }
+
+    fn from_meta(meta: &PrefixMeta) -> Option<&Self> {
+        match meta {
+            PrefixMeta::Delegation(rec) => Some(rec),
+            _ => None,
+        }
+    }
+}
+
+//------------ DelegationStatus ----------------------------------------------
+
+/// The status of a delegation in a delegated-extended file.
+///
+/// The files also have "reserved" and "available" records, but these
+/// aren't delegated, so they aren't loaded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DelegationStatus {
+    Allocated,
+    Assigned,
+}
+
+impl FromStr for DelegationStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allocated" => Ok(DelegationStatus::Allocated),
+            "assigned" => Ok(DelegationStatus::Assigned),
+            _ => Err(format!("unknown delegation status '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for DelegationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DelegationStatus::Allocated => write!(f, "allocated"),
+            DelegationStatus::Assigned => write!(f, "assigned"),
+        }
+    }
+}
+
+//------------ DelExtHeader --------------------------------------------------
+
+/// The version line of a delegated-extended file.
+///
+/// This is the first line that isn't a comment, e.g.
+/// `2.3|ripencc|1717196399|124503|19830705|20240531|+0200`.
+#[derive(Clone, Debug)]
+pub struct DelExtHeader {
+    pub version: String,
+
+    /// The registry that published the file, `nro` for the combined file.
+    pub registry: String,
+
+    /// The serial of the file. Its meaning differs between the RIRs, but it
+    /// increases with every file.
+    pub serial: u64,
+
+    /// The number of records in the file, without the version, summary and
+    /// comment lines.
+    pub records: u64,
+
+    /// The period the file covers, if the RIR fills it in.
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+
+    /// The offset of the local time of the RIR from UTC.
+    pub utc_offset: Option<FixedOffset>,
+}
+
+impl DelExtHeader {
+    /// Returns whether a record is the version line.
+    ///
+    /// Only the version line starts with a version number.
+    fn is_version_line(record: &csv::StringRecord) -> bool {
+        let version = record.get(0).unwrap_or_default();
+        !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+    }
+
+    fn from_record(record: &csv::StringRecord) -> Result<Self, Box<dyn Error>> {
+        let field = |idx| record.get(idx).unwrap_or_default();
+        let date = |idx| NaiveDate::parse_from_str(field(idx), "%Y%m%d").ok();
+        Ok(DelExtHeader {
+            version: field(0).to_string(),
+            registry: field(1).to_string(),
+            serial: u64::from_str(field(2)).map_err(|err| format!("invalid serial '{}': {}", field(2), err))?,
+            records: u64::from_str(field(3)).map_err(|err| format!("invalid record count '{}': {}", field(3), err))?,
+            start_date: date(4),
+            end_date: date(5),
+            utc_offset: parse_utc_offset(field(6)),
+        })
+    }
+}
+
+/// Parses the UTC offset of a version line, e.g. `+0200` or `-0400`.
+fn parse_utc_offset(s: &str) -> Option<FixedOffset> {
+    let (sign, digits) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours = i32::from_str(&digits[..2]).ok()?;
+    let minutes = i32::from_str(&digits[2..]).ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
 }
 
 // Not really used right now, since the
@@ -435,251 +847,1164 @@ impl fmt::Display for AsnArray {
         write!(f, "{}", arr_str)
     }
 }
+
 #[derive(Clone, Debug)]
 pub struct RisWhoisRecord {
     pub origin_asns: AsnArray,
+
+    /// The number of RIS peers that see the announcement by each of the
+    /// origin ASNs, in the same order as `origin_asns`.
+    ///
+    /// This is only known when loading from a riswhoisdump file.
+    pub seen_by_peers: Vec<Option<u32>>,
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct Asn(u32);
+impl PrefixRecord for RisWhoisRecord {
+    const SOURCE_TYPE: SourceType = SourceType::Bgp;
 
-impl fmt::Display for Asn {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "AS{}", self.0)
+    fn merge(&mut self, other: Self) {
+        self.origin_asns.0.extend(other.origin_asns.0);
+        self.seen_by_peers.extend(other.seen_by_peers);
     }
-}
 
-impl FromStr for Asn {
-    fn from_str(as_str: &str) -> std::result::Result<Asn, std::num::ParseIntError> {
-        as_str.parse::<u32>().map(Asn)
+    fn origin_asns(&self) -> &[Asn] {
+        &self.origin_asns.0
     }
 
-    type Err = std::num::ParseIntError;
-}
-
-// ----------- TimeStamp & TimeStamps ------------------------------------------------
+    fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_array("originASNs", |builder| {
+            for asn in self.origin_asns.0.iter() {
+                builder.array_str(asn)
+            }
+        });
+        if self.seen_by_peers.iter().any(Option::is_some) {
+            builder.member_array("seenByPeers", |builder| {
+                for peers in self.seen_by_peers.iter() {
+                    match peers {
+                        Some(peers) => builder.array_raw(peers),
+                        None => builder.array_raw("null"),
+                    }
+                }
+            });
+        }
+    }
 
-#[derive(Copy, Clone, Debug)]
-pub struct TimeStamp(pub Rir, pub u64, pub chrono::DateTime<chrono::FixedOffset>);
+    fn into_meta(self) -> PrefixMeta {
+        PrefixMeta::RisWhois(self)
+    }
 
-impl fmt::Display for TimeStamp {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "\"{}\": \"{} {}\"", self.0, self.1, self.2)
+    fn from_meta(meta: &PrefixMeta) -> Option<&Self> {
+        match meta {
+            PrefixMeta::RisWhois(rec) => Some(rec),
+            _ => None,
+        }
     }
 }
 
-#[allow(clippy::inherent_to_string_shadow_display)]
-impl TimeStamp {
-    pub fn to_string(self) -> String {
-        format!("{} {} {}", self.0, self.1, self.2)
-    }
+//------------ BgpRibRecord --------------------------------------------------
+
+/// The routes for a prefix in the RIB dumps of a single route collector,
+/// or the route of a single peer in a live BMP feed.
+///
+/// The source is the collector, e.g. "rrc00" or "route-views2", or the
+/// monitored peer, e.g. "bmp/192.0.2.1/198.51.100.7".
+#[derive(Clone, Debug)]
+pub struct BgpRibRecord {
+    pub origin_asns: AsnArray,
+
+    /// The distinct AS paths, each with the peers that announced it.
+    pub paths: Vec<BgpRibPath>,
+
+    /// Whether the record comes from a live feed rather than a dump.
+    pub live: bool,
 }
 
-#[derive(Copy, Clone, Debug, Default)]
-pub struct TimeStamps {
-    pub afrinic: Option<TimeStamp>,
-    pub apnic: Option<TimeStamp>,
-    pub arin: Option<TimeStamp>,
-    pub lacnic: Option<TimeStamp>,
-    pub ripencc: Option<TimeStamp>,
-    pub riswhois: Option<TimeStamp>,
+#[derive(Clone, Debug)]
+pub struct BgpRibPath {
+    pub as_path: Vec<Asn>,
+    pub peers: Vec<BgpPeer>,
 }
 
-impl TimeStamps {
-    pub fn push(&mut self, ts: TimeStamp) -> Result<(), Box<dyn std::error::Error>> {
-        match ts.0 {
-            Rir::Afrinic => {
-                self.afrinic = Some(ts);
-            }
-            Rir::Apnic => {
-                self.apnic = Some(ts);
-            }
-            Rir::Arin => {
-                self.arin = Some(ts);
+impl BgpRibRecord {
+    fn from_entry(entry: RibEntry) -> Self {
+        let mut rec = BgpRibRecord {
+            origin_asns: AsnArray(Vec::new()),
+            paths: Vec::new(),
+            live: false,
+        };
+        for route in entry.routes {
+            rec.add_path(route.as_path.asns, &route.as_path.origin_asns, &[route.peer]);
+        }
+        rec
+    }
+
+    fn add_path(&mut self, as_path: Vec<Asn>, origin_asns: &[Asn], peers: &[BgpPeer]) {
+        for asn in origin_asns {
+            if !self.origin_asns.0.contains(asn) {
+                self.origin_asns.0.push(*asn);
             }
-            Rir::Lacnic => {
-                self.lacnic = Some(ts);
+        }
+        let path = match self.paths.iter_mut().position(|path| path.as_path == as_path) {
+            Some(idx) => &mut self.paths[idx],
+            None => {
+                self.paths.push(BgpRibPath {
+                    as_path,
+                    peers: Vec::new(),
+                });
+                self.paths.last_mut().unwrap()
             }
-            Rir::RipeNcc => {
-                self.ripencc = Some(ts);
+        };
+        for peer in peers {
+            if !path.peers.contains(peer) {
+                path.peers.push(*peer);
             }
-            Rir::Unknown => {
-                self.riswhois = Some(ts);
+        }
+    }
+
+    fn merge_paths(&mut self, other: BgpRibRecord) {
+        for asn in &other.origin_asns.0 {
+            if !self.origin_asns.0.contains(asn) {
+                self.origin_asns.0.push(*asn);
             }
         }
-        Ok(())
+        for path in other.paths {
+            self.add_path(path.as_path, &[], &path.peers);
+        }
     }
 
-    pub fn to_json_builder(self, builder: &mut JsonBuilder) {
-        builder.member_array("sources", |builder| {
-            for rir in [
-                self.afrinic,
-                self.apnic,
-                self.arin,
-                self.lacnic,
-                self.ripencc,
-                self.riswhois,
-            ]
-            .iter()
-            .flatten()
-            {
-                builder.array_object(|builder| {
-                    // RisWhois dataset has Rir::Unknown set
-                    if let Rir::Unknown = rir.0 {
-                        builder.member_str("type", "bgp");
-                    } else {
-                        builder.member_str("type", "rir-alloc");
-                    }
-                    builder.member_str("id", rir.0.to_json_id());
-                    builder.member_raw("serial", rir.1);
-                    builder.member_str("lastUpdated", rir.2.format("%+"));
-                })
+    /// Returns the distinct peers of the collector that see the prefix.
+    pub fn peers(&self) -> Vec<BgpPeer> {
+        let mut peers: Vec<BgpPeer> = Vec::new();
+        for peer in self.paths.iter().flat_map(|path| path.peers.iter()) {
+            if !peers.contains(peer) {
+                peers.push(*peer);
             }
-        });
+        }
+        peers
     }
 }
 
-//------------ Store ---------------------------------------------------------
-
-pub struct Store {
-    v4: TreeBitMap<InMemStorage<u32, ExtPrefixRecord>>,
-    v6: TreeBitMap<InMemStorage<u128, ExtPrefixRecord>>,
-    updated: DateTime<Utc>,
-    pub timestamps: TimeStamps,
-}
+impl PrefixRecord for BgpRibRecord {
+    const SOURCE_TYPE: SourceType = SourceType::Bgp;
 
-impl Default for Store {
-    fn default() -> Self {
-        Self {
-            v4: TreeBitMap::new(vec![4]),
-            v6: TreeBitMap::new(vec![4]),
-            updated: Utc::now(),
-            timestamps: Default::default(),
+    /// Records from RIB dumps are added up, records from live feeds
+    /// replace the previous one of their source.
+    fn merge(&mut self, other: Self) {
+        if other.live {
+            *self = other;
+        } else {
+            self.merge_paths(other);
         }
     }
-}
 
-impl Store {
-    pub fn updated(&self) -> DateTime<Utc> {
-        self.updated
+    /// Returns whether the record is a withdrawal.
+    fn is_empty(&self) -> bool {
+        self.paths.is_empty()
     }
 
-    pub fn load_riswhois(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
-        let file = File::open(path)?;
-        let mut rdr = csv::Reader::from_reader(file);
-        for result in rdr.records() {
-            let record = result?;
-            let net = Addr::from_str(&record[0]).unwrap_or_else(|_| {
-                println!("Error parsing {}/{}", &record[0], &record[1]);
-                panic!("can't continue parsing")
-            });
-            let len = u8::from_str(&record[1]).unwrap_or_else(|_| {
-                println!("Error parsing {}/{}", &record[0], &record[1]);
-                panic!("can't continue parsing")
-            });
-            let asn: Asn = Asn::from_str(&record[2]).unwrap_or_else(|e| {
-                println!("{:?}", e);
-                println!(
-                    "Error parsing {}/{} with asn AS{}",
-                    &record[0], &record[1], &record[2]
-                );
-                panic!("can't continue parsing")
-            });
-            let meta = ExtPrefixRecord(
-                None,
-                Some(RisWhoisRecord {
-                    origin_asns: AsnArray(vec![asn]),
-                }),
-            );
-
-            match net {
-                Addr::V4(net) => self
-                    .v4
-                    .insert(RotondaPrefix::new_with_meta(net, len, meta))
-                    .unwrap_or_else(|_| {
-                        println!("Error parsing {} {}", net, len);
-                        panic!("can't continue parsing")
-                    }),
-                Addr::V6(net) => self
-                    .v6
-                    .insert(RotondaPrefix::new_with_meta(net, len, meta))
-                    .unwrap_or_else(|_| {
-                        println!("Error parsing {} {}", net, len);
-                        panic!("can't continue parsing")
-                    }),
+    fn origin_asns(&self) -> &[Asn] {
+        &self.origin_asns.0
+    }
+
+    fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_array("originASNs", |builder| {
+            for asn in self.origin_asns.0.iter() {
+                builder.array_str(asn)
             }
-        }
-        self.updated = Utc::now();
-        Ok(())
+        });
+        builder.member_array("peers", |builder| {
+            for peer in self.peers() {
+                builder.array_object(|builder| {
+                    builder.member_str("address", peer.addr);
+                    builder.member_str("asn", peer.asn);
+                });
+            }
+        });
+        builder.member_array("asPaths", |builder| {
+            for path in &self.paths {
+                builder.array_object(|builder| {
+                    builder.member_array("path", |builder| {
+                        for asn in &path.as_path {
+                            builder.array_str(asn);
+                        }
+                    });
+                    builder.member_array("peers", |builder| {
+                        for peer in &path.peers {
+                            builder.array_str(peer.addr);
+                        }
+                    });
+                });
+            }
+        });
     }
 
-    pub fn load_prefixes(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
-        let file = File::open(path)?;
-        let mut rdr = csv::ReaderBuilder::new()
-            .delimiter(b'|')
-            .flexible(true)
-            .trim(csv::Trim::Headers)
-            .from_reader(file);
+    fn into_meta(self) -> PrefixMeta {
+        PrefixMeta::Rib(self)
+    }
 
-        for record in rdr.records() {
-            let record = record?;
+    fn from_meta(meta: &PrefixMeta) -> Option<&Self> {
+        match meta {
+            PrefixMeta::Rib(rec) => Some(rec),
+            _ => None,
+        }
+    }
+}
 
-            if record[0].starts_with('#')
-                || &record[5] == "summary"
-                || &record[6] == "reserved"
-                || &record[6] == "available"
-            {
-                continue;
-            }
+//------------ IrrRecord -----------------------------------------------------
 
-            let group_id = match record.get(7) {
-                Some(id) => id.to_string(),
-                None => continue,
-            };
+/// The route objects for a prefix registered in a single IRR database.
+///
+/// The source is the database, lowercased, e.g. "ripe" or "radb".
+#[derive(Clone, Debug)]
+pub struct IrrRecord {
+    /// The origins of all route objects for the prefix in the database.
+    pub origin_asns: AsnArray,
+}
 
-            let meta = ExtPrefixRecord(
-                Some(RirDelExtRecord {
-                    group_id,
-                    rir: record[0].into(),
-                }),
-                None,
-            );
+impl PrefixRecord for IrrRecord {
+    const SOURCE_TYPE: SourceType = SourceType::Irr;
 
-            match &record[2] {
-                "ipv4" => {
-                    let net = Ipv4Addr::from_str(&record[3])?;
+    fn merge(&mut self, other: Self) {
+        for asn in other.origin_asns.0 {
+            if !self.origin_asns.0.contains(&asn) {
+                self.origin_asns.0.push(asn);
+            }
+        }
+    }
 
-                    // record[4] is the number of addresses in the allocation.
-                    // We assume that proper prefixes are allocated and then
-                    // can do this:
-                    let len_base = u32::from_str(&record[4])?;
-                    let len: u8 = (len_base.leading_zeros() + 1) as u8;
+    fn origin_asns(&self) -> &[Asn] {
+        &self.origin_asns.0
+    }
 
-                    self.v4
-                        .insert(RotondaPrefix::new_with_meta(net.into(), len, meta))?;
-                }
-                "ipv6" => {
-                    let net = Ipv6Addr::from_str(&record[3])?;
+    fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_array("originASNs", |builder| {
+            for asn in self.origin_asns.0.iter() {
+                builder.array_str(asn)
+            }
+        });
+    }
 
-                    // record[4] is just the prefix length here. No shenanigans
-                    // necessary.
-                    let len = u8::from_str(&record[4])?;
+    fn into_meta(self) -> PrefixMeta {
+        PrefixMeta::Irr(self)
+    }
 
-                    self.v6
-                        .insert(RotondaPrefix::new_with_meta(net.into(), len, meta))?;
-                }
-                _ => {}
-            }
+    fn from_meta(meta: &PrefixMeta) -> Option<&Self> {
+        match meta {
+            PrefixMeta::Irr(rec) => Some(rec),
+            _ => None,
         }
-        self.updated = Utc::now();
-        Ok(())
     }
+}
 
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Asn(u32);
+
+impl From<u32> for Asn {
+    fn from(asn: u32) -> Self {
+        Asn(asn)
+    }
+}
+
+impl From<Asn> for u32 {
+    fn from(asn: Asn) -> Self {
+        asn.0
+    }
+}
+
+impl fmt::Display for Asn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AS{}", self.0)
+    }
+}
+
+impl FromStr for Asn {
+    fn from_str(as_str: &str) -> std::result::Result<Asn, std::num::ParseIntError> {
+        as_str.parse::<u32>().map(Asn)
+    }
+
+    type Err = std::num::ParseIntError;
+}
+
+//------------ LoadMode ------------------------------------------------------
+
+/// How lines of data files that cannot be loaded are handled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LoadMode {
+    /// Loading the file fails on the first bad line.
+    Strict,
+
+    /// Bad lines are skipped and collected in the `LoadReport`.
+    Lenient,
+}
+
+//------------ LoadIssue -----------------------------------------------------
+
+/// A line of a data file that was skipped while loading.
+#[derive(Clone, Debug)]
+pub struct LoadIssue {
+    pub file: String,
+    pub line: u64,
+    pub reason: String,
+    pub raw: String,
+}
+
+impl LoadIssue {
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("file", &self.file);
+        builder.member_raw("line", self.line);
+        builder.member_str("reason", &self.reason);
+        builder.member_str("raw", &self.raw);
+    }
+}
+
+impl fmt::Display for LoadIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}: '{}'", self.file, self.line, self.reason, self.raw)
+    }
+}
+
+//------------ LoadReport ----------------------------------------------------
+
+/// The lines skipped while loading the data files of a store.
+#[derive(Clone, Debug, Default)]
+pub struct LoadReport {
+    issues: Vec<LoadIssue>,
+}
+
+impl LoadReport {
+    pub fn push(&mut self, issue: LoadIssue) {
+        self.issues.push(issue)
+    }
+
+    pub fn len(&self) -> usize {
+        self.issues.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, LoadIssue> {
+        self.issues.iter()
+    }
+
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_raw("skippedLines", self.issues.len());
+        builder.member_array("issues", |builder| {
+            for issue in &self.issues {
+                builder.array_object(|builder| issue.to_json_builder(builder));
+            }
+        });
+    }
+}
+
+//------------ AsnDelegation -------------------------------------------------
+
+/// A range of consecutive ASNs delegated by a RIR.
+#[derive(Clone, Debug)]
+pub struct AsnDelegation {
+    pub first: Asn,
+    pub last: Asn,
+    pub record: RirDelExtRecord,
+}
+
+impl AsnDelegation {
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("sourceType", RirDelExtRecord::SOURCE_TYPE);
+        builder.member_str("sourceID", self.record.rir.to_json_id());
+        self.record.to_json_builder(builder);
+        builder.member_str("firstASN", self.first);
+        builder.member_str("lastASN", self.last);
+    }
+}
+
+//------------ AsnRegistry ---------------------------------------------------
+
+/// The ASN delegations from the delegated-extended files.
+///
+/// Delegations are keyed by their first ASN. Since they don't overlap, the
+/// delegation an ASN falls into is the one with the highest first ASN not
+/// above it.
+#[derive(Clone, Debug, Default)]
+pub struct AsnRegistry {
+    delegations: BTreeMap<u32, AsnDelegation>,
+}
+
+impl AsnRegistry {
+    pub fn insert(
+        &mut self,
+        first: u32,
+        count: u32,
+        record: RirDelExtRecord,
+    ) -> Result<(), Box<dyn Error>> {
+        let last = count
+            .checked_sub(1)
+            .and_then(|n| first.checked_add(n))
+            .ok_or_else(|| format!("invalid ASN range AS{} with count {}", first, count))?;
+        self.delegations.insert(
+            first,
+            AsnDelegation {
+                first: Asn(first),
+                last: Asn(last),
+                record,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, asn: Asn) -> Option<&AsnDelegation> {
+        self.delegations
+            .range(..=asn.0)
+            .next_back()
+            .map(|(_, delegation)| delegation)
+            .filter(|delegation| delegation.last.0 >= asn.0)
+    }
+
+    /// Returns the delegations, ordered by their first ASN.
+    pub fn iter(&self) -> impl Iterator<Item = &AsnDelegation> {
+        self.delegations.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.delegations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.delegations.is_empty()
+    }
+}
+
+//------------ Store ---------------------------------------------------------
+
+pub struct Store {
+    v4: TreeBitMap<InMemStorage<u32, ExtPrefixRecord>>,
+    v6: TreeBitMap<InMemStorage<u128, ExtPrefixRecord>>,
+    asns: AsnRegistry,
+    as_names: AsDirectory,
+    as_graph: AsGraph,
+    vrps: VrpSet,
+
+    /// The number of route objects loaded from IRR databases.
+    irr_objects: usize,
+
+    /// Whether the IANA allocations were loaded for IPv4 and IPv6.
+    iana_v4: bool,
+    iana_v6: bool,
+
+    /// The RIRs whose delegations were loaded.
+    delegation_rirs: Vec<Rir>,
+    load_mode: LoadMode,
+    load_report: LoadReport,
+
+    /// The number of prefixes withdrawn by all their sources that are
+    /// still in the trees.
+    tombstones: usize,
+    updated: DateTime<Utc>,
+    sources: SourceRegistry,
+
+    /// The strides of the trees, kept to rebuild them.
+    strides_v4: Vec<u8>,
+    strides_v6: Vec<u8>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self {
+            v4: TreeBitMap::new(vec![4]),
+            v6: TreeBitMap::new(vec![4]),
+            asns: Default::default(),
+            as_names: Default::default(),
+            as_graph: Default::default(),
+            vrps: Default::default(),
+            irr_objects: 0,
+            iana_v4: false,
+            iana_v6: false,
+            delegation_rirs: Vec::new(),
+            load_mode: LoadMode::Strict,
+            load_report: Default::default(),
+            tombstones: 0,
+            updated: Utc::now(),
+            sources: Default::default(),
+            strides_v4: vec![4],
+            strides_v6: vec![4],
+        }
+    }
+}
+
+impl Store {
+    /// Creates an empty store with the given strides for the IPv4 and IPv6
+    /// trees.
+    pub fn with_strides(strides_v4: Vec<u8>, strides_v6: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        check_strides(&strides_v4, 32).map_err(|err| format!("IPv4 {}", err))?;
+        check_strides(&strides_v6, 128).map_err(|err| format!("IPv6 {}", err))?;
+        Ok(Store {
+            v4: TreeBitMap::new(strides_v4.clone()),
+            v6: TreeBitMap::new(strides_v6.clone()),
+            strides_v4,
+            strides_v6,
+            ..Default::default()
+        })
+    }
+
+    pub fn updated(&self) -> DateTime<Utc> {
+        self.updated
+    }
+
+    /// Sets how lines of data files that cannot be loaded are handled.
+    pub fn set_load_mode(&mut self, mode: LoadMode) {
+        self.load_mode = mode;
+    }
+
+    /// Returns the data sets that were loaded.
+    pub fn sources(&self) -> &SourceRegistry {
+        &self.sources
+    }
+
+    pub fn sources_mut(&mut self) -> &mut SourceRegistry {
+        &mut self.sources
+    }
+
+    /// Returns the names of the ASNs.
+    pub fn as_names(&self) -> &AsDirectory {
+        &self.as_names
+    }
+
+    /// Returns the relationships between ASes.
+    pub fn as_graph(&self) -> &AsGraph {
+        &self.as_graph
+    }
+
+    /// Returns the validated ROA payloads.
+    pub fn vrps(&self) -> &VrpSet {
+        &self.vrps
+    }
+
+    /// Adds the meta object of the record of a source for `prefix`.
+    ///
+    /// The record is one of those in `ext_rec`. The origins of IRR and BGP
+    /// sources get their names, those of BGP sources are checked as well.
+    /// `unallocated` is what `is_unallocated` says about the prefix, worked
+    /// out once for all its records.
+    pub fn source_record_to_json_builder(
+        &self,
+        prefix: Prefix,
+        ext_rec: &ExtPrefixRecord,
+        rec: &SourceRecord,
+        unallocated: Option<bool>,
+        builder: &mut JsonBuilder,
+    ) {
+        let source = self.sources.get(rec.source);
+        builder.member_str("sourceType", source.source_type);
+        builder.member_str("sourceID", &source.id);
+        rec.meta.to_json_builder(builder);
+        match source.source_type {
+            SourceType::Bgp => {
+                let origins = rec.meta.origin_asns();
+                self.as_names.to_json_builder(origins, builder);
+                self.bgp_checks_to_json_builder(prefix, origins, ext_rec, unallocated, builder);
+            }
+            SourceType::Irr => self.as_names.to_json_builder(rec.meta.origin_asns(), builder),
+            _ => {}
+        }
+    }
+
+    /// Adds the checks of a route for `prefix` to its bgp meta object.
+    ///
+    /// The origins are validated against the VRPs and looked up in the
+    /// route objects of `ext_rec`, the record of the prefix. The prefix is
+    /// checked against the IANA and RIR allocations. The upstreams of the
+    /// origins are added as well. Every check is only added if its data was
+    /// loaded.
+    pub fn bgp_checks_to_json_builder(
+        &self,
+        prefix: Prefix,
+        origins: &[Asn],
+        ext_rec: &ExtPrefixRecord,
+        unallocated: Option<bool>,
+        builder: &mut JsonBuilder,
+    ) {
+        self.vrps.to_json_builder(prefix, origins, builder);
+        self.as_graph.to_json_builder(origins, builder);
+        if let Some(unallocated) = unallocated {
+            builder.member_raw("unallocated", unallocated);
+        }
+        if self.irr_objects == 0 {
+            return;
+        }
+        builder.member_array("irr", |builder| {
+            for origin in origins {
+                let sources: Vec<_> = ext_rec
+                    .records::<IrrRecord>()
+                    .filter(|(_, rec)| rec.origin_asns.0.contains(origin))
+                    .map(|(source, _)| &self.sources.get(source).id)
+                    .collect();
+                builder.array_object(|builder| {
+                    builder.member_str("originASN", origin);
+                    builder.member_raw("registered", !sources.is_empty());
+                    builder.member_array("sources", |builder| {
+                        for source in sources {
+                            builder.array_str(source);
+                        }
+                    });
+                });
+            }
+        });
+    }
+
+    /// Returns the lines of data files that were skipped while loading.
+    pub fn load_report(&self) -> &LoadReport {
+        &self.load_report
+    }
+
+    /// Handles a line of a data file that cannot be loaded.
+    ///
+    /// In strict mode this fails the whole load. In lenient mode the line
+    /// is added to the load report and loading continues.
+    fn skip_line(
+        &mut self,
+        path: &Path,
+        line: u64,
+        reason: impl fmt::Display,
+        raw: impl Into<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let issue = LoadIssue {
+            file: path.display().to_string(),
+            line,
+            reason: reason.to_string(),
+            raw: raw.into(),
+        };
+        match self.load_mode {
+            LoadMode::Strict => Err(issue.to_string().into()),
+            LoadMode::Lenient => {
+                self.load_report.push(issue);
+                Ok(())
+            }
+        }
+    }
+
+    /// Loads BGP origins from a riswhois file.
+    ///
+    /// This accepts both the original tab-separated riswhoisdump format and
+    /// the `<prefix>,<len>,<asn>` CSV the old download scripts produced.
+    /// Either may be gzipped.
+    ///
+    /// RIS publishes one file per address family, so the routes become the
+    /// `riswhois-v4` and `riswhois-v6` sources. The modification time of
+    /// the file is the time they were last updated.
+    pub fn load_riswhois(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut reader = open_data_file(path)?;
+        let mut sources = Vec::new();
+        if is_riswhois_dump(reader.fill_buf()?) {
+            for (idx, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.starts_with('%') || line.trim().is_empty() {
+                    continue;
+                }
+                match self.load_riswhois_dump_line(&line) {
+                    Ok(source) if !sources.contains(&source) => sources.push(source),
+                    Ok(_) => {}
+                    Err(err) => self.skip_line(path, idx as u64 + 1, err, line)?,
+                }
+            }
+        } else {
+            let mut rdr = csv::Reader::from_reader(reader);
+            for result in rdr.records() {
+                match result {
+                    Ok(record) => match self.load_riswhois_record(&record) {
+                        Ok(source) if !sources.contains(&source) => sources.push(source),
+                        Ok(_) => {}
+                        Err(err) => {
+                            let line = record.position().map_or(0, |pos| pos.line());
+                            let raw = record.iter().collect::<Vec<_>>().join(",");
+                            self.skip_line(path, line, err, raw)?;
+                        }
+                    },
+                    Err(err) => {
+                        let line = err.position().map_or(0, |pos| pos.line());
+                        self.skip_line(path, line, &err, "")?;
+                    }
+                }
+            }
+        }
+        if let Ok(modified) = path.metadata().and_then(|meta| meta.modified()) {
+            let modified = DateTime::<Utc>::from(modified).trunc_subsecs(0).into();
+            for source in sources {
+                self.sources.set_last_updated(SourceType::Bgp, source, modified);
+            }
+        }
+        self.updated = Utc::now();
+        Ok(())
+    }
+
+    fn load_riswhois_record(&mut self, record: &csv::StringRecord) -> Result<&'static str, Box<dyn Error>> {
+        let field = |idx| record.get(idx).ok_or("missing field");
+        let net = Addr::from_str(field(0)?)?;
+        let len = u8::from_str(field(1)?)?;
+        let asn = Asn::from_str(field(2)?)?;
+        self.insert_riswhois(net, len, asn, None)
+    }
+
+    /// Loads a line of a riswhoisdump file.
+    ///
+    /// Every line has the origin ASN, the prefix and the number of RIS
+    /// peers that see the announcement, separated by tabs. Lines starting
+    /// with `%` are comments.
+    fn load_riswhois_dump_line(&mut self, line: &str) -> Result<&'static str, Box<dyn Error>> {
+        let mut fields = line.split('\t');
+        let mut field = || fields.next().map(str::trim).ok_or("missing field");
+        let asn = Asn::from_str(field()?)?;
+        let (net, len) = field()?.split_once('/').ok_or("missing prefix length")?;
+        let peers = u32::from_str(field()?)?;
+        self.insert_riswhois(Addr::from_str(net)?, u8::from_str(len)?, asn, Some(peers))
+    }
+
+    /// Inserts a route from a riswhois file and returns the ID of the
+    /// source it went into.
+    fn insert_riswhois(
+        &mut self,
+        net: Addr,
+        len: u8,
+        asn: Asn,
+        peers: Option<u32>,
+    ) -> Result<&'static str, Box<dyn Error>> {
+        let id = match net {
+            Addr::V4(_) => "riswhois-v4",
+            Addr::V6(_) => "riswhois-v6",
+        };
+        let source = self.sources.register(SourceType::Bgp, id);
+        let rec = RisWhoisRecord {
+            origin_asns: AsnArray(vec![asn]),
+            seen_by_peers: vec![peers],
+        };
+        let meta = ExtPrefixRecord::new(source, rec);
+
+        check_prefix_len(net, len)?;
+        match net {
+            Addr::V4(net) => self.v4.insert(RotondaPrefix::new_with_meta(net, len, meta))?,
+            Addr::V6(net) => self.v6.insert(RotondaPrefix::new_with_meta(net, len, meta))?,
+        }
+        Ok(id)
+    }
+
+    /// Loads the routes from an MRT TABLE_DUMP_V2 RIB dump, optionally
+    /// gzipped.
+    ///
+    /// All routes are attributed to `collector`, which becomes the source
+    /// ID of the records. Records that can't be decoded are reported with
+    /// their record number instead of a line number.
+    pub fn load_mrt(&mut self, path: &Path, collector: &str) -> Result<(), Box<dyn Error>> {
+        let mut reader = open_data_file(path)?;
+        if !is_table_dump_v2(reader.fill_buf()?) {
+            return Err(format!("{} is not an MRT TABLE_DUMP_V2 file", path.display()).into());
+        }
+        let source = self.sources.register(SourceType::Bgp, collector);
+        let mut reader = TableDumpReader::new(reader);
+        while let Some(entry) = reader.next_entry()? {
+            let res = entry.map_err(Into::into).and_then(|entry| {
+                let (net, len) = (entry.net, entry.len);
+                let meta = ExtPrefixRecord::new(source, BgpRibRecord::from_entry(entry));
+                match net {
+                    Addr::V4(net) => self.v4.insert(RotondaPrefix::new_with_meta(net, len, meta)),
+                    Addr::V6(net) => self.v6.insert(RotondaPrefix::new_with_meta(net, len, meta)),
+                }
+            });
+            if let Err(err) = res {
+                self.skip_line(path, reader.record(), err, "")?;
+            }
+        }
+        self.updated = Utc::now();
+        Ok(())
+    }
+
+    /// Loads the built-in IANA special-purpose address blocks.
+    pub fn load_special_purpose(&mut self) -> Result<(), Box<dyn Error>> {
+        let source = self.sources.register(SourceType::IanaSpecial, "iana");
+        for (net, len, rec) in iana::special_purpose_blocks()? {
+            self.insert_record(net, len, ExtPrefixRecord::new(source, rec))?;
+        }
+        self.updated = Utc::now();
+        Ok(())
+    }
+
+    /// Loads the top-level allocations from the CSV version of the IANA
+    /// IPv4 address space or IPv6 unicast address assignments registry.
+    pub fn load_iana_address_space(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(open_data_file(path)?);
+        let source = self.sources.register(SourceType::IanaAlloc, "iana");
+        for record in rdr.records() {
+            match record {
+                Ok(record) => {
+                    let res = IanaAllocationRecord::from_csv_record(&record);
+                    let res = res.and_then(|(net, len, rec)| {
+                        self.insert_record(net, len, ExtPrefixRecord::new(source, rec))?;
+                        Ok(net)
+                    });
+                    match res {
+                        Ok(Addr::V4(_)) => self.iana_v4 = true,
+                        Ok(Addr::V6(_)) => self.iana_v6 = true,
+                        Err(err) => {
+                            let line = record.position().map_or(0, |pos| pos.line());
+                            let raw = record.iter().collect::<Vec<_>>().join(",");
+                            self.skip_line(path, line, err, raw)?;
+                        }
+                    }
+                }
+                Err(err) => {
+                    let line = err.position().map_or(0, |pos| pos.line());
+                    self.skip_line(path, line, &err, "")?;
+                }
+            }
+        }
+        self.updated = Utc::now();
+        Ok(())
+    }
+
+    /// Loads the route and route6 objects from an RPSL dump of an IRR
+    /// database, optionally gzipped.
+    ///
+    /// Objects that can't be loaded are reported with the number of their
+    /// first line.
+    pub fn load_irr(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut reader = RpslReader::new(open_data_file(path)?);
+        while let Some((line, object)) = reader.next_object()? {
+            let res = object.map_err(Into::into).and_then(|object| {
+                let source = self
+                    .sources
+                    .register(SourceType::Irr, &object.source.to_ascii_lowercase());
+                let rec = IrrRecord {
+                    origin_asns: AsnArray(vec![object.origin]),
+                };
+                self.insert_record(object.net, object.len, ExtPrefixRecord::new(source, rec))
+            });
+            match res {
+                Ok(()) => self.irr_objects += 1,
+                Err(err) => self.skip_line(path, line, err, "")?,
+            }
+        }
+        self.updated = Utc::now();
+        Ok(())
+    }
+
+    /// Loads the names of ASNs from a CAIDA as2org file or the RIPE AS
+    /// names list, optionally gzipped.
+    ///
+    /// Names loaded later replace those of the same ASN loaded earlier.
+    pub fn load_as_names(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let names = asnames::read(open_data_file(path)?, |line, reason, raw| {
+            self.skip_line(path, line, reason, raw)
+        })?;
+        for (asn, name) in names {
+            self.sources.register(SourceType::AsName, name.source);
+            self.as_names.insert(asn, name);
+        }
+        self.updated = Utc::now();
+        Ok(())
+    }
+
+    /// Loads the relationships between ASes from a CAIDA as-rel file,
+    /// optionally gzipped.
+    pub fn load_as_relationships(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let reader = open_data_file(path)?;
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Err(err) = self.as_graph.insert_line(line) {
+                self.skip_line(path, idx as u64 + 1, err, line)?;
+            }
+        }
+        self.sources.register(SourceType::AsRel, "caida");
+        self.updated = Utc::now();
+        Ok(())
+    }
+
+    /// Loads validated ROA payloads from the JSON or CSV export of
+    /// Routinator or rpki-client, optionally gzipped.
+    ///
+    /// Entries of a JSON export that can't be loaded are reported with
+    /// their position in the `roas` array instead of a line number.
+    pub fn load_vrps(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut data = String::new();
+        open_data_file(path)?.read_to_string(&mut data)?;
+
+        let mut generated = None;
+        if data.trim_start().starts_with('{') {
+            let (time, vrps) = rpki::parse_json(&data)?;
+            generated = time;
+            for (idx, vrp) in vrps.into_iter().enumerate() {
+                if let Err(err) = vrp.and_then(|vrp| self.vrps.insert(vrp)) {
+                    self.skip_line(path, idx as u64 + 1, err, "")?;
+                }
+            }
+        } else {
+            let mut rdr = csv::Reader::from_reader(data.as_bytes());
+            for record in rdr.records() {
+                match record {
+                    Ok(record) => {
+                        let res = rpki::parse_csv_record(&record).and_then(|vrp| self.vrps.insert(vrp));
+                        if let Err(err) = res {
+                            let line = record.position().map_or(0, |pos| pos.line());
+                            let raw = record.iter().collect::<Vec<_>>().join(",");
+                            self.skip_line(path, line, err, raw)?;
+                        }
+                    }
+                    Err(err) => {
+                        let line = err.position().map_or(0, |pos| pos.line());
+                        self.skip_line(path, line, &err, "")?;
+                    }
+                }
+            }
+        }
+
+        // Without a time in the export itself, the modification time of
+        // the file is the best we have.
+        let generated = match generated {
+            Some(time) => time,
+            None => DateTime::<Utc>::from(std::fs::metadata(path)?.modified()?).into(),
+        };
+        self.sources.set_last_updated(SourceType::Rpki, "vrps", generated);
+        self.updated = Utc::now();
+        Ok(())
+    }
+
+    /// Loads the delegations of a delegated-extended file.
+    ///
+    /// The serial of the RIR source is taken from the version line of the
+    /// file. Files concatenated into one, as the download script does, have
+    /// a version line each. A file without one, or with fewer or more
+    /// records than its version line announces, is reported like a bad
+    /// line, since it was likely cut off or mangled.
+    pub fn load_prefixes(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let file = open_data_file(path)?;
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(b'|')
+            .flexible(true)
+            .has_headers(false)
+            .from_reader(file);
+
+        // The version lines with their line number and the number of
+        // records that followed them.
+        let mut headers: Vec<(u64, DelExtHeader, u64)> = Vec::new();
+        let mut missing_header = false;
+        for record in rdr.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    let line = err.position().map_or(0, |pos| pos.line());
+                    self.skip_line(path, line, &err, "")?;
+                    continue;
+                }
+            };
+            let line = record.position().map_or(0, |pos| pos.line());
+            let raw = || record.iter().collect::<Vec<_>>().join("|");
+            if record.get(0).unwrap_or_default().starts_with('#') || record.get(5) == Some("summary") {
+                continue;
+            }
+            if DelExtHeader::is_version_line(&record) {
+                match DelExtHeader::from_record(&record) {
+                    Ok(header) => headers.push((line, header, 0)),
+                    Err(err) => self.skip_line(path, line, err, raw())?,
+                }
+                continue;
+            }
+            match headers.last_mut() {
+                Some((_, _, records)) => *records += 1,
+                None if !missing_header => {
+                    missing_header = true;
+                    self.skip_line(path, line, "no version line before the first record", raw())?;
+                }
+                None => {}
+            }
+            if let Err(err) = self.load_prefixes_record(&record) {
+                self.skip_line(path, line, err, raw())?;
+            }
+        }
+
+        for (line, header, records) in headers {
+            if header.records != records {
+                let reason = format!(
+                    "version line of {} announces {} records, found {}",
+                    header.registry, header.records, records
+                );
+                self.skip_line(path, line, reason, "")?;
+            }
+            let rir = Rir::from(header.registry.as_str());
+            if rir != Rir::Unknown {
+                self.sources.set_serial(SourceType::RirAlloc, &rir.to_json_id(), header.serial);
+            }
+        }
+        self.updated = Utc::now();
+        Ok(())
+    }
+
+    fn load_prefixes_record(&mut self, record: &csv::StringRecord) -> Result<(), Box<dyn Error>> {
+        let field = |idx| record.get(idx).unwrap_or_default();
+
+        if field(0).starts_with('#')
+            || field(5) == "summary"
+            || field(6) == "reserved"
+            || field(6) == "available"
+        {
+            return Ok(());
+        }
+
+        let group_id = match record.get(7) {
+            Some(id) => id.to_string(),
+            None => return Ok(()),
+        };
+
+        // The country is empty or "ZZ" if it's unknown, the date is
+        // empty or all zeros for some legacy delegations.
+        let country = match field(1) {
+            "" | "ZZ" => None,
+            cc => Some(cc.to_string()),
+        };
+        let date = NaiveDate::parse_from_str(field(5), "%Y%m%d").ok();
+
+        // The combined file of the NRO has records of other registries,
+        // e.g. "iana", which aren't delegations by a RIR.
+        let rir = Rir::from(field(0));
+        if rir == Rir::Unknown {
+            return Ok(());
+        }
+
+        let mut rec = RirDelExtRecord {
+            group_id,
+            rir,
+            country,
+            date,
+            status: DelegationStatus::from_str(field(6))?,
+            range: None,
+        };
+        if !self.delegation_rirs.contains(&rec.rir) {
+            self.delegation_rirs.push(rec.rir);
+        }
+        let source = self.sources.register(SourceType::RirAlloc, &rec.rir.to_json_id());
+
+        match field(2) {
+            "ipv4" => {
+                let net = Ipv4Addr::from_str(field(3))?;
+
+                // field 4 is the number of addresses in the allocation.
+                // This doesn't need to be a power of two, nor does the
+                // range need to be aligned on it, so we may need several
+                // prefixes to cover it. All of them get the same record.
+                let count = u64::from_str(field(4))?;
+                let prefixes = ipv4_range_to_prefixes(net.into(), count)?;
+                let start = u32::from(net);
+                rec.range = Some((Addr::V4(start), Addr::V4(start + (count - 1) as u32)));
+                for (net, len) in prefixes {
+                    let meta = ExtPrefixRecord::new(source, rec.clone());
+                    self.v4
+                        .insert(RotondaPrefix::new_with_meta(net, len, meta))?;
+                }
+            }
+            "ipv6" => {
+                let net = Ipv6Addr::from_str(field(3))?;
+
+                // field 4 is just the prefix length here. No shenanigans
+                // necessary.
+                let len = u8::from_str(field(4))?;
+                check_prefix_len(net.into(), len)?;
+                rec.range = Some(Prefix::new(net.into(), len).range());
+
+                let meta = ExtPrefixRecord::new(source, rec);
+                self.v6
+                    .insert(RotondaPrefix::new_with_meta(net.into(), len, meta))?;
+            }
+            "asn" => {
+                // field 4 is the number of consecutive ASNs in the
+                // delegation.
+                let first = u32::from_str(field(3))?;
+                let count = u32::from_str(field(4))?;
+                self.asns.insert(first, count, rec)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Searches the trees for a prefix.
+    ///
+    /// Despite the name, this does all match types. The exact and empty
+    /// matches of the trees collect the wrong less-specifics, or still
+    /// return the longest match, so they are done by a longest match here.
     pub fn match_longest_prefix<AF: AddressFamily>(
         &self,
         prefix: Prefix,
         match_options: &MatchOptions,
-    ) -> QueryResult {
+    ) -> QueryResult<'_> {
+        let longest = MatchOptions {
+            match_type: MatchType::LongestMatch,
+            include_less_specifics: match_options.include_less_specifics,
+            include_more_specifics: match_options.include_more_specifics,
+        };
+        match match_options.match_type {
+            MatchType::LongestMatch => {}
+            MatchType::ExactMatch => {
+                let mut res = self.match_longest_prefix::<AF>(prefix, &longest);
+                if res.prefix.is_some_and(|pfx| pfx.len == prefix.len) {
+                    res.match_type = MatchType::ExactMatch;
+                    return res;
+                }
+                return self.match_empty(prefix, &longest);
+            }
+            MatchType::EmptyMatch => return self.match_empty(prefix, &longest),
+        }
+
+        if self.tombstones == 0 {
+            return self.match_prefix(prefix, match_options);
+        }
+
+        // A withdrawn prefix may be the match. To be able to fall back to
+        // the next less-specific prefix, we always need those.
+        let mut res = self.match_prefix(
+            prefix,
+            &MatchOptions {
+                match_type: match_options.match_type.clone(),
+                include_less_specifics: true,
+                include_more_specifics: match_options.include_more_specifics,
+            },
+        );
+        if res.prefix_meta.is_some_and(ExtPrefixRecord::is_empty) {
+            res.prefix = None;
+            res.prefix_meta = None;
+            res.match_type = MatchType::EmptyMatch;
+            if let MatchType::LongestMatch = match_options.match_type {
+                // The less-specifics leave out withdrawn prefixes, so the
+                // most specific of them is the match. Like any longest match
+                // that is less specific than the prefix, it stays among
+                // them.
+                if let Some((pfx, meta)) = res.less_specifics.last() {
+                    res.prefix = Some(pfx);
+                    res.prefix_meta = meta;
+                    res.match_type = MatchType::LongestMatch;
+                }
+            }
+        }
+        if !match_options.include_less_specifics {
+            res.less_specifics = RecordSet::default();
+        }
+        res
+    }
+
+    /// Returns only the less- and more-specifics of a prefix.
+    ///
+    /// The less-specifics of a longest match already include the match if
+    /// it is less specific than the prefix, unless it was withdrawn.
+    fn match_empty(&self, prefix: Prefix, longest: &MatchOptions) -> QueryResult<'_> {
+        let mut res = self.match_prefix(prefix, longest);
+        res.prefix = None;
+        res.prefix_meta = None;
+        res.match_type = MatchType::EmptyMatch;
+        res
+    }
+
+    fn match_prefix(&self, prefix: Prefix, match_options: &MatchOptions) -> QueryResult<'_> {
         match prefix.addr {
             Addr::V4(addr) => self
                 .v4
@@ -692,7 +2017,231 @@ impl Store {
         }
     }
 
-    pub fn get_related_prefixes(&self, meta: &RirDelExtRecord) -> RecordSet {
+    /// Returns the delegation by a RIR a prefix falls into.
+    pub fn get_delegation(&self, prefix: Prefix) -> Option<(Prefix, &RirDelExtRecord)> {
+        self.covering_records(prefix)
+            .into_iter()
+            .rev()
+            .find_map(|(pfx, meta)| meta.get::<RirDelExtRecord>().map(|rec| (pfx, rec)))
+    }
+
+    /// Returns the most specific announced prefix covering a prefix, with
+    /// the origin ASNs of all its BGP sources.
+    pub fn get_bgp_origins(&self, prefix: Prefix) -> Option<(Prefix, Vec<Asn>)> {
+        self.covering_records(prefix)
+            .into_iter()
+            .rev()
+            .map(|(pfx, meta)| (pfx, meta.origin_asns()))
+            .find(|(_, origins)| !origins.is_empty())
+    }
+
+    /// Returns the IANA block a prefix falls into, with its allocation.
+    pub fn get_iana_allocation(&self, prefix: Prefix) -> Option<(Prefix, &IanaAllocationRecord)> {
+        self.covering_records(prefix)
+            .into_iter()
+            .rev()
+            .find_map(|(pfx, meta)| meta.get::<IanaAllocationRecord>().map(|rec| (pfx, rec)))
+    }
+
+    /// Returns whether a prefix is in unallocated space.
+    ///
+    /// That is space IANA reserved, or that it allocated to a RIR and that
+    /// isn't within a delegation of the RIR. Legacy space counts as
+    /// allocated. Only the prefix and its less-specifics are looked at, so
+    /// a prefix that covers delegations without being within one counts as
+    /// unallocated. Returns `None` if the IANA allocations for the address
+    /// family, or the delegations of the responsible RIR, weren't loaded.
+    pub fn is_unallocated(&self, prefix: Prefix) -> Option<bool> {
+        let loaded = match prefix.addr {
+            Addr::V4(_) => self.iana_v4,
+            Addr::V6(_) => self.iana_v6,
+        };
+        if !loaded {
+            return None;
+        }
+        let records = self.covering_records(prefix);
+        if records.iter().any(|(_, meta)| meta.get::<RirDelExtRecord>().is_some()) {
+            return Some(false);
+        }
+        let iana = records
+            .iter()
+            .rev()
+            .find_map(|(_, meta)| meta.get::<IanaAllocationRecord>());
+        match iana {
+            None => Some(true),
+            Some(rec) => match (rec.status, rec.rir) {
+                (IanaStatus::Reserved, _) => Some(true),
+                (IanaStatus::Legacy, _) | (IanaStatus::Allocated, None) => Some(false),
+                (IanaStatus::Allocated, Some(rir)) if self.delegation_rirs.contains(&rir) => Some(true),
+                (IanaStatus::Allocated, Some(_)) => None,
+            },
+        }
+    }
+
+    /// Returns the records of a prefix and all its less-specifics, from
+    /// least to most specific.
+    fn covering_records(&self, prefix: Prefix) -> Vec<(Prefix, &ExtPrefixRecord)> {
+        // The less-specifics of a query leave out those in the last stride
+        // of the tree that was searched, so walk up by longest matches
+        // instead.
+        let options = MatchOptions {
+            match_type: MatchType::LongestMatch,
+            include_less_specifics: false,
+            include_more_specifics: false,
+        };
+        let mut records = Vec::new();
+        let mut search = Some(prefix);
+        while let Some(res) = search.map(|pfx| self.match_prefix(pfx, &options)) {
+            search = None;
+            if let Some(pfx) = res.prefix {
+                if let Some(meta) = res.prefix_meta {
+                    records.push((pfx, meta));
+                }
+                if pfx.len > 0 {
+                    search = Some(Prefix::new(pfx.addr, pfx.len - 1));
+                }
+            }
+        }
+        records.reverse();
+        records
+    }
+
+    /// Returns the record of a prefix, if it is in the trees.
+    ///
+    /// This includes prefixes that were withdrawn by all their sources.
+    fn exact_record(&self, net: Addr, len: u8) -> Option<&ExtPrefixRecord> {
+        let options = MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_less_specifics: false,
+            include_more_specifics: false,
+        };
+        self.match_prefix(Prefix::new(net, len), &options)
+            .prefix_meta
+    }
+
+    /// Applies the updates of live BGP sources.
+    ///
+    /// Withdrawn prefixes stay in the trees for the time being, but are
+    /// skipped in all queries. Once they make up a quarter of all
+    /// prefixes, the trees are rebuilt without them.
+    pub fn apply_bgp_updates(&mut self, updates: Vec<BgpUpdate>) -> Result<(), Box<dyn Error>> {
+        for update in updates {
+            match update {
+                BgpUpdate::Announce {
+                    source,
+                    peer,
+                    net,
+                    len,
+                    as_path,
+                } => {
+                    let source = self.sources.register(SourceType::Bgp, &source);
+                    let mut rec = BgpRibRecord {
+                        origin_asns: AsnArray(Vec::new()),
+                        paths: Vec::new(),
+                        live: true,
+                    };
+                    rec.add_path(as_path.asns, &as_path.origin_asns, &[peer]);
+                    if self.exact_record(net, len).is_some_and(ExtPrefixRecord::is_empty) {
+                        self.tombstones -= 1;
+                    }
+                    self.insert_record(net, len, ExtPrefixRecord::new(source, rec))?;
+                }
+                BgpUpdate::Withdraw { source, net, len } => {
+                    let source = match self.sources.find(SourceType::Bgp, &source) {
+                        Some(source) => source,
+                        None => continue,
+                    };
+                    match self.exact_record(net, len) {
+                        Some(rec) if rec.has_source(source) => {}
+                        _ => continue,
+                    }
+                    let rec = BgpRibRecord {
+                        origin_asns: AsnArray(Vec::new()),
+                        paths: Vec::new(),
+                        live: true,
+                    };
+                    self.insert_record(net, len, ExtPrefixRecord::new(source, rec))?;
+                    if self.exact_record(net, len).is_some_and(ExtPrefixRecord::is_empty) {
+                        self.tombstones += 1;
+                    }
+                }
+                BgpUpdate::PeerDown { source } => {
+                    let source = match self.sources.find(SourceType::Bgp, &source) {
+                        Some(source) => source,
+                        None => continue,
+                    };
+                    let v4 = self.v4.store.prefixes.iter_mut().filter_map(|p| p.meta.as_mut());
+                    let v6 = self.v6.store.prefixes.iter_mut().filter_map(|p| p.meta.as_mut());
+                    for meta in v4.chain(v6) {
+                        if meta.remove_source(source) && meta.is_empty() {
+                            self.tombstones += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        let prefixes = self.v4.store.prefixes.len() + self.v6.store.prefixes.len();
+        if self.tombstones * 4 > prefixes {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn insert_record(&mut self, net: Addr, len: u8, meta: ExtPrefixRecord) -> Result<(), Box<dyn Error>> {
+        check_prefix_len(net, len)?;
+        match net {
+            Addr::V4(net) => self.v4.insert(RotondaPrefix::new_with_meta(net, len, meta)),
+            Addr::V6(net) => self.v6.insert(RotondaPrefix::new_with_meta(net, len, meta)),
+        }
+    }
+
+    /// Rebuilds the trees without the withdrawn prefixes.
+    fn compact(&mut self) -> Result<(), Box<dyn Error>> {
+        let v4 = std::mem::replace(&mut self.v4, TreeBitMap::new(self.strides_v4.clone()));
+        let v6 = std::mem::replace(&mut self.v6, TreeBitMap::new(self.strides_v6.clone()));
+        for pfx in v4.store.prefixes {
+            if pfx.meta.as_ref().is_some_and(|meta| !meta.is_empty()) {
+                self.v4.insert(pfx)?;
+            }
+        }
+        for pfx in v6.store.prefixes {
+            if pfx.meta.as_ref().is_some_and(|meta| !meta.is_empty()) {
+                self.v6.insert(pfx)?;
+            }
+        }
+        self.tombstones = 0;
+        Ok(())
+    }
+
+    /// Copies the routes of the live BGP sources from another store.
+    ///
+    /// When the store is rebuilt from the data files, the routes received
+    /// from live feeds so far are only in the old store.
+    pub fn copy_live_routes(&mut self, other: &Store) -> Result<(), Box<dyn Error>> {
+        let v4 = other
+            .v4
+            .store
+            .prefixes
+            .iter()
+            .filter_map(|p| Some((Addr::V4(p.net), p.len, p.meta.as_ref()?)));
+        let v6 = other
+            .v6
+            .store
+            .prefixes
+            .iter()
+            .filter_map(|p| Some((Addr::V6(p.net), p.len, p.meta.as_ref()?)));
+        for (net, len, meta) in v4.chain(v6) {
+            for (source, rec) in meta.records::<BgpRibRecord>().filter(|(_, rec)| rec.live) {
+                let source = &other.sources.get(source).id;
+                let source = self.sources.register(SourceType::Bgp, source);
+                self.insert_record(net, len, ExtPrefixRecord::new(source, rec.clone()))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_related_prefixes(&self, meta: &RirDelExtRecord) -> RecordSet<'_> {
         RecordSet {
             v4: Self::_get_related_prefixes(&self.v4, meta),
             v6: Self::_get_related_prefixes(&self.v6, meta),
@@ -708,7 +2257,7 @@ impl Store {
             .iter()
             .filter(|&rel_p| {
                 if let Some(rel_p_meta) = rel_p.meta.as_ref() {
-                    if let Some(rel_p_meta_rde) = rel_p_meta.0.as_ref() {
+                    if let Some(rel_p_meta_rde) = rel_p_meta.get::<RirDelExtRecord>() {
                         rel_p_meta_rde.group_id == meta.group_id
                     } else {
                         false
@@ -720,11 +2269,16 @@ impl Store {
             .collect()
     }
 
+    /// Returns the delegation of an ASN by a RIR, if any.
+    pub fn get_asn_delegation(&self, asn: Asn) -> Option<&AsnDelegation> {
+        self.asns.get(asn)
+    }
+
     pub fn get_prefixes_for_bgp_asn(
         &self,
         asns: &[Asn],
         _search_options: &SearchByAsnOptions,
-    ) -> AsnQueryResult {
+    ) -> AsnQueryResult<'_> {
         let prefixes_v4 = self
             .v4
             .store
@@ -732,17 +2286,9 @@ impl Store {
             .as_slice()
             .iter()
             .filter(|p| {
-                if let Some(meta) = p.meta.as_ref() {
-                    if let Some(asn_rec) = meta.1.as_ref() {
-                        // search the vector of | search_asn X origin_asn |
-                        asns.iter()
-                            .any(|a1| asn_rec.origin_asns.0.iter().any(|a2| a2.0 == a1.0))
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                }
+                p.meta
+                    .as_ref()
+                    .is_some_and(|meta| meta.has_origin_asn(asns))
             })
             .collect::<Vec<&RotondaPrefix<_, _>>>();
 
@@ -753,17 +2299,9 @@ impl Store {
             .as_slice()
             .iter()
             .filter(|p| {
-                if let Some(meta) = p.meta.as_ref() {
-                    if let Some(asn_rec) = meta.1.as_ref() {
-                        // search the vector of | search_asn X origin_asn |
-                        asns.iter()
-                            .any(|a1| asn_rec.origin_asns.0.iter().any(|a2| a2.0 == a1.0))
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                }
+                p.meta
+                    .as_ref()
+                    .is_some_and(|meta| meta.has_origin_asn(asns))
             })
             .collect::<Vec<&RotondaPrefix<_, _>>>();
 
@@ -777,6 +2315,7 @@ impl Store {
     }
 
     pub fn output_stats(&self) {
+        println!("ASN delegations: {}\n", self.asns.len());
         println!("IPv4\n----");
         Self::output_tree_stats(&self.v4);
         println!("\nIPv6\n----");
@@ -887,6 +2426,90 @@ impl Store {
     }
 }
 
+//------------ Data File Helpers ---------------------------------------------
+
+/// Opens a data file for reading, decompressing it if it is gzipped.
+fn open_data_file(path: &Path) -> Result<Box<dyn BufRead>, Box<dyn Error>> {
+    let mut file = BufReader::new(File::open(path)?);
+    if file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Returns whether the start of a riswhois file is in the dump format.
+///
+/// The dump starts with a `%` comment header and separates its fields with
+/// tabs, the CSV has neither.
+fn is_riswhois_dump(start: &[u8]) -> bool {
+    let first_line = start.split(|&ch| ch == b'\n').next().unwrap_or_default();
+    first_line.starts_with(b"%") || first_line.contains(&b'\t')
+}
+
+/// Checks that a prefix length fits the address family of the prefix.
+fn check_prefix_len(net: Addr, len: u8) -> Result<(), Box<dyn Error>> {
+    let max_len = match net {
+        Addr::V4(_) => 32,
+        Addr::V6(_) => 128,
+    };
+    if len > max_len {
+        return Err(format!("invalid prefix length {} for {}", len, net).into());
+    }
+    Ok(())
+}
+
+/// Checks that the strides of a tree fit an address family of `bits` bits.
+///
+/// The strides are repeated until they reach the last bit, so they need
+/// to add up to exactly `bits` that way. Every stride needs to be between
+/// 3 and 8.
+pub fn check_strides(strides: &[u8], bits: u8) -> Result<(), String> {
+    if strides.is_empty() {
+        return Err("no strides".into());
+    }
+    if let Some(stride) = strides.iter().find(|stride| !(3..=8).contains(*stride)) {
+        return Err(format!("invalid stride {}, must be between 3 and 8", stride));
+    }
+    let mut sum = 0;
+    for stride in strides.iter().cycle() {
+        sum += u16::from(*stride);
+        if sum >= u16::from(bits) - 1 {
+            break;
+        }
+    }
+    if sum != u16::from(bits) {
+        return Err(format!("strides {:?} don't add up to {} bits", strides, bits));
+    }
+    Ok(())
+}
+
+/// Splits a range of IPv4 addresses into the minimal set of prefixes.
+///
+/// Each prefix is the largest one that starts at the first address not yet
+/// covered, is aligned on its own size and doesn't reach beyond the range.
+fn ipv4_range_to_prefixes(start: u32, count: u64) -> Result<Vec<(u32, u8)>, Box<dyn Error>> {
+    let end = u64::from(start) + count;
+    if count == 0 || end > 1 << 32 {
+        return Err(format!(
+            "invalid IPv4 range {} with count {}",
+            Ipv4Addr::from(start),
+            count
+        )
+        .into());
+    }
+
+    let mut res = Vec::new();
+    let mut net = u64::from(start);
+    while net < end {
+        let align_bits = if net == 0 { 32 } else { net.trailing_zeros() };
+        let size_bits = align_bits.min(63 - (end - net).leading_zeros());
+        res.push((net as u32, (32 - size_bits) as u8));
+        net += 1 << size_bits;
+    }
+    Ok(res)
+}
+
 //------------ JsonBuilder ---------------------------------------------------
 
 /// A helper type for building a JSON-encoded string on the fly.
@@ -1063,13 +2686,331 @@ struct JsonString<'a> {
 
 impl<'a> fmt::Write for JsonString<'a> {
     fn write_str(&mut self, mut s: &str) -> Result<(), fmt::Error> {
-        while let Some(idx) = s.find(|ch| ch == '"' || ch == '\\') {
+        while let Some(idx) = s.find(|ch: char| ch == '"' || ch == '\\' || ch.is_ascii_control()) {
             self.target.push_str(&s[..idx]);
-            self.target.push('\\');
-            self.target.push(char::from(s.as_bytes()[idx]));
+            match s.as_bytes()[idx] {
+                b'"' => self.target.push_str("\\\""),
+                b'\\' => self.target.push_str("\\\\"),
+                b'\n' => self.target.push_str("\\n"),
+                b'\r' => self.target.push_str("\\r"),
+                b'\t' => self.target.push_str("\\t"),
+                ch => write!(self.target, "\\u{:04x}", ch)?,
+            }
             s = &s[idx + 1..];
         }
         self.target.push_str(s);
         Ok(())
     }
 }
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn v4(s: &str) -> u32 {
+        Ipv4Addr::from_str(s).unwrap().into()
+    }
+
+    fn pfx(s: &str) -> Prefix {
+        let (addr, len) = s.split_once('/').unwrap();
+        Prefix::new(Addr::V4(v4(addr)), u8::from_str(len).unwrap())
+    }
+
+    fn options(match_type: MatchType) -> MatchOptions {
+        MatchOptions {
+            match_type,
+            include_less_specifics: true,
+            include_more_specifics: true,
+        }
+    }
+
+    fn prefixes_of(set: &RecordSet) -> Vec<String> {
+        set.iter().map(|(pfx, _)| pfx.to_string()).collect()
+    }
+
+    fn announce(prefix: &str) -> BgpUpdate {
+        let asn = Asn::from(64500);
+        BgpUpdate::Announce {
+            source: "bmp/192.0.2.254/192.0.2.1".into(),
+            peer: BgpPeer {
+                addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                asn,
+            },
+            net: pfx(prefix).addr,
+            len: pfx(prefix).len,
+            as_path: bgp::AsPath {
+                asns: vec![asn],
+                origin_asns: vec![asn],
+            },
+        }
+    }
+
+    fn withdraw(prefix: &str) -> BgpUpdate {
+        BgpUpdate::Withdraw {
+            source: "bmp/192.0.2.254/192.0.2.1".into(),
+            net: pfx(prefix).addr,
+            len: pfx(prefix).len,
+        }
+    }
+
+    /// Returns a store with 10.0.0.0/8 and 10.0.0.0/16 and 10.0.0.0/24,
+    /// both of the latter withdrawn but not yet compacted away.
+    fn store_with_tombstones() -> Store {
+        let mut store = Store::default();
+        let mut updates = vec![announce("10.0.0.0/8"), announce("10.0.0.0/16"), announce("10.0.0.0/24")];
+        // Enough other prefixes to keep the store from being compacted.
+        updates.extend((1..=10).map(|i| announce(&format!("192.0.{}.0/24", i))));
+        store.apply_bgp_updates(updates).unwrap();
+        store
+            .apply_bgp_updates(vec![withdraw("10.0.0.0/16"), withdraw("10.0.0.0/24")])
+            .unwrap();
+        assert_eq!(store.tombstones, 2);
+        store
+    }
+
+    fn prefixes(start: &str, count: u64) -> Vec<String> {
+        ipv4_range_to_prefixes(v4(start), count)
+            .unwrap()
+            .into_iter()
+            .map(|(net, len)| format!("{}/{}", Ipv4Addr::from(net), len))
+            .collect()
+    }
+
+    #[test]
+    fn aligned_range_is_one_prefix() {
+        assert_eq!(prefixes("10.0.0.0", 65536), ["10.0.0.0/16"]);
+        assert_eq!(prefixes("192.0.2.5", 1), ["192.0.2.5/32"]);
+        assert_eq!(prefixes("0.0.0.0", 1 << 32), ["0.0.0.0/0"]);
+    }
+
+    #[test]
+    fn unaligned_start_is_split() {
+        assert_eq!(prefixes("10.0.1.0", 512), ["10.0.1.0/24", "10.0.2.0/24"]);
+        assert_eq!(prefixes("10.0.0.128", 256), ["10.0.0.128/25", "10.0.1.0/25"]);
+    }
+
+    #[test]
+    fn count_not_a_power_of_two_is_split() {
+        assert_eq!(prefixes("10.0.0.0", 768), ["10.0.0.0/23", "10.0.2.0/24"]);
+        assert_eq!(prefixes("192.0.2.0", 7), ["192.0.2.0/30", "192.0.2.4/31", "192.0.2.6/32"]);
+    }
+
+    #[test]
+    fn range_may_end_at_the_last_address() {
+        assert_eq!(prefixes("255.255.255.0", 256), ["255.255.255.0/24"]);
+        assert_eq!(prefixes("255.255.254.128", 384), ["255.255.254.128/25", "255.255.255.0/24"]);
+        assert!(ipv4_range_to_prefixes(v4("255.255.255.0"), 257).is_err());
+    }
+
+    #[test]
+    fn zero_count_is_rejected() {
+        assert!(ipv4_range_to_prefixes(v4("10.0.0.0"), 0).is_err());
+    }
+
+    #[test]
+    fn longest_match_skips_withdrawn_prefixes() {
+        let store = store_with_tombstones();
+        let res = store.match_longest_prefix::<u32>(pfx("10.0.0.0/24"), &options(MatchType::LongestMatch));
+        assert!(matches!(res.match_type, MatchType::LongestMatch));
+        assert_eq!(res.prefix, Some(pfx("10.0.0.0/8")));
+        assert_eq!(prefixes_of(&res.less_specifics), ["10.0.0.0/8"]);
+
+        // Without tombstones, the match is among the less-specifics too.
+        let nested = store_with_nested_prefixes();
+        let res = nested.match_longest_prefix::<u32>(pfx("10.1.2.0/24"), &options(MatchType::LongestMatch));
+        assert_eq!(res.prefix, Some(pfx("10.1.0.0/16")));
+        assert_eq!(prefixes_of(&res.less_specifics), ["10.0.0.0/8", "10.1.0.0/16"]);
+
+        let res = store.match_longest_prefix::<u32>(pfx("10.0.0.0/8"), &options(MatchType::LongestMatch));
+        assert_eq!(res.prefix, Some(pfx("10.0.0.0/8")));
+        assert!(res.more_specifics.is_empty());
+    }
+
+    #[test]
+    fn empty_match_skips_withdrawn_prefixes() {
+        let store = store_with_tombstones();
+        let res = store.match_longest_prefix::<u32>(pfx("10.0.0.0/25"), &options(MatchType::EmptyMatch));
+        assert!(matches!(res.match_type, MatchType::EmptyMatch));
+        assert_eq!(res.prefix, None);
+        assert_eq!(prefixes_of(&res.less_specifics), ["10.0.0.0/8"]);
+        assert!(res.more_specifics.is_empty());
+    }
+
+    /// Returns a store with 10.0.0.0/8, 10.1.0.0/16 and 10.1.1.0/24.
+    fn store_with_nested_prefixes() -> Store {
+        let mut store = Store::default();
+        store
+            .apply_bgp_updates(vec![announce("10.0.0.0/8"), announce("10.1.0.0/16"), announce("10.1.1.0/24")])
+            .unwrap();
+        store
+    }
+
+    #[test]
+    fn exact_match() {
+        let store = store_with_nested_prefixes();
+        let res = store.match_longest_prefix::<u32>(pfx("10.1.0.0/16"), &options(MatchType::ExactMatch));
+        assert!(matches!(res.match_type, MatchType::ExactMatch));
+        assert_eq!(res.prefix, Some(pfx("10.1.0.0/16")));
+        assert_eq!(prefixes_of(&res.less_specifics), ["10.0.0.0/8"]);
+        assert_eq!(prefixes_of(&res.more_specifics), ["10.1.1.0/24"]);
+    }
+
+    #[test]
+    fn exact_match_miss_is_an_empty_match() {
+        let store = store_with_nested_prefixes();
+        let res = store.match_longest_prefix::<u32>(pfx("10.1.2.0/24"), &options(MatchType::ExactMatch));
+        assert!(matches!(res.match_type, MatchType::EmptyMatch));
+        assert_eq!(res.prefix, None);
+        assert!(res.prefix_meta.is_none());
+        assert_eq!(prefixes_of(&res.less_specifics), ["10.0.0.0/8", "10.1.0.0/16"]);
+        assert!(res.more_specifics.is_empty());
+
+        let res = store.match_longest_prefix::<u32>(
+            pfx("10.1.2.0/24"),
+            &MatchOptions {
+                match_type: MatchType::ExactMatch,
+                include_less_specifics: false,
+                include_more_specifics: false,
+            },
+        );
+        assert!(matches!(res.match_type, MatchType::EmptyMatch));
+        assert!(res.less_specifics.is_empty());
+    }
+
+    #[test]
+    fn exact_match_skips_withdrawn_prefixes() {
+        let store = store_with_tombstones();
+        let res = store.match_longest_prefix::<u32>(pfx("10.0.0.0/24"), &options(MatchType::ExactMatch));
+        assert!(matches!(res.match_type, MatchType::EmptyMatch));
+        assert_eq!(res.prefix, None);
+        assert_eq!(prefixes_of(&res.less_specifics), ["10.0.0.0/8"]);
+    }
+
+    #[test]
+    fn empty_match() {
+        let store = store_with_nested_prefixes();
+        let res = store.match_longest_prefix::<u32>(pfx("10.1.0.0/16"), &options(MatchType::EmptyMatch));
+        assert!(matches!(res.match_type, MatchType::EmptyMatch));
+        assert_eq!(res.prefix, None);
+        assert_eq!(prefixes_of(&res.less_specifics), ["10.0.0.0/8"]);
+        assert_eq!(prefixes_of(&res.more_specifics), ["10.1.1.0/24"]);
+
+        let res = store.match_longest_prefix::<u32>(pfx("10.1.2.0/24"), &options(MatchType::EmptyMatch));
+        assert_eq!(prefixes_of(&res.less_specifics), ["10.0.0.0/8", "10.1.0.0/16"]);
+    }
+
+    #[test]
+    fn longest_match() {
+        let store = store_with_nested_prefixes();
+        let res = store.match_longest_prefix::<u32>(pfx("10.1.2.0/24"), &options(MatchType::LongestMatch));
+        assert!(matches!(res.match_type, MatchType::LongestMatch));
+        assert_eq!(res.prefix, Some(pfx("10.1.0.0/16")));
+        // The less-specifics are those of the requested prefix, so they
+        // include the match.
+        assert_eq!(prefixes_of(&res.less_specifics), ["10.0.0.0/8", "10.1.0.0/16"]);
+        assert!(res.more_specifics.is_empty());
+
+        let res = store.match_longest_prefix::<u32>(pfx("10.1.1.0/24"), &options(MatchType::LongestMatch));
+        assert_eq!(res.prefix, Some(pfx("10.1.1.0/24")));
+        assert_eq!(prefixes_of(&res.less_specifics), ["10.0.0.0/8", "10.1.0.0/16"]);
+    }
+
+    #[test]
+    fn missing_prefix_is_an_empty_match() {
+        let store = store_with_nested_prefixes();
+        for match_type in [MatchType::ExactMatch, MatchType::LongestMatch, MatchType::EmptyMatch] {
+            let res = store.match_longest_prefix::<u32>(pfx("172.16.0.0/12"), &options(match_type));
+            assert!(matches!(res.match_type, MatchType::EmptyMatch));
+            assert_eq!(res.prefix, None);
+            assert!(res.less_specifics.is_empty());
+            assert!(res.more_specifics.is_empty());
+        }
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        let json = JsonBuilder::build(|builder| {
+            builder.member_str("error_msg", "Cannot parse `match`: \"x\"\\\n\u{1}");
+        });
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["error_msg"], "Cannot parse `match`: \"x\"\\\n\u{1}");
+        assert!(json.contains(r#""Cannot parse `match`: \"x\"\\\n\u0001""#));
+    }
+
+    #[test]
+    fn unallocated_space() {
+        let mut store = Store::default();
+        assert_eq!(store.is_unallocated(pfx("193.0.0.0/24")), None);
+
+        let iana = store.sources.register(SourceType::IanaAlloc, "iana");
+        for (net, status, rir) in [
+            ("193.0.0.0/8", IanaStatus::Allocated, Some(Rir::RipeNcc)),
+            ("41.0.0.0/8", IanaStatus::Allocated, Some(Rir::Afrinic)),
+            ("12.0.0.0/8", IanaStatus::Legacy, None),
+            ("240.0.0.0/4", IanaStatus::Reserved, None),
+        ] {
+            let rec = IanaAllocationRecord {
+                designation: String::new(),
+                date: String::new(),
+                whois: None,
+                status,
+                rir,
+            };
+            store.insert_record(pfx(net).addr, pfx(net).len, ExtPrefixRecord::new(iana, rec)).unwrap();
+        }
+        store.iana_v4 = true;
+        let ripe = store.sources.register(SourceType::RirAlloc, "ripencc");
+        let rec = RirDelExtRecord {
+            group_id: "ORG-TEST1".into(),
+            rir: Rir::RipeNcc,
+            country: None,
+            date: None,
+            status: DelegationStatus::Allocated,
+            range: None,
+        };
+        store.insert_record(pfx("193.0.0.0/21").addr, 21, ExtPrefixRecord::new(ripe, rec)).unwrap();
+        store.delegation_rirs.push(Rir::RipeNcc);
+
+        assert_eq!(store.is_unallocated(pfx("193.0.0.0/21")), Some(false));
+        assert_eq!(store.is_unallocated(pfx("193.0.4.0/24")), Some(false));
+        assert_eq!(store.is_unallocated(pfx("193.1.0.0/16")), Some(true));
+        // Only what covers the prefix counts.
+        assert_eq!(store.is_unallocated(pfx("193.0.0.0/16")), Some(true));
+        assert_eq!(store.is_unallocated(pfx("12.1.0.0/16")), Some(false));
+        assert_eq!(store.is_unallocated(pfx("240.0.0.0/8")), Some(true));
+        assert_eq!(store.is_unallocated(pfx("8.0.0.0/8")), Some(true));
+        // The delegations of AFRINIC weren't loaded.
+        assert_eq!(store.is_unallocated(pfx("41.0.0.0/16")), None);
+    }
+
+    #[test]
+    fn load_prefixes_leaves_out_other_registries() {
+        let path = std::env::temp_dir().join(format!("roto-api-nro-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "2.3|nro|1717196399|3|19830705|20240531|+0000\n\
+             nro|*|ipv4|*|2|summary\n\
+             ripencc|NL|ipv4|193.0.0.0|2048|19930901|allocated|ORG-A1|e-stats\n\
+             iana|ZZ|ipv4|0.0.0.0|16777216|19810901|assigned|ietf|iana\n\
+             ripencc|NL|asn|3333|1|19930901|assigned|ORG-A1|e-stats\n",
+        )
+        .unwrap();
+        let mut store = Store::default();
+        let res = store.load_prefixes(&path);
+        std::fs::remove_file(&path).unwrap();
+        res.unwrap();
+
+        let sources: Vec<_> = store
+            .sources()
+            .iter()
+            .map(|(_, info)| (info.source_type, info.id.as_str()))
+            .collect();
+        assert_eq!(sources, [(SourceType::RirAlloc, "ripe")]);
+        assert_eq!(store.delegation_rirs, [Rir::RipeNcc]);
+        let prefixes: Vec<_> = store.v4.store.prefixes.iter().map(|p| (p.net, p.len)).collect();
+        assert_eq!(prefixes, [(v4("193.0.0.0"), 21)]);
+        assert!(store.asns.get(Asn(3333)).is_some());
+    }
+}
@@ -0,0 +1,226 @@
+//! Reading MRT TABLE_DUMP_V2 RIB dumps.
+//!
+//! These are the `bview.*.gz` files of the RIS route collectors and the
+//! `rib.*` files of RouteViews. Only what is needed to find the origin of
+//! a prefix is decoded: the peer index table, the unicast RIB records and
+//! the AS_PATH attribute of their entries. All other record types and
+//! attributes are skipped. See RFC 6396 and, for the ADD-PATH variants of
+//! the RIB records, RFC 8050.
+
+use crate::bgp::{attributes, AsPath, BgpPeer, Parser, ATTR_TYPE_AS_PATH};
+use crate::{Addr, Asn};
+use std::error::Error;
+use std::io::{self, Read};
+
+const TABLE_DUMP_V2: u16 = 13;
+
+const PEER_INDEX_TABLE: u16 = 1;
+const RIB_IPV4_UNICAST: u16 = 2;
+const RIB_IPV6_UNICAST: u16 = 4;
+const RIB_IPV4_UNICAST_ADDPATH: u16 = 8;
+const RIB_IPV6_UNICAST_ADDPATH: u16 = 10;
+
+/// Returns whether the start of a file looks like an MRT TABLE_DUMP_V2
+/// file.
+///
+/// Every such file starts with a peer index table.
+pub fn is_table_dump_v2(buf: &[u8]) -> bool {
+    buf.len() >= 12
+        && u16::from_be_bytes([buf[4], buf[5]]) == TABLE_DUMP_V2
+        && u16::from_be_bytes([buf[6], buf[7]]) == PEER_INDEX_TABLE
+}
+
+//------------ RibEntry ------------------------------------------------------
+
+/// The routes for a single prefix, one for every peer that announced it.
+#[derive(Clone, Debug)]
+pub struct RibEntry {
+    pub net: Addr,
+    pub len: u8,
+    pub routes: Vec<RibRoute>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RibRoute {
+    pub peer: BgpPeer,
+
+    /// The AS path of the route. A route without an AS_PATH, from an
+    /// iBGP peer, has an empty path and no origin.
+    pub as_path: AsPath,
+}
+
+//------------ TableDumpReader -----------------------------------------------
+
+/// Reads the RIB entries from a TABLE_DUMP_V2 file.
+///
+/// Records are read one at a time. A record that can't be decoded is
+/// returned as an error without affecting the following records, as long
+/// as its MRT header could be read.
+pub struct TableDumpReader<R> {
+    reader: R,
+    peers: Vec<BgpPeer>,
+    record: u64,
+}
+
+impl<R: Read> TableDumpReader<R> {
+    pub fn new(reader: R) -> Self {
+        TableDumpReader {
+            reader,
+            peers: Vec::new(),
+            record: 0,
+        }
+    }
+
+    /// Returns the number of the MRT record last read, starting at one.
+    pub fn record(&self) -> u64 {
+        self.record
+    }
+
+    /// Returns the next RIB entry, skipping other records.
+    ///
+    /// The outer error is for the file itself, which can't be read any
+    /// further. The inner error is for a single record that was skipped.
+    pub fn next_entry(&mut self) -> Result<Option<Result<RibEntry, String>>, Box<dyn Error>> {
+        loop {
+            let mut header = [0; 12];
+            if !self.read_header(&mut header)? {
+                return Ok(None);
+            }
+            self.record += 1;
+            let mrt_type = u16::from_be_bytes([header[4], header[5]]);
+            let subtype = u16::from_be_bytes([header[6], header[7]]);
+            let length = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+
+            // The body is read through `take`, so the buffer only grows with
+            // the data that is actually there rather than with whatever the
+            // length field claims. Other records are skipped unbuffered.
+            let mut record = (&mut self.reader).take(u64::from(length));
+            let mut body = Vec::new();
+            let read = if mrt_type == TABLE_DUMP_V2 {
+                record.read_to_end(&mut body)? as u64
+            } else {
+                io::copy(&mut record, &mut io::sink())?
+            };
+            if read != u64::from(length) {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+
+            if mrt_type != TABLE_DUMP_V2 {
+                continue;
+            }
+            let mut body = Parser(&body);
+            let res = match subtype {
+                PEER_INDEX_TABLE => match self.parse_peer_index_table(&mut body) {
+                    Ok(()) => continue,
+                    Err(err) => Err(err),
+                },
+                RIB_IPV4_UNICAST => self.parse_rib(&mut body, false, false),
+                RIB_IPV6_UNICAST => self.parse_rib(&mut body, true, false),
+                RIB_IPV4_UNICAST_ADDPATH => self.parse_rib(&mut body, false, true),
+                RIB_IPV6_UNICAST_ADDPATH => self.parse_rib(&mut body, true, true),
+                _ => continue,
+            };
+            return Ok(Some(res));
+        }
+    }
+
+    /// Reads an MRT header. Returns false at a clean end of the file.
+    fn read_header(&mut self, header: &mut [u8; 12]) -> Result<bool, io::Error> {
+        let mut read = 0;
+        while read < header.len() {
+            match self.reader.read(&mut header[read..]) {
+                Ok(0) if read == 0 => return Ok(false),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+
+    fn parse_peer_index_table(&mut self, body: &mut Parser) -> Result<(), String> {
+        let _collector_id = body.take(4)?;
+        let view_len = body.u16()?;
+        let _view_name = body.take(view_len.into())?;
+        let count = body.u16()?;
+        let mut peers = Vec::with_capacity(count.into());
+        for _ in 0..count {
+            let peer_type = body.u8()?;
+            let _bgp_id = body.take(4)?;
+            let addr = body.addr(peer_type & 0x01 != 0)?;
+            let asn = if peer_type & 0x02 != 0 {
+                body.u32()?
+            } else {
+                body.u16()?.into()
+            };
+            peers.push(BgpPeer {
+                addr,
+                asn: Asn::from(asn),
+            });
+        }
+        self.peers = peers;
+        Ok(())
+    }
+
+    fn parse_rib(&self, body: &mut Parser, ipv6: bool, add_path: bool) -> Result<RibEntry, String> {
+        let _sequence = body.u32()?;
+        let (net, len) = body.prefix(ipv6)?;
+
+        let count = body.u16()?;
+        let mut routes = Vec::with_capacity(count.into());
+        for _ in 0..count {
+            let peer_idx = body.u16()?;
+            let _originated = body.u32()?;
+            if add_path {
+                let _path_id = body.u32()?;
+            }
+            let attr_len = body.u16()?;
+            let attrs = attributes(body.take(attr_len.into())?)?;
+            let peer = *self
+                .peers
+                .get(usize::from(peer_idx))
+                .ok_or_else(|| format!("unknown peer index {}", peer_idx))?;
+
+            // AS_PATHs in TABLE_DUMP_V2 always have four octet ASNs.
+            let as_path = match attrs.iter().find(|attr| attr.attr_type == ATTR_TYPE_AS_PATH) {
+                Some(attr) => AsPath::parse(attr.value, true)?,
+                None => AsPath::default(),
+            };
+            routes.push(RibRoute { peer, as_path });
+        }
+        Ok(RibEntry { net, len, routes })
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(mrt_type: u16, subtype: u16, length: u32, body: &[u8]) -> Vec<u8> {
+        let mut res = vec![0; 4];
+        res.extend_from_slice(&mrt_type.to_be_bytes());
+        res.extend_from_slice(&subtype.to_be_bytes());
+        res.extend_from_slice(&length.to_be_bytes());
+        res.extend_from_slice(body);
+        res
+    }
+
+    #[test]
+    fn other_records_are_skipped() {
+        let data = record(16, 4, 4, b"junk");
+        assert!(TableDumpReader::new(data.as_slice()).next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn oversized_length_is_an_error() {
+        for mrt_type in [TABLE_DUMP_V2, 16] {
+            let data = record(mrt_type, PEER_INDEX_TABLE, u32::MAX, b"junk");
+            let err = TableDumpReader::new(data.as_slice()).next_entry().unwrap_err();
+            let err = err.downcast_ref::<io::Error>().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+}
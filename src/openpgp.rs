@@ -0,0 +1,488 @@
+//! Checking detached OpenPGP signatures.
+//!
+//! The RIRs sign their delegated-extended files with detached signatures,
+//! published as `.asc` files next to them. This is just enough of RFC 4880
+//! to check those: ASCII armor, version 4 RSA keys and version 4 signatures
+//! over binary or text documents. Other keys in a key file are ignored.
+//!
+//! Keys are taken as they are: their expiry and revocation signatures and
+//! the binding signatures of subkeys are not checked. Which keys are trusted
+//! is up to whoever configures the key file.
+
+use base64::Engine;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use std::convert::TryInto;
+use std::fmt;
+
+const TAG_SIGNATURE: u8 = 2;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+
+const SUBPACKET_ISSUER: u8 = 16;
+const SUBPACKET_ISSUER_FINGERPRINT: u8 = 33;
+
+/// The largest RSA key that is accepted, in bits.
+const MAX_KEY_BITS: usize = 16384;
+
+//------------ KeyId ---------------------------------------------------------
+
+/// The ID of a key, the last eight octets of its fingerprint.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KeyId([u8; 8]);
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for octet in self.0 {
+            write!(f, "{:02X}", octet)?;
+        }
+        Ok(())
+    }
+}
+
+//------------ PublicKey -----------------------------------------------------
+
+/// A version 4 RSA key or subkey.
+struct PublicKey {
+    id: KeyId,
+    key: RsaPublicKey,
+}
+
+impl PublicKey {
+    /// Parses the body of a public key packet.
+    ///
+    /// Returns `None` for keys that can't be used, i.e. other versions or
+    /// algorithms.
+    fn parse(body: &[u8]) -> Result<Option<Self>, String> {
+        let mut r = Reader(body);
+        if r.octet()? != 4 {
+            return Ok(None);
+        }
+        let _created = r.take(4)?;
+        if !matches!(r.octet()?, 1..=3) {
+            return Ok(None);
+        }
+        let n = BigUint::from_bytes_be(r.mpi()?);
+        let e = BigUint::from_bytes_be(r.mpi()?);
+        let key = RsaPublicKey::new_with_max_size(n, e, MAX_KEY_BITS)
+            .map_err(|err| format!("invalid RSA key: {}", err))?;
+
+        let mut fingerprint = Sha1::new();
+        fingerprint.update([0x99]);
+        fingerprint.update((body.len() as u16).to_be_bytes());
+        fingerprint.update(body);
+        let mut id = [0; 8];
+        id.copy_from_slice(&fingerprint.finalize()[12..]);
+        Ok(Some(PublicKey { id: KeyId(id), key }))
+    }
+}
+
+//------------ Signature -----------------------------------------------------
+
+/// A version 4 RSA signature.
+struct Signature<'a> {
+    /// Whether line endings are normalized before hashing.
+    text: bool,
+    hash_algo: u8,
+
+    /// The part of the packet that is hashed along with the document.
+    hashed: &'a [u8],
+
+    /// The first two octets of the hash.
+    left: &'a [u8],
+    issuer: Option<KeyId>,
+    value: &'a [u8],
+}
+
+impl<'a> Signature<'a> {
+    fn parse(body: &'a [u8]) -> Result<Self, String> {
+        let mut r = Reader(body);
+        if r.octet()? != 4 {
+            return Err("only version 4 signatures are supported".into());
+        }
+        let text = match r.octet()? {
+            0x00 => false,
+            0x01 => true,
+            sig_type => return Err(format!("signature of type {:#04x} is not a document signature", sig_type)),
+        };
+        if !matches!(r.octet()?, 1 | 3) {
+            return Err("only RSA signatures are supported".into());
+        }
+        let hash_algo = r.octet()?;
+        let hashed_len = r.u16()? as usize;
+        let hashed_subpackets = r.take(hashed_len)?;
+        let hashed = &body[..6 + hashed_len];
+        let unhashed_len = r.u16()? as usize;
+        let unhashed_subpackets = r.take(unhashed_len)?;
+        let left = r.take(2)?;
+        let value = r.mpi()?;
+
+        let mut issuer = None;
+        for subpackets in [hashed_subpackets, unhashed_subpackets] {
+            let mut r = Reader(subpackets);
+            while !r.0.is_empty() {
+                let len = match r.octet()? {
+                    len @ 0..=191 => len as usize,
+                    first @ 192..=254 => ((first as usize - 192) << 8) + r.octet()? as usize + 192,
+                    255 => r.u32()? as usize,
+                };
+                let (kind, data) = r.take(len)?.split_first().ok_or("empty signature subpacket")?;
+                match kind & 0x7f {
+                    SUBPACKET_ISSUER if data.len() == 8 => {
+                        issuer = Some(KeyId(data.try_into().unwrap()));
+                    }
+                    SUBPACKET_ISSUER_FINGERPRINT if data.len() == 21 && data[0] == 4 => {
+                        issuer = Some(KeyId(data[13..].try_into().unwrap()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Signature {
+            text,
+            hash_algo,
+            hashed,
+            left,
+            issuer,
+            value,
+        })
+    }
+
+    /// Returns the digest of the document and the scheme to check it with.
+    fn digest(&self, data: &[u8]) -> Result<(Vec<u8>, Pkcs1v15Sign), String> {
+        fn hash<D: Digest + rsa::pkcs8::AssociatedOid>(sig: &Signature, data: &[u8]) -> (Vec<u8>, Pkcs1v15Sign) {
+            let mut digest = D::new();
+            if sig.text {
+                // Text signatures are made over the document with CRLF
+                // line endings.
+                let mut lines = data.split(|&octet| octet == b'\n').peekable();
+                while let Some(line) = lines.next() {
+                    digest.update(line.strip_suffix(b"\r").unwrap_or(line));
+                    if lines.peek().is_some() {
+                        digest.update(b"\r\n");
+                    }
+                }
+            } else {
+                digest.update(data);
+            }
+            digest.update(sig.hashed);
+            digest.update([0x04, 0xff]);
+            digest.update((sig.hashed.len() as u32).to_be_bytes());
+            (digest.finalize().to_vec(), Pkcs1v15Sign::new::<D>())
+        }
+
+        match self.hash_algo {
+            2 => Ok(hash::<Sha1>(self, data)),
+            8 => Ok(hash::<Sha256>(self, data)),
+            9 => Ok(hash::<Sha384>(self, data)),
+            10 => Ok(hash::<Sha512>(self, data)),
+            11 => Ok(hash::<Sha224>(self, data)),
+            algo => Err(format!("unsupported hash algorithm {}", algo)),
+        }
+    }
+
+    fn verify(&self, key: &PublicKey, digest: &[u8], scheme: Pkcs1v15Sign) -> bool {
+        // The MPI drops leading zeros, but the signature has the size of
+        // the key.
+        let size = rsa::traits::PublicKeyParts::size(&key.key);
+        if self.value.len() > size {
+            return false;
+        }
+        let mut value = vec![0; size - self.value.len()];
+        value.extend_from_slice(self.value);
+        key.key.verify(scheme, digest, &value).is_ok()
+    }
+}
+
+//------------ verify_detached -----------------------------------------------
+
+/// Checks a detached signature of `data` against the keys in `keys`.
+///
+/// Both the signature and the keys can be armored or binary. Returns the
+/// ID of the key that made the signature.
+pub fn verify_detached(keys: &[u8], signature: &[u8], data: &[u8]) -> Result<KeyId, String> {
+    let keys = dearmor(keys).map_err(|err| format!("cannot read keys: {}", err))?;
+    let keys = packets(&keys)
+        .map_err(|err| format!("cannot read keys: {}", err))?
+        .into_iter()
+        .filter(|(tag, _)| *tag == TAG_PUBLIC_KEY || *tag == TAG_PUBLIC_SUBKEY)
+        .map(|(_, body)| PublicKey::parse(body))
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("cannot read keys: {}", err))?;
+    if keys.is_empty() {
+        return Err("no usable RSA keys".into());
+    }
+
+    let signature = dearmor(signature).map_err(|err| format!("cannot read signature: {}", err))?;
+    let packets = packets(&signature).map_err(|err| format!("cannot read signature: {}", err))?;
+    let mut last_err = "no signature found".to_string();
+    for (_, body) in packets.into_iter().filter(|(tag, _)| *tag == TAG_SIGNATURE) {
+        let sig = match Signature::parse(body) {
+            Ok(sig) => sig,
+            Err(err) => {
+                last_err = err;
+                continue;
+            }
+        };
+        let (digest, scheme) = match sig.digest(data) {
+            Ok(digest) => digest,
+            Err(err) => {
+                last_err = err;
+                continue;
+            }
+        };
+        if digest[..2] != *sig.left {
+            last_err = "signature does not match the data".into();
+            continue;
+        }
+        let candidates = keys
+            .iter()
+            .filter(|key| sig.issuer.is_none_or(|issuer| issuer == key.id));
+        for key in candidates {
+            if sig.verify(key, &digest, scheme.clone()) {
+                return Ok(key.id);
+            }
+        }
+        last_err = match sig.issuer {
+            Some(issuer) if !keys.iter().any(|key| key.id == issuer) => {
+                format!("signed by unknown key {}", issuer)
+            }
+            _ => "signature does not match the data".into(),
+        };
+    }
+    Err(last_err)
+}
+
+/// Removes the ASCII armor, if there is one.
+fn dearmor(data: &[u8]) -> Result<Vec<u8>, String> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) if text.trim_start().starts_with("-----BEGIN PGP ") => text,
+        _ => return Ok(data.to_vec()),
+    };
+    let mut lines = text.lines().map(str::trim).skip_while(|line| !line.starts_with("-----BEGIN PGP "));
+    lines.next();
+
+    // The armor headers end with an empty line.
+    let lines: Vec<&str> = lines.take_while(|line| !line.starts_with("-----END PGP ")).collect();
+    let body = match lines.iter().position(|line| line.is_empty()) {
+        Some(idx) => &lines[idx + 1..],
+        None => &lines[..],
+    };
+    let mut encoded = String::new();
+    let mut checksum = None;
+    for line in body {
+        match line.strip_prefix('=') {
+            Some(crc) if line.len() == 5 => checksum = Some(crc),
+            _ => encoded.push_str(line),
+        }
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|err| format!("invalid armor: {}", err))?;
+    if let Some(checksum) = checksum {
+        let checksum = base64::engine::general_purpose::STANDARD
+            .decode(checksum)
+            .map_err(|err| format!("invalid armor checksum: {}", err))?;
+        if checksum != crc24(&decoded).to_be_bytes()[1..] {
+            return Err("armor checksum mismatch".into());
+        }
+    }
+    Ok(decoded)
+}
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xb704ce;
+    for &octet in data {
+        crc ^= (octet as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= 0x1864cfb;
+            }
+        }
+    }
+    crc & 0xffffff
+}
+
+/// Splits data into packets, returning the tag and body of each.
+fn packets(data: &[u8]) -> Result<Vec<(u8, &[u8])>, String> {
+    let mut r = Reader(data);
+    let mut packets = Vec::new();
+    while !r.0.is_empty() {
+        let header = r.octet()?;
+        if header & 0x80 == 0 {
+            return Err("invalid packet header".into());
+        }
+        let (tag, len) = if header & 0x40 != 0 {
+            let len = match r.octet()? {
+                len @ 0..=191 => len as usize,
+                first @ 192..=223 => ((first as usize - 192) << 8) + r.octet()? as usize + 192,
+                255 => r.u32()? as usize,
+                _ => return Err("partial body lengths are not supported".into()),
+            };
+            (header & 0x3f, len)
+        } else {
+            let len = match header & 0x03 {
+                0 => r.octet()? as usize,
+                1 => r.u16()? as usize,
+                2 => r.u32()? as usize,
+                _ => r.0.len(),
+            };
+            ((header >> 2) & 0x0f, len)
+        };
+        packets.push((tag, r.take(len)?));
+    }
+    Ok(packets)
+}
+
+//------------ Reader --------------------------------------------------------
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("unexpected end of data".into());
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn octet(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Reads a multiprecision integer, returning its octets.
+    fn mpi(&mut self) -> Result<&'a [u8], String> {
+        let bits = self.u16()? as usize;
+        self.take(bits.div_ceil(8))
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DOCUMENT: &[u8] = b"line one\nline two\n";
+
+    /// A 1024 bit RSA key, small to keep the fixtures short.
+    const KEY: &str = "\
+-----BEGIN PGP PUBLIC KEY BLOCK-----\n\
+\n\
+mI0EatRw+wEEAMAqRByzOLIQTEXOAPxXSumwNj+zllSMDJSXdUeHLNqVDa22xctB\n\
+0DzkEbyCoWX6BQxDdju/6rei8PwKRpKyhW1JgLKLN75kjs75QX2yGJRbPmrgypHG\n\
+xenohVuA/kom94t0fBDfbdYIAgVQWEQ3HQJtEsYzSVk6kbOZDKVg+P/ZABEBAAG0\n\
+F3Rlc3QtZzEgPHRAZXhhbXBsZS5uZXQ+iM4EEwEKADgWIQQYNVg5n5gAJ/62ymQG\n\
+DfRHQcV0RAUCatRw+wIbAwULCQgHAgYVCgkICwIEFgIDAQIeAQIXgAAKCRAGDfRH\n\
+QcV0RFG5A/9VwH+tRFMrDGvcSvCkxU60J8koCugYfoh5tA3nKHYAJWfTb8qncWWG\n\
+oN9M36xAiVmwtLKn/DjfsopG7tYTObPC36nArl/bOFfzwntfqab3ULbjbTuvwDAP\n\
+JcKnZTSuIyOH1vXdOO53RY9p/fo5ouVDMgKDE+4R4wHChaEXc2kKIw==\n\
+=3I9d\n\
+-----END PGP PUBLIC KEY BLOCK-----\n";
+
+    /// A key that didn't sign anything.
+    const OTHER_KEY: &str = "\
+-----BEGIN PGP PUBLIC KEY BLOCK-----\n\
+\n\
+mI0EatRw+wEEAMC+3agKmIQ+XmzhUwPs3+8EsPl+qhVVKQ+Qw10JEJ6/6msUwQZZ\n\
+oF528f3RELJ/+WwbLa6chWYUXpViUTM9YbYGcHNXfLQ4eR8Tqga58Bz5G0t4ZwuI\n\
+EwqfTyyKsC+G6UZNTyIUDXnkFSOylfTq2XHroK04MXXy4qj+h7LMBrS3ABEBAAG0\n\
+F3Rlc3QtZzIgPHRAZXhhbXBsZS5uZXQ+iM4EEwEKADgWIQRcZaypUmU1IGoHW/lM\n\
+iPyRf77I/QUCatRw+wIbAwULCQgHAgYVCgkICwIEFgIDAQIeAQIXgAAKCRBMiPyR\n\
+f77I/RyXBACy5kk3M/hDkVjredWjH8lKerPdIiOe9piUmDSfCy8gwNTnZCBv/xFn\n\
+MF1q7Nhjdsvqq1p3eb+waEMnHasUlfE4efuuDF2dQZ4jKcOvcbjMh6bK0Pb5pUde\n\
+DRNDKjvkxA7/n8Ci5cBmZfonTxA5Jgm5n8cTwahHdV01wuoTXywsBw==\n\
+=ZEBM\n\
+-----END PGP PUBLIC KEY BLOCK-----\n";
+
+    /// A binary SHA-256 signature of `DOCUMENT` by `KEY`.
+    const SIGNATURE: &str = "\
+-----BEGIN PGP SIGNATURE-----\n\
+\n\
+iLMEAAEIAB0WIQQYNVg5n5gAJ/62ymQGDfRHQcV0RAUCatRw+wAKCRAGDfRHQcV0\n\
+RMJkA/0UJW1lVzwgrrzQxVmawMEGdK4XudR4lRoSGuvN8hkhnOjobKm4DX4GLweN\n\
+cgp+K5q1ZG3r5LGLJnnaUmZalYCSIiP3i3QV0nBCfK4U9jfesU05ArUBEKZJjonl\n\
+rxsvaFCWwprTjZIVaH97oXeNP8iDAN0snSn7hbeUZjBI4q5mCQ==\n\
+=E12K\n\
+-----END PGP SIGNATURE-----\n";
+
+    /// A text mode SHA-256 signature of `DOCUMENT` by `KEY`.
+    const TEXT_SIGNATURE: &str = "\
+-----BEGIN PGP SIGNATURE-----\n\
+\n\
+iLMEAQEIAB0WIQQYNVg5n5gAJ/62ymQGDfRHQcV0RAUCatRw+wAKCRAGDfRHQcV0\n\
+RJYHA/94WxnODOHe5iVVkMWmnFtbA9DPxjz6MyJzXJbxw0TzaQr00wtAo/8IHykP\n\
+SwkUZGIh7+Yb0P8eFeSkIzQu86K1jWNLPr7wFdjl1a3PfhbaG57C7y/jkJ2yIZdc\n\
+ETj22UAtwhyX7n+88Sxm+wOJ/TrwHGmx6d9PiAXnrl0vgBUbug==\n\
+=NAah\n\
+-----END PGP SIGNATURE-----\n";
+
+    fn key_id() -> KeyId {
+        KeyId([0x06, 0x0D, 0xF4, 0x47, 0x41, 0xC5, 0x74, 0x44])
+    }
+
+    #[test]
+    fn good_signature() {
+        let signed_by = verify_detached(KEY.as_bytes(), SIGNATURE.as_bytes(), DOCUMENT).unwrap();
+        assert_eq!(signed_by, key_id());
+        assert_eq!(signed_by.to_string(), "060DF44741C57444");
+    }
+
+    #[test]
+    fn tampered_data() {
+        let err = verify_detached(KEY.as_bytes(), SIGNATURE.as_bytes(), b"line one\nline 2\n").unwrap_err();
+        assert_eq!(err, "signature does not match the data");
+    }
+
+    #[test]
+    fn wrong_key() {
+        let err = verify_detached(OTHER_KEY.as_bytes(), SIGNATURE.as_bytes(), DOCUMENT).unwrap_err();
+        assert_eq!(err, "signed by unknown key 060DF44741C57444");
+    }
+
+    #[test]
+    fn bad_armor_checksum() {
+        let signature = SIGNATURE.replace("\n=E12K\n", "\n=E12L\n");
+        assert_ne!(signature, SIGNATURE);
+        let err = verify_detached(KEY.as_bytes(), signature.as_bytes(), DOCUMENT).unwrap_err();
+        assert_eq!(err, "cannot read signature: armor checksum mismatch");
+    }
+
+    #[test]
+    fn text_signature() {
+        assert_eq!(verify_detached(KEY.as_bytes(), TEXT_SIGNATURE.as_bytes(), DOCUMENT), Ok(key_id()));
+        let crlf = b"line one\r\nline two\r\n";
+        assert_eq!(verify_detached(KEY.as_bytes(), TEXT_SIGNATURE.as_bytes(), crlf), Ok(key_id()));
+
+        // A binary signature covers the line endings as they are.
+        assert!(verify_detached(KEY.as_bytes(), SIGNATURE.as_bytes(), crlf).is_err());
+    }
+
+    #[test]
+    fn unsupported_hash_is_skipped() {
+        // Put a copy of the signature that claims MD5 in front of it.
+        let good = dearmor(SIGNATURE.as_bytes()).unwrap();
+        let mut signature = good.clone();
+        signature[5] = 1;
+        signature.extend_from_slice(&good);
+        assert_eq!(verify_detached(KEY.as_bytes(), &signature, DOCUMENT), Ok(key_id()));
+
+        signature.truncate(good.len());
+        let err = verify_detached(KEY.as_bytes(), &signature, DOCUMENT).unwrap_err();
+        assert_eq!(err, "unsupported hash algorithm 1");
+    }
+}
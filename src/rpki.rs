@@ -0,0 +1,476 @@
+//! Route origin validation against validated ROA payloads.
+//!
+//! The VRPs are read from the JSON or CSV exports of Routinator or
+//! rpki-client and kept in their own prefix trees, so the VRPs covering a
+//! route can be found by walking up from its longest match. The validation
+//! itself follows RFC 6811.
+
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::{Addr, Asn, JsonBuilder, Prefix};
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use rotonda_store::common::{AddressFamily, MergeUpdate, Prefix as RotondaPrefix};
+use rotonda_store::{InMemStorage, MatchOptions, MatchType, TreeBitMap};
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+//------------ Vrp -----------------------------------------------------------
+
+/// A validated ROA payload.
+#[derive(Clone, Debug)]
+pub struct Vrp {
+    pub asn: Asn,
+    pub prefix: Prefix,
+    pub max_len: u8,
+
+    /// The trust anchor the VRP was validated under, if known.
+    pub ta: Option<String>,
+}
+
+impl Vrp {
+    /// Parses a VRP from its fields as they appear in the exports.
+    ///
+    /// The ASN may be given with or without the "AS" prefix.
+    fn from_fields(asn: &str, prefix: &str, max_len: &str, ta: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let asn = Asn::from_str(asn.trim().trim_start_matches("AS"))?;
+        let (addr, len) = prefix.trim().split_once('/').ok_or("missing prefix length")?;
+        let prefix = Prefix::new(Addr::from_str(addr)?, u8::from_str(len)?);
+        let max_len = u8::from_str(max_len.trim())?;
+        let family_len = match prefix.addr {
+            Addr::V4(_) => 32,
+            Addr::V6(_) => 128,
+        };
+        if prefix.len > max_len || max_len > family_len {
+            return Err(format!("invalid max length {} for {}", max_len, prefix).into());
+        }
+        Ok(Vrp {
+            asn,
+            prefix,
+            max_len,
+            ta: ta.map(|ta| ta.trim().to_string()),
+        })
+    }
+
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("asn", self.asn);
+        builder.member_str("prefix", self.prefix);
+        builder.member_raw("maxLength", self.max_len);
+        match &self.ta {
+            Some(ta) => builder.member_str("ta", ta),
+            None => builder.member_raw("ta", "null"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct VrpRecord(Vec<Vrp>);
+
+impl MergeUpdate for VrpRecord {
+    fn merge_update(&mut self, update_record: VrpRecord) -> Result<(), Box<dyn Error>> {
+        self.0.extend(update_record.0);
+        Ok(())
+    }
+}
+
+//------------ RovState ------------------------------------------------------
+
+/// The route origin validation state of a route.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RovState {
+    /// A VRP for the origin ASN covers the route.
+    Valid,
+
+    /// VRPs cover the route, but none is for the origin ASN.
+    InvalidAsn,
+
+    /// A VRP for the origin ASN covers the route, but the route is more
+    /// specific than its max length allows.
+    InvalidLength,
+
+    /// No VRP covers the route.
+    NotFound,
+}
+
+impl fmt::Display for RovState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RovState::Valid => write!(f, "valid"),
+            RovState::InvalidAsn => write!(f, "invalid-asn"),
+            RovState::InvalidLength => write!(f, "invalid-length"),
+            RovState::NotFound => write!(f, "not-found"),
+        }
+    }
+}
+
+//------------ RouteValidation -----------------------------------------------
+
+/// The validation of a route, with the VRPs that cover it.
+#[derive(Clone, Debug)]
+pub struct RouteValidation<'a> {
+    pub origin: Asn,
+    pub state: RovState,
+    pub vrps: Vec<&'a Vrp>,
+}
+
+impl RouteValidation<'_> {
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("originASN", self.origin);
+        builder.member_str("state", self.state);
+        builder.member_array("vrps", |builder| {
+            for vrp in &self.vrps {
+                builder.array_object(|builder| vrp.to_json_builder(builder));
+            }
+        });
+    }
+}
+
+//------------ VrpSet --------------------------------------------------------
+
+pub struct VrpSet {
+    v4: TreeBitMap<InMemStorage<u32, VrpRecord>>,
+    v6: TreeBitMap<InMemStorage<u128, VrpRecord>>,
+    len: usize,
+}
+
+impl Default for VrpSet {
+    fn default() -> Self {
+        VrpSet {
+            v4: TreeBitMap::new(vec![4]),
+            v6: TreeBitMap::new(vec![4]),
+            len: 0,
+        }
+    }
+}
+
+impl fmt::Debug for VrpSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VrpSet").field("len", &self.len).finish()
+    }
+}
+
+impl VrpSet {
+    pub fn insert(&mut self, vrp: Vrp) -> Result<(), Box<dyn Error>> {
+        let len = vrp.prefix.len;
+        match vrp.prefix.addr {
+            Addr::V4(net) => self
+                .v4
+                .insert(RotondaPrefix::new_with_meta(net, len, VrpRecord(vec![vrp])))?,
+            Addr::V6(net) => self
+                .v6
+                .insert(RotondaPrefix::new_with_meta(net, len, VrpRecord(vec![vrp])))?,
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the VRPs whose prefix covers `prefix`.
+    pub fn covering(&self, prefix: Prefix) -> Vec<&Vrp> {
+        let records = match prefix.addr {
+            Addr::V4(net) => covering_records(&self.v4, net, prefix.len),
+            Addr::V6(net) => covering_records(&self.v6, net, prefix.len),
+        };
+        records.into_iter().flat_map(|rec| rec.0.iter()).collect()
+    }
+
+    /// Validates a route for `prefix` originated by `origin`.
+    pub fn validate(&self, prefix: Prefix, origin: Asn) -> RouteValidation<'_> {
+        let vrps = self.covering(prefix);
+        let state = if vrps.is_empty() {
+            RovState::NotFound
+        } else if vrps
+            .iter()
+            .any(|vrp| vrp.asn == origin && vrp.asn != Asn::from(0) && prefix.len <= vrp.max_len)
+        {
+            RovState::Valid
+        } else if vrps.iter().any(|vrp| vrp.asn == origin && vrp.asn != Asn::from(0)) {
+            RovState::InvalidLength
+        } else {
+            RovState::InvalidAsn
+        };
+        RouteValidation { origin, state, vrps }
+    }
+
+    /// Adds the validation of every origin of a route to a bgp meta
+    /// object.
+    ///
+    /// Nothing is added if no VRPs were loaded, since every route would be
+    /// "not-found".
+    pub fn to_json_builder(&self, prefix: Prefix, origins: &[Asn], builder: &mut JsonBuilder) {
+        if self.is_empty() {
+            return;
+        }
+        builder.member_array("rpki", |builder| {
+            for origin in origins {
+                builder.array_object(|builder| {
+                    self.validate(prefix, *origin).to_json_builder(builder);
+                });
+            }
+        });
+    }
+}
+
+/// Returns the records of a prefix and all its less-specifics.
+///
+/// The less-specifics of a query leave out those in the last stride of the
+/// tree that was searched, so this walks up by longest matches instead.
+fn covering_records<AF: AddressFamily>(
+    tree: &TreeBitMap<InMemStorage<AF, VrpRecord>>,
+    net: AF,
+    len: u8,
+) -> Vec<&VrpRecord> {
+    let options = MatchOptions {
+        match_type: MatchType::LongestMatch,
+        include_less_specifics: false,
+        include_more_specifics: false,
+    };
+    let mut records = Vec::new();
+    let mut search = Some((net, len));
+    while let Some((net, len)) = search.take() {
+        if let Some(pfx) = tree.match_prefix(&RotondaPrefix::new(net, len), &options).prefix {
+            records.extend(pfx.meta.as_ref());
+            if pfx.len > 0 {
+                search = Some((pfx.net, pfx.len - 1));
+            }
+        }
+    }
+    records
+}
+
+impl Snapshot for VrpSet {
+    fn write(&self, w: &mut Writer) {
+        let v4 = self.v4.store.prefixes.iter().filter_map(|pfx| pfx.meta.as_ref());
+        let v6 = self.v6.store.prefixes.iter().filter_map(|pfx| pfx.meta.as_ref());
+        w.put_len(self.len);
+        for vrp in v4.chain(v6).flat_map(|rec| rec.0.iter()) {
+            vrp.asn.write(w);
+            vrp.prefix.addr.write(w);
+            vrp.prefix.len.write(w);
+            vrp.max_len.write(w);
+            vrp.ta.write(w);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let mut set = VrpSet::default();
+        for _ in 0..r.take_len()? {
+            let asn = Snapshot::read(r)?;
+            let addr = Snapshot::read(r)?;
+            set.insert(Vrp {
+                asn,
+                prefix: Prefix::new(addr, Snapshot::read(r)?),
+                max_len: Snapshot::read(r)?,
+                ta: Snapshot::read(r)?,
+            })?;
+        }
+        Ok(set)
+    }
+}
+
+//------------ Parsing -------------------------------------------------------
+
+/// Parses the JSON export of Routinator or rpki-client.
+///
+/// Returns the time the export was generated, if it says so, and the
+/// result of parsing every entry of its `roas` array.
+#[allow(clippy::type_complexity)]
+pub(crate) fn parse_json(
+    data: &str,
+) -> Result<(Option<DateTime<FixedOffset>>, Vec<Result<Vrp, Box<dyn Error>>>), Box<dyn Error>> {
+    let json: Value = serde_json::from_str(data)?;
+
+    // Routinator has "generated" as a UNIX timestamp, rpki-client has
+    // "buildtime" as RFC 3339.
+    let metadata = &json["metadata"];
+    let generated = match (metadata["generated"].as_i64(), metadata["buildtime"].as_str()) {
+        (Some(secs), _) => Utc.timestamp_opt(secs, 0).single().map(|time| time.into()),
+        (None, Some(time)) => DateTime::parse_from_rfc3339(time).ok(),
+        _ => None,
+    };
+
+    let roas = json["roas"].as_array().ok_or("missing roas array")?;
+    let vrps = roas
+        .iter()
+        .map(|roa| {
+            let asn = match &roa["asn"] {
+                Value::String(asn) => asn.clone(),
+                Value::Number(asn) => asn.to_string(),
+                _ => return Err("missing asn".into()),
+            };
+            let prefix = roa["prefix"].as_str().ok_or("missing prefix")?;
+            let max_len = roa["maxLength"].as_u64().ok_or("missing maxLength")?;
+            Vrp::from_fields(&asn, prefix, &max_len.to_string(), roa["ta"].as_str())
+        })
+        .collect();
+    Ok((generated, vrps))
+}
+
+/// Parses a record of the CSV export of Routinator or rpki-client.
+///
+/// Both start with the ASN, prefix, max length and trust anchor columns.
+pub(crate) fn parse_csv_record(record: &csv::StringRecord) -> Result<Vrp, Box<dyn Error>> {
+    let field = |idx| record.get(idx).ok_or("missing field");
+    Vrp::from_fields(field(0)?, field(1)?, field(2)?, record.get(3))
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn prefix(s: &str) -> Prefix {
+        let (addr, len) = s.split_once('/').unwrap();
+        Prefix::new(Addr::from_str(addr).unwrap(), u8::from_str(len).unwrap())
+    }
+
+    fn vrp_set(vrps: &[(&str, &str, &str)]) -> VrpSet {
+        let mut set = VrpSet::default();
+        for (asn, prefix, max_len) in vrps {
+            set.insert(Vrp::from_fields(asn, prefix, max_len, None).unwrap()).unwrap();
+        }
+        set
+    }
+
+    fn state(set: &VrpSet, route: &str, origin: u32) -> RovState {
+        set.validate(prefix(route), Asn::from(origin)).state
+    }
+
+    #[test]
+    fn from_fields() {
+        let vrp = Vrp::from_fields("AS13335", " 1.1.1.0/24", "24 ", Some(" apnic")).unwrap();
+        assert_eq!(vrp.asn, Asn::from(13335));
+        assert_eq!(vrp.prefix, prefix("1.1.1.0/24"));
+        assert_eq!(vrp.max_len, 24);
+        assert_eq!(vrp.ta.as_deref(), Some("apnic"));
+        assert_eq!(Vrp::from_fields("13335", "2606:4700::/32", "48", None).unwrap().max_len, 48);
+
+        let err = Vrp::from_fields("AS13335", "1.1.1.0/24", "23", None).unwrap_err();
+        assert_eq!(err.to_string(), "invalid max length 23 for 1.1.1.0/24");
+        assert!(Vrp::from_fields("AS13335", "1.1.1.0/24", "33", None).is_err());
+        assert!(Vrp::from_fields("AS13335", "2606:4700::/32", "129", None).is_err());
+        assert!(Vrp::from_fields("AS13335", "1.1.1.0", "24", None).is_err());
+        assert!(Vrp::from_fields("ASx", "1.1.1.0/24", "24", None).is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let set = vrp_set(&[("AS13335", "1.1.1.0/24", "24"), ("AS3333", "193.0.0.0/21", "22")]);
+        assert_eq!(state(&set, "1.1.1.0/24", 13335), RovState::Valid);
+        assert_eq!(state(&set, "193.0.0.0/22", 3333), RovState::Valid);
+        assert_eq!(state(&set, "1.1.1.0/24", 64500), RovState::InvalidAsn);
+        assert_eq!(state(&set, "193.0.0.0/23", 3333), RovState::InvalidLength);
+        assert_eq!(state(&set, "193.0.0.0/23", 64500), RovState::InvalidAsn);
+        assert_eq!(state(&set, "1.1.0.0/16", 13335), RovState::NotFound);
+        assert_eq!(state(&set, "8.8.8.0/24", 15169), RovState::NotFound);
+
+        let validation = set.validate(prefix("193.0.0.0/23"), Asn::from(3333));
+        assert_eq!(validation.vrps.len(), 1);
+        assert_eq!(validation.vrps[0].prefix, prefix("193.0.0.0/21"));
+    }
+
+    #[test]
+    fn validate_as0() {
+        // An AS0 VRP makes every route it covers invalid, unless another
+        // VRP matches.
+        let set = vrp_set(&[("AS0", "192.0.2.0/24", "32"), ("AS0", "198.51.100.0/24", "24")]);
+        assert_eq!(state(&set, "192.0.2.0/24", 0), RovState::InvalidAsn);
+        assert_eq!(state(&set, "192.0.2.128/25", 64500), RovState::InvalidAsn);
+
+        let set = vrp_set(&[("AS0", "192.0.2.0/24", "32"), ("AS64500", "192.0.2.0/24", "24")]);
+        assert_eq!(state(&set, "192.0.2.0/24", 64500), RovState::Valid);
+        assert_eq!(state(&set, "192.0.2.0/25", 64500), RovState::InvalidLength);
+        assert_eq!(state(&set, "192.0.2.0/24", 64501), RovState::InvalidAsn);
+    }
+
+    #[test]
+    fn covering_vrps_of_all_lengths() {
+        let set = vrp_set(&[
+            ("AS1", "2001:db8::/32", "48"),
+            ("AS2", "2001:db8::/33", "33"),
+            ("AS3", "2001:db8:1::/48", "48"),
+        ]);
+        let mut asns: Vec<_> = set
+            .covering(prefix("2001:db8:1::/48"))
+            .iter()
+            .map(|vrp| vrp.asn)
+            .collect();
+        asns.sort();
+        assert_eq!(asns, [Asn::from(1), Asn::from(2), Asn::from(3)]);
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn parse_routinator_json() {
+        let data = r#"{
+            "metadata": {"generated": 1714564800, "generatedTime": "2024-05-01T12:00:00Z"},
+            "roas": [
+                {"asn": "AS13335", "prefix": "1.1.1.0/24", "maxLength": 24, "ta": "apnic"},
+                {"asn": "AS3333", "prefix": "193.0.0.0/21", "maxLength": 20, "ta": "ripe"}
+            ]
+        }"#;
+        let (generated, vrps) = parse_json(data).unwrap();
+        assert_eq!(generated, DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").ok());
+        assert_eq!(vrps.len(), 2);
+        let vrp = vrps[0].as_ref().unwrap();
+        assert_eq!((vrp.asn, vrp.max_len, vrp.ta.as_deref()), (Asn::from(13335), 24, Some("apnic")));
+        assert!(vrps[1].is_err());
+    }
+
+    #[test]
+    fn parse_rpki_client_json() {
+        let data = r#"{
+            "metadata": {"buildmachine": "example", "buildtime": "2024-05-01T12:00:00+02:00", "vrps": 1},
+            "roas": [
+                {"asn": 13335, "prefix": "2606:4700::/32", "maxLength": 48, "ta": "arin", "expires": 1714600000}
+            ]
+        }"#;
+        let (generated, vrps) = parse_json(data).unwrap();
+        assert_eq!(generated, DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").ok());
+        let vrp = vrps[0].as_ref().unwrap();
+        assert_eq!(vrp.asn, Asn::from(13335));
+        assert_eq!(vrp.prefix, prefix("2606:4700::/32"));
+        assert_eq!(vrp.ta.as_deref(), Some("arin"));
+
+        let (generated, vrps) = parse_json(r#"{"roas": [{"prefix": "1.1.1.0/24", "maxLength": 24}]}"#).unwrap();
+        assert_eq!(generated, None);
+        assert_eq!(vrps[0].as_ref().unwrap_err().to_string(), "missing asn");
+        assert!(parse_json(r#"{"metadata": {}}"#).is_err());
+    }
+
+    fn parse_csv(data: &str) -> Vec<Result<Vrp, Box<dyn Error>>> {
+        csv::Reader::from_reader(data.as_bytes())
+            .records()
+            .map(|record| parse_csv_record(&record.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_routinator_csv() {
+        let vrps = parse_csv("ASN,IP Prefix,Max Length,Trust Anchor\nAS13335,1.1.1.0/24,24,apnic\n");
+        let vrp = vrps[0].as_ref().unwrap();
+        assert_eq!((vrp.asn, vrp.prefix, vrp.max_len), (Asn::from(13335), prefix("1.1.1.0/24"), 24));
+        assert_eq!(vrp.ta.as_deref(), Some("apnic"));
+    }
+
+    #[test]
+    fn parse_rpki_client_csv() {
+        let vrps = parse_csv(
+            "ASN,IP Prefix,Max Length,Trust Anchor,Expires\n\
+             AS3333,2001:67c:2e8::/48,48,ripe,1714600000\n\
+             AS3333,2001:67c:2e8::/48,47,ripe,1714600000\n",
+        );
+        let vrp = vrps[0].as_ref().unwrap();
+        assert_eq!((vrp.asn, vrp.prefix, vrp.max_len), (Asn::from(3333), prefix("2001:67c:2e8::/48"), 48));
+        assert_eq!(vrp.ta.as_deref(), Some("ripe"));
+        assert!(vrps[1].is_err());
+    }
+}
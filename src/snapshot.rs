@@ -0,0 +1,1006 @@
+//! Binary snapshots of a store.
+//!
+//! A snapshot has everything that was loaded into a store, so the store can
+//! be restored from it without parsing the data files again. The file
+//! starts with the magic `ROTOSNAP`, the version of the format and the
+//! length of the payload, and ends with the CRC-32 of the payload. All
+//! numbers are little-endian.
+//!
+//! The built-in special-purpose registries are left out and added again
+//! when a snapshot is loaded.
+
+use crate::bgp::BgpPeer;
+use crate::source::{SourceId, SourceRegistry};
+use crate::{
+    Addr, Asn, AsnArray, AsnDelegation, AsnRegistry, BgpRibPath, BgpRibRecord, DelegationStatus,
+    ExtPrefixRecord, IrrRecord, LoadIssue, LoadReport, PrefixMeta, Rir, RirDelExtRecord,
+    RisWhoisRecord, SourceRecord, Store,
+};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, TimeZone, Utc};
+use rotonda_store::common::{AddressFamily, Prefix as RotondaPrefix};
+use rotonda_store::{InMemStorage, TreeBitMap};
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::hash::Hash;
+use std::io::{Read, Write};
+use std::net::IpAddr;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"ROTOSNAP";
+
+/// The version of the format, bumped whenever the payload changes.
+pub const VERSION: u32 = 3;
+
+/// The length of the magic, version and payload length.
+const HEADER_LEN: usize = 20;
+
+//------------ Snapshot ------------------------------------------------------
+
+/// A value that can be written to a snapshot and read back.
+pub(crate) trait Snapshot: Sized {
+    fn write(&self, w: &mut Writer);
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>>;
+}
+
+//------------ Writer --------------------------------------------------------
+
+pub(crate) struct Writer(Vec<u8>);
+
+impl Writer {
+    pub fn put(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data);
+    }
+
+    /// Writes the number of elements of a collection.
+    pub fn put_len(&mut self, len: usize) {
+        (len as u64).write(self);
+    }
+}
+
+//------------ Reader --------------------------------------------------------
+
+pub(crate) struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.0.len() < len {
+            return Err("snapshot is truncated".into());
+        }
+        let (data, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(data)
+    }
+
+    /// Reads the number of elements of a collection.
+    ///
+    /// Every element takes at least a byte, so a length beyond the end of
+    /// the data is rejected before anything is allocated for it.
+    pub fn take_len(&mut self) -> Result<usize, Box<dyn Error>> {
+        let len = u64::read(self)?;
+        if len > self.0.len() as u64 {
+            return Err(format!("invalid length {} in snapshot", len).into());
+        }
+        Ok(len as usize)
+    }
+
+    /// Returns an error for an unknown tag of an enum.
+    pub fn bad_tag<T>(what: &str, tag: u8) -> Result<T, Box<dyn Error>> {
+        Err(format!("invalid {} {} in snapshot", what, tag).into())
+    }
+}
+
+//------------ Store ---------------------------------------------------------
+
+impl Store {
+    /// Returns whether a file is a snapshot.
+    pub fn is_snapshot(path: &Path) -> bool {
+        let mut magic = [0; 8];
+        fs::File::open(path)
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok()
+            && &magic == MAGIC
+    }
+
+    /// Writes a snapshot of the store.
+    ///
+    /// The snapshot is written next to `path` first and then moved there,
+    /// so a snapshot that is being loaded is never half written.
+    pub fn save_snapshot(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut w = Writer(Vec::with_capacity(HEADER_LEN));
+        w.put(MAGIC);
+        VERSION.write(&mut w);
+        0u64.write(&mut w);
+        self.write_payload(&mut w);
+        let payload_len = (w.0.len() - HEADER_LEN) as u64;
+        w.0[12..HEADER_LEN].copy_from_slice(&payload_len.to_le_bytes());
+        let crc = crc32fast::hash(&w.0[HEADER_LEN..]);
+        crc.write(&mut w);
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&w.0)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Loads a store from a snapshot.
+    pub fn load_snapshot(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read(path)?;
+        if data.len() < HEADER_LEN + 4 || &data[..8] != MAGIC {
+            return Err("not a snapshot".into());
+        }
+        let mut r = Reader(&data[8..HEADER_LEN]);
+        let version = u32::read(&mut r)?;
+        if version != VERSION {
+            return Err(format!("unsupported snapshot version {}, expected {}", version, VERSION).into());
+        }
+        let payload_len = u64::read(&mut r)?;
+        if payload_len != (data.len() - HEADER_LEN - 4) as u64 {
+            return Err("snapshot is truncated".into());
+        }
+        let (payload, crc) = data[HEADER_LEN..].split_at(payload_len as usize);
+        if crc32fast::hash(payload).to_le_bytes() != crc {
+            return Err("snapshot checksum mismatch".into());
+        }
+
+        let mut r = Reader(payload);
+        let mut store = Self::read_payload(&mut r)?;
+        if !r.0.is_empty() {
+            return Err("trailing data in snapshot".into());
+        }
+        let updated = store.updated;
+        store.load_special_purpose()?;
+        store.updated = updated;
+        Ok(store)
+    }
+
+    fn write_payload(&self, w: &mut Writer) {
+        self.updated.write(w);
+        self.strides_v4.write(w);
+        self.strides_v6.write(w);
+        self.sources.write(w);
+        write_tree(&self.v4, w);
+        write_tree(&self.v6, w);
+        self.asns.write(w);
+        self.as_names.write(w);
+        self.as_graph.write(w);
+        self.vrps.write(w);
+        self.irr_objects.write(w);
+        self.iana_v4.write(w);
+        self.iana_v6.write(w);
+        self.delegation_rirs.write(w);
+        self.load_report.write(w);
+    }
+
+    fn read_payload(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let updated = Snapshot::read(r)?;
+        let mut store = Store::with_strides(Snapshot::read(r)?, Snapshot::read(r)?)?;
+        store.updated = updated;
+        store.sources = SourceRegistry::read(r)?;
+        read_tree(&mut store.v4, &store.sources, r)?;
+        read_tree(&mut store.v6, &store.sources, r)?;
+        store.asns = Snapshot::read(r)?;
+        store.as_names = Snapshot::read(r)?;
+        store.as_graph = Snapshot::read(r)?;
+        store.vrps = Snapshot::read(r)?;
+        store.irr_objects = Snapshot::read(r)?;
+        store.iana_v4 = Snapshot::read(r)?;
+        store.iana_v6 = Snapshot::read(r)?;
+        store.delegation_rirs = Snapshot::read(r)?;
+        store.load_report = Snapshot::read(r)?;
+        Ok(store)
+    }
+}
+
+/// Writes the prefixes of a tree with the records of their sources.
+///
+/// Withdrawn prefixes and those with only special-purpose records are left
+/// out.
+fn write_tree<AF: AddressFamily + Snapshot>(tree: &TreeBitMap<InMemStorage<AF, ExtPrefixRecord>>, w: &mut Writer) {
+    let prefixes = tree
+        .store
+        .prefixes
+        .iter()
+        .filter_map(|pfx| Some((pfx, pfx.meta.as_ref()?)))
+        .filter(|(_, meta)| meta.0.iter().any(|rec| !matches!(rec.meta, PrefixMeta::SpecialPurpose(_))))
+        .collect::<Vec<_>>();
+    w.put_len(prefixes.len());
+    for (pfx, meta) in prefixes {
+        pfx.net.write(w);
+        pfx.len.write(w);
+        meta.write(w);
+    }
+}
+
+fn read_tree<AF: AddressFamily + Snapshot>(
+    tree: &mut TreeBitMap<InMemStorage<AF, ExtPrefixRecord>>,
+    sources: &SourceRegistry,
+    r: &mut Reader,
+) -> Result<(), Box<dyn Error>> {
+    for _ in 0..r.take_len()? {
+        let net = AF::read(r)?;
+        let len = u8::read(r)?;
+        if len > AF::BITS {
+            return Err(format!("invalid prefix length {} in snapshot", len).into());
+        }
+        let meta = ExtPrefixRecord::read(r)?;
+        if let Some(rec) = meta.0.iter().find(|rec| !sources.contains(rec.source)) {
+            return Err(format!("unknown source {:?} in snapshot", rec.source).into());
+        }
+        tree.insert(RotondaPrefix::new_with_meta(net, len, meta))?;
+    }
+    Ok(())
+}
+
+//------------ Primitives ----------------------------------------------------
+
+macro_rules! snapshot_int {
+    ($($ty:ty),*) => {
+        $(
+            impl Snapshot for $ty {
+                fn write(&self, w: &mut Writer) {
+                    w.put(&self.to_le_bytes());
+                }
+
+                fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+                    let mut buf = [0; std::mem::size_of::<$ty>()];
+                    buf.copy_from_slice(r.take(std::mem::size_of::<$ty>())?);
+                    Ok(<$ty>::from_le_bytes(buf))
+                }
+            }
+        )*
+    };
+}
+
+snapshot_int!(u8, u16, u32, u64, u128, i32, i64);
+
+impl Snapshot for usize {
+    fn write(&self, w: &mut Writer) {
+        (*self as u64).write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(u64::read(r)? as usize)
+    }
+}
+
+impl Snapshot for bool {
+    fn write(&self, w: &mut Writer) {
+        u8::from(*self).write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        match u8::read(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Reader::bad_tag("bool", tag),
+        }
+    }
+}
+
+impl Snapshot for String {
+    fn write(&self, w: &mut Writer) {
+        w.put_len(self.len());
+        w.put(self.as_bytes());
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let len = r.take_len()?;
+        Ok(std::str::from_utf8(r.take(len)?)?.to_string())
+    }
+}
+
+impl<T: Snapshot> Snapshot for Option<T> {
+    fn write(&self, w: &mut Writer) {
+        match self {
+            Some(value) => {
+                true.write(w);
+                value.write(w);
+            }
+            None => false.write(w),
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        match bool::read(r)? {
+            true => Ok(Some(T::read(r)?)),
+            false => Ok(None),
+        }
+    }
+}
+
+impl<A: Snapshot, B: Snapshot> Snapshot for (A, B) {
+    fn write(&self, w: &mut Writer) {
+        self.0.write(w);
+        self.1.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok((A::read(r)?, B::read(r)?))
+    }
+}
+
+impl<T: Snapshot> Snapshot for Vec<T> {
+    fn write(&self, w: &mut Writer) {
+        w.put_len(self.len());
+        for item in self {
+            item.write(w);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let len = r.take_len()?;
+        let mut items = Vec::with_capacity(len);
+        for _ in 0..len {
+            items.push(T::read(r)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Snapshot + Ord> Snapshot for BTreeSet<T> {
+    fn write(&self, w: &mut Writer) {
+        w.put_len(self.len());
+        for item in self {
+            item.write(w);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        (0..r.take_len()?).map(|_| T::read(r)).collect()
+    }
+}
+
+impl<K: Snapshot + Eq + Hash, V: Snapshot> Snapshot for HashMap<K, V> {
+    fn write(&self, w: &mut Writer) {
+        w.put_len(self.len());
+        for (key, value) in self {
+            key.write(w);
+            value.write(w);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let len = r.take_len()?;
+        let mut map = HashMap::with_capacity(len);
+        for _ in 0..len {
+            map.insert(K::read(r)?, V::read(r)?);
+        }
+        Ok(map)
+    }
+}
+
+impl Snapshot for IpAddr {
+    fn write(&self, w: &mut Writer) {
+        match self {
+            IpAddr::V4(addr) => {
+                4u8.write(w);
+                u32::from(*addr).write(w);
+            }
+            IpAddr::V6(addr) => {
+                6u8.write(w);
+                u128::from(*addr).write(w);
+            }
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        match u8::read(r)? {
+            4 => Ok(IpAddr::V4(u32::read(r)?.into())),
+            6 => Ok(IpAddr::V6(u128::read(r)?.into())),
+            tag => Reader::bad_tag("address family", tag),
+        }
+    }
+}
+
+impl Snapshot for NaiveDate {
+    fn write(&self, w: &mut Writer) {
+        self.num_days_from_ce().write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let days = i32::read(r)?;
+        NaiveDate::from_num_days_from_ce_opt(days).ok_or_else(|| format!("invalid date {} in snapshot", days).into())
+    }
+}
+
+impl Snapshot for DateTime<Utc> {
+    fn write(&self, w: &mut Writer) {
+        self.timestamp().write(w);
+        self.timestamp_subsec_nanos().write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let (secs, nanos) = (i64::read(r)?, u32::read(r)?);
+        Utc.timestamp_opt(secs, nanos)
+            .single()
+            .ok_or_else(|| format!("invalid time {} in snapshot", secs).into())
+    }
+}
+
+impl Snapshot for DateTime<FixedOffset> {
+    fn write(&self, w: &mut Writer) {
+        self.offset().local_minus_utc().write(w);
+        self.with_timezone(&Utc).write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let offset = i32::read(r)?;
+        let offset = FixedOffset::east_opt(offset).ok_or_else(|| format!("invalid offset {} in snapshot", offset))?;
+        Ok(DateTime::<Utc>::read(r)?.with_timezone(&offset))
+    }
+}
+
+//------------ Records -------------------------------------------------------
+
+impl Snapshot for Asn {
+    fn write(&self, w: &mut Writer) {
+        self.0.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(Asn(u32::read(r)?))
+    }
+}
+
+impl Snapshot for AsnArray {
+    fn write(&self, w: &mut Writer) {
+        self.0.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(AsnArray(Snapshot::read(r)?))
+    }
+}
+
+impl Snapshot for Addr {
+    fn write(&self, w: &mut Writer) {
+        match self {
+            Addr::V4(addr) => IpAddr::from(std::net::Ipv4Addr::from(*addr)).write(w),
+            Addr::V6(addr) => IpAddr::from(std::net::Ipv6Addr::from(*addr)).write(w),
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        match IpAddr::read(r)? {
+            IpAddr::V4(addr) => Ok(Addr::V4(addr.into())),
+            IpAddr::V6(addr) => Ok(Addr::V6(addr.into())),
+        }
+    }
+}
+
+impl Snapshot for Rir {
+    fn write(&self, w: &mut Writer) {
+        let tag: u8 = match self {
+            Rir::Afrinic => 0,
+            Rir::Apnic => 1,
+            Rir::Arin => 2,
+            Rir::Lacnic => 3,
+            Rir::RipeNcc => 4,
+            Rir::Unknown => 5,
+        };
+        tag.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        match u8::read(r)? {
+            0 => Ok(Rir::Afrinic),
+            1 => Ok(Rir::Apnic),
+            2 => Ok(Rir::Arin),
+            3 => Ok(Rir::Lacnic),
+            4 => Ok(Rir::RipeNcc),
+            5 => Ok(Rir::Unknown),
+            tag => Reader::bad_tag("RIR", tag),
+        }
+    }
+}
+
+impl Snapshot for DelegationStatus {
+    fn write(&self, w: &mut Writer) {
+        let tag: u8 = match self {
+            DelegationStatus::Allocated => 0,
+            DelegationStatus::Assigned => 1,
+        };
+        tag.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        match u8::read(r)? {
+            0 => Ok(DelegationStatus::Allocated),
+            1 => Ok(DelegationStatus::Assigned),
+            tag => Reader::bad_tag("delegation status", tag),
+        }
+    }
+}
+
+impl Snapshot for RirDelExtRecord {
+    fn write(&self, w: &mut Writer) {
+        self.group_id.write(w);
+        self.rir.write(w);
+        self.country.write(w);
+        self.date.write(w);
+        self.status.write(w);
+        self.range.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(RirDelExtRecord {
+            group_id: Snapshot::read(r)?,
+            rir: Snapshot::read(r)?,
+            country: Snapshot::read(r)?,
+            date: Snapshot::read(r)?,
+            status: Snapshot::read(r)?,
+            range: Snapshot::read(r)?,
+        })
+    }
+}
+
+impl Snapshot for RisWhoisRecord {
+    fn write(&self, w: &mut Writer) {
+        self.origin_asns.write(w);
+        self.seen_by_peers.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(RisWhoisRecord {
+            origin_asns: Snapshot::read(r)?,
+            seen_by_peers: Snapshot::read(r)?,
+        })
+    }
+}
+
+impl Snapshot for BgpPeer {
+    fn write(&self, w: &mut Writer) {
+        self.addr.write(w);
+        self.asn.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(BgpPeer {
+            addr: Snapshot::read(r)?,
+            asn: Snapshot::read(r)?,
+        })
+    }
+}
+
+impl Snapshot for BgpRibPath {
+    fn write(&self, w: &mut Writer) {
+        self.as_path.write(w);
+        self.peers.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(BgpRibPath {
+            as_path: Snapshot::read(r)?,
+            peers: Snapshot::read(r)?,
+        })
+    }
+}
+
+impl Snapshot for BgpRibRecord {
+    fn write(&self, w: &mut Writer) {
+        self.origin_asns.write(w);
+        self.paths.write(w);
+        self.live.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(BgpRibRecord {
+            origin_asns: Snapshot::read(r)?,
+            paths: Snapshot::read(r)?,
+            live: Snapshot::read(r)?,
+        })
+    }
+}
+
+impl Snapshot for IrrRecord {
+    fn write(&self, w: &mut Writer) {
+        self.origin_asns.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(IrrRecord {
+            origin_asns: Snapshot::read(r)?,
+        })
+    }
+}
+
+/// The records of a prefix.
+///
+/// The built-in special-purpose records are left out, they are added again
+/// when the snapshot is loaded.
+impl Snapshot for ExtPrefixRecord {
+    fn write(&self, w: &mut Writer) {
+        fn put<T: Snapshot>(w: &mut Writer, source: SourceId, tag: u8, meta: &T) {
+            source.write(w);
+            tag.write(w);
+            meta.write(w);
+        }
+
+        let mut records = Writer(Vec::new());
+        let mut count = 0;
+        for rec in &self.0 {
+            match &rec.meta {
+                PrefixMeta::Delegation(meta) => put(&mut records, rec.source, 0, meta),
+                PrefixMeta::RisWhois(meta) => put(&mut records, rec.source, 1, meta),
+                PrefixMeta::Rib(meta) => put(&mut records, rec.source, 2, meta),
+                PrefixMeta::Irr(meta) => put(&mut records, rec.source, 3, meta),
+                PrefixMeta::IanaAllocation(meta) => put(&mut records, rec.source, 5, meta),
+                PrefixMeta::SpecialPurpose(_) => continue,
+            }
+            count += 1;
+        }
+        w.put_len(count);
+        w.put(&records.0);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let len = r.take_len()?;
+        let mut records = Vec::with_capacity(len);
+        for _ in 0..len {
+            let source = Snapshot::read(r)?;
+            let meta = match u8::read(r)? {
+                0 => PrefixMeta::Delegation(Snapshot::read(r)?),
+                1 => PrefixMeta::RisWhois(Snapshot::read(r)?),
+                2 => PrefixMeta::Rib(Snapshot::read(r)?),
+                3 => PrefixMeta::Irr(Snapshot::read(r)?),
+                5 => PrefixMeta::IanaAllocation(Snapshot::read(r)?),
+                tag => return Reader::bad_tag("record kind", tag),
+            };
+            records.push(SourceRecord { source, meta });
+        }
+        Ok(ExtPrefixRecord(records))
+    }
+}
+
+impl Snapshot for AsnRegistry {
+    fn write(&self, w: &mut Writer) {
+        w.put_len(self.delegations.len());
+        for delegation in self.delegations.values() {
+            delegation.first.write(w);
+            delegation.last.write(w);
+            delegation.record.write(w);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let mut registry = AsnRegistry::default();
+        for _ in 0..r.take_len()? {
+            let delegation = AsnDelegation {
+                first: Snapshot::read(r)?,
+                last: Snapshot::read(r)?,
+                record: Snapshot::read(r)?,
+            };
+            registry.delegations.insert(delegation.first.0, delegation);
+        }
+        Ok(registry)
+    }
+}
+
+impl Snapshot for LoadReport {
+    fn write(&self, w: &mut Writer) {
+        w.put_len(self.issues.len());
+        for issue in &self.issues {
+            issue.file.write(w);
+            issue.line.write(w);
+            issue.reason.write(w);
+            issue.raw.write(w);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let mut report = LoadReport::default();
+        for _ in 0..r.take_len()? {
+            report.push(LoadIssue {
+                file: Snapshot::read(r)?,
+                line: Snapshot::read(r)?,
+                reason: Snapshot::read(r)?,
+                raw: Snapshot::read(r)?,
+            });
+        }
+        Ok(report)
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asnames::AsName;
+    use crate::asrel::AsRelation;
+    use crate::bgp::{AsPath, BgpUpdate};
+    use crate::iana::{IanaAllocationRecord, IanaStatus};
+    use crate::rpki::Vrp;
+    use crate::source::SourceType;
+    use crate::verify::Verification;
+    use crate::Prefix;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("roto-api-{}-{}.snapshot", name, std::process::id()))
+    }
+
+    fn addr(s: &str) -> Addr {
+        Addr::from_str(s).unwrap()
+    }
+
+    fn delegation(rir: Rir, range: Option<(Addr, Addr)>) -> RirDelExtRecord {
+        RirDelExtRecord {
+            group_id: "ORG-TEST1".into(),
+            rir,
+            country: Some("NL".into()),
+            date: NaiveDate::from_ymd_opt(1993, 9, 1),
+            status: DelegationStatus::Allocated,
+            range,
+        }
+    }
+
+    /// Returns a store with something of everything a snapshot keeps.
+    fn full_store() -> Store {
+        let mut store = Store::with_strides(vec![8, 8, 8, 8], vec![4; 32]).unwrap();
+
+        let ripe = store.sources.register(SourceType::RirAlloc, "ripencc");
+        store.sources.set_serial(SourceType::RirAlloc, "ripencc", 20240501);
+        let updated = DateTime::parse_from_rfc3339("2024-05-01T12:00:00+02:00").unwrap();
+        store.sources.set_last_updated(SourceType::RirAlloc, "ripencc", updated);
+        store.sources.set_verification(
+            SourceType::RirAlloc,
+            "ripencc",
+            Verification::Passed {
+                md5: true,
+                signed_by: Some("060DF44741C57444".into()),
+            },
+        );
+        let range = Some((addr("193.0.0.0"), addr("193.0.11.255")));
+        for (net, len) in [("193.0.0.0", 21), ("193.0.8.0", 22)] {
+            let rec = ExtPrefixRecord::new(ripe, delegation(Rir::RipeNcc, range));
+            store.insert_record(addr(net), len, rec).unwrap();
+        }
+        let range = Prefix::new(addr("2001:67c::"), 32).range();
+        let rec = ExtPrefixRecord::new(ripe, delegation(Rir::RipeNcc, Some(range)));
+        store.insert_record(addr("2001:67c::"), 32, rec).unwrap();
+        store.asns.insert(3333, 1, delegation(Rir::RipeNcc, None)).unwrap();
+        store.asns.insert(196608, 1024, delegation(Rir::RipeNcc, None)).unwrap();
+        store.delegation_rirs.push(Rir::RipeNcc);
+
+        let riswhois = store.sources.register(SourceType::Bgp, "riswhois-v4");
+        let rec = RisWhoisRecord {
+            origin_asns: AsnArray(vec![Asn(3333), Asn(1103)]),
+            seen_by_peers: vec![Some(300), None],
+        };
+        store.insert_record(addr("193.0.0.0"), 21, ExtPrefixRecord::new(riswhois, rec)).unwrap();
+
+        let irr = store.sources.register(SourceType::Irr, "ripe");
+        let rec = IrrRecord {
+            origin_asns: AsnArray(vec![Asn(3333)]),
+        };
+        store.insert_record(addr("193.0.0.0"), 21, ExtPrefixRecord::new(irr, rec)).unwrap();
+        store.irr_objects = 1;
+
+        let iana = store.sources.register(SourceType::IanaAlloc, "iana");
+        let rec = IanaAllocationRecord {
+            designation: "RIPE NCC".into(),
+            date: "1993-05".into(),
+            whois: Some("whois.ripe.net".into()),
+            status: IanaStatus::Allocated,
+            rir: Some(Rir::RipeNcc),
+        };
+        store.insert_record(addr("193.0.0.0"), 8, ExtPrefixRecord::new(iana, rec)).unwrap();
+        store.iana_v4 = true;
+        store.load_special_purpose().unwrap();
+
+        let peer = BgpPeer {
+            addr: IpAddr::V6(Ipv6Addr::from_str("2001:db8::1").unwrap()),
+            asn: Asn(64500),
+        };
+        let announce = |net: &str, len, live| BgpUpdate::Announce {
+            source: if live { "bmp/192.0.2.254/2001:db8::1".into() } else { "rrc00".into() },
+            peer,
+            net: addr(net),
+            len,
+            as_path: AsPath {
+                asns: vec![Asn(64500), Asn(3333)],
+                origin_asns: vec![Asn(3333)],
+            },
+        };
+        store
+            .apply_bgp_updates(vec![announce("193.0.10.0", 23, true), announce("2001:67c:2e8::", 48, false)])
+            .unwrap();
+
+        store.as_names.insert(
+            Asn(3333),
+            AsName {
+                name: "RIPE-NCC-AS".into(),
+                org_name: Some("Reseaux IP Europeens Network Coordination Centre (RIPE NCC)".into()),
+                country: Some("NL".into()),
+                source: "ripe",
+            },
+        );
+        store.as_names.insert(
+            Asn(1103),
+            AsName {
+                name: "SURFNET-NL".into(),
+                org_name: None,
+                country: None,
+                source: "caida",
+            },
+        );
+        store.as_graph.insert_line("1299|3333|-1").unwrap();
+        store.as_graph.insert_line("3333|1103|0").unwrap();
+
+        let vrps = [(3333, "193.0.0.0", 21, 24, Some("ripe")), (0, "2001:67c::", 32, 32, None)];
+        for (asn, net, len, max_len, ta) in vrps {
+            store
+                .vrps
+                .insert(Vrp {
+                    asn: Asn(asn),
+                    prefix: Prefix::new(addr(net), len),
+                    max_len,
+                    ta: ta.map(Into::into),
+                })
+                .unwrap();
+        }
+
+        store.load_report.push(LoadIssue {
+            file: "ripencc.txt".into(),
+            line: 7,
+            reason: "invalid digit found in string".into(),
+            raw: "ripencc|NL|ipv4|193.0.0.0|x|19930901|allocated".into(),
+        });
+        store
+    }
+
+    /// Returns everything that can be asked of a store, comparable across
+    /// stores.
+    fn dump(store: &Store) -> Vec<String> {
+        let mut prefixes = Vec::new();
+        for pfx in &store.v4.store.prefixes {
+            prefixes.push(format!("{}/{} {:?}", Ipv4Addr::from(pfx.net), pfx.len, pfx.meta));
+        }
+        for pfx in &store.v6.store.prefixes {
+            prefixes.push(format!("{}/{} {:?}", Ipv6Addr::from(pfx.net), pfx.len, pfx.meta));
+        }
+        prefixes.sort();
+
+        let mut lines = vec![
+            format!("{:?}", store.updated),
+            format!("{:?} {:?}", store.strides_v4, store.strides_v6),
+            format!("{:?}", store.sources.iter().collect::<Vec<_>>()),
+            format!("{:?}", store.asns.iter().collect::<Vec<_>>()),
+            format!("{} {} {}", store.irr_objects, store.iana_v4, store.iana_v6),
+            format!("{:?}", store.delegation_rirs),
+            format!("{:?}", store.load_report.iter().collect::<Vec<_>>()),
+        ];
+        lines.extend(prefixes);
+        for asn in [Asn(3333), Asn(1103), Asn(1299)] {
+            lines.push(format!("{:?}", store.as_names.get(asn)));
+            for relation in [
+                AsRelation::Upstreams,
+                AsRelation::Downstreams,
+                AsRelation::Peers,
+                AsRelation::CustomerCone,
+            ] {
+                let mut related = store.as_graph.related(asn, relation);
+                related.sort();
+                lines.push(format!("{} {:?}", relation, related));
+            }
+        }
+        for (net, len) in [("193.0.10.0", 23), ("193.0.10.0", 25), ("2001:67c:2e8::", 48)] {
+            let prefix = Prefix::new(addr(net), len);
+            lines.push(format!("{:?}", store.vrps.validate(prefix, Asn(3333))));
+        }
+        lines
+    }
+
+    #[test]
+    fn round_trip() {
+        let store = full_store();
+        let path = temp_path("round-trip");
+        store.save_snapshot(&path).unwrap();
+        assert!(Store::is_snapshot(&path));
+        let loaded = Store::load_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let (expected, actual) = (dump(&store), dump(&loaded));
+        for kind in ["Delegation", "RisWhois", "Rib", "Irr", "IanaAllocation", "SpecialPurpose"] {
+            assert!(expected.iter().any(|line| line.contains(&format!("{}(", kind))), "no {} record", kind);
+        }
+        assert_eq!(expected, actual);
+        assert_eq!(store.vrps.len(), loaded.vrps.len());
+    }
+
+    #[test]
+    fn withdrawn_prefixes_are_left_out() {
+        let mut store = full_store();
+        store
+            .apply_bgp_updates(vec![BgpUpdate::Withdraw {
+                source: "bmp/192.0.2.254/2001:db8::1".into(),
+                net: addr("193.0.10.0"),
+                len: 23,
+            }])
+            .unwrap();
+        let path = temp_path("withdrawn");
+        store.save_snapshot(&path).unwrap();
+        let loaded = Store::load_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.tombstones, 0);
+        assert!(!dump(&loaded).iter().any(|line| line.starts_with("193.0.10.0/23")));
+    }
+
+    /// Saves a snapshot, lets `mangle` loose on the file and returns the
+    /// error loading it.
+    fn load_mangled(name: &str, mangle: impl FnOnce(&mut Vec<u8>)) -> String {
+        let path = temp_path(name);
+        full_store().save_snapshot(&path).unwrap();
+        let mut data = fs::read(&path).unwrap();
+        mangle(&mut data);
+        fs::write(&path, data).unwrap();
+        let res = Store::load_snapshot(&path);
+        fs::remove_file(&path).unwrap();
+        res.err().expect("mangled snapshot was loaded").to_string()
+    }
+
+    #[test]
+    fn flipped_crc_byte_is_rejected() {
+        let err = load_mangled("crc", |data| *data.last_mut().unwrap() ^= 0x01);
+        assert_eq!(err, "snapshot checksum mismatch");
+        let err = load_mangled("payload", |data| data[HEADER_LEN + 3] ^= 0x80);
+        assert_eq!(err, "snapshot checksum mismatch");
+    }
+
+    #[test]
+    fn short_file_is_rejected() {
+        let err = load_mangled("short", |data| {
+            data.pop();
+        });
+        assert_eq!(err, "snapshot is truncated");
+        let err = load_mangled("header", |data| data.truncate(HEADER_LEN));
+        assert_eq!(err, "not a snapshot");
+    }
+
+    #[test]
+    fn other_version_is_rejected() {
+        let err = load_mangled("version", |data| data[8..12].copy_from_slice(&2u32.to_le_bytes()));
+        assert_eq!(err, format!("unsupported snapshot version 2, expected {}", VERSION));
+    }
+
+    #[test]
+    fn bad_tag_is_rejected() {
+        // A record of an IPv4 prefix with an unknown record kind.
+        let mut w = Writer(Vec::new());
+        w.put_len(1);
+        let source = Store::default().sources.register(SourceType::Bgp, "rrc00");
+        source.write(&mut w);
+        4u8.write(&mut w);
+        let err = ExtPrefixRecord::read(&mut Reader(&w.0)).unwrap_err();
+        assert_eq!(err.to_string(), "invalid record kind 4 in snapshot");
+
+        let err = Rir::read(&mut Reader(&[6])).unwrap_err();
+        assert_eq!(err.to_string(), "invalid RIR 6 in snapshot");
+        let err = Option::<u8>::read(&mut Reader(&[2, 0])).unwrap_err();
+        assert_eq!(err.to_string(), "invalid bool 2 in snapshot");
+    }
+
+    #[test]
+    fn special_purpose_records_are_not_written() {
+        let mut store = Store::default();
+        store.load_special_purpose().unwrap();
+        let meta = store
+            .v4
+            .store
+            .prefixes
+            .iter()
+            .find_map(|pfx| pfx.meta.as_ref())
+            .unwrap();
+        let mut w = Writer(Vec::new());
+        meta.write(&mut w);
+        assert_eq!(w.0, 0u64.to_le_bytes());
+    }
+}
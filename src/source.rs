@@ -0,0 +1,368 @@
+//! The data sets the store is built from.
+//!
+//! Every data set that was loaded is registered as a source with a type and
+//! an ID, e.g. `rir-alloc`/`ripe` or `bgp`/`rrc00`, and, if known, a serial.
+//! What a source has for a prefix is a `PrefixRecord` tagged with the
+//! `SourceId` of the source, so the meta objects of a prefix and the sources
+//! of `/status` are rendered by iterating over the sources rather than
+//! naming each of them.
+
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::verify::Verification;
+use crate::{Asn, JsonBuilder, PrefixMeta};
+use chrono::{DateTime, FixedOffset};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+//------------ SourceType ----------------------------------------------------
+
+/// The kind of data a source provides.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SourceType {
+    /// Delegations from the delegated-extended file of a RIR.
+    RirAlloc,
+
+    /// Routes seen in BGP, from riswhois, MRT RIB dumps or a BMP feed.
+    Bgp,
+
+    /// Route objects of an IRR database.
+    Irr,
+
+    /// Validated ROA payloads.
+    Rpki,
+
+    /// The built-in IANA special-purpose registries.
+    IanaSpecial,
+
+    /// The IANA top-level allocations.
+    IanaAlloc,
+
+    /// Names of ASNs.
+    AsName,
+
+    /// Relationships between ASes.
+    AsRel,
+}
+
+impl fmt::Display for SourceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceType::RirAlloc => write!(f, "rir-alloc"),
+            SourceType::Bgp => write!(f, "bgp"),
+            SourceType::Irr => write!(f, "irr"),
+            SourceType::Rpki => write!(f, "rpki"),
+            SourceType::IanaSpecial => write!(f, "iana-special"),
+            SourceType::IanaAlloc => write!(f, "iana-alloc"),
+            SourceType::AsName => write!(f, "as-name"),
+            SourceType::AsRel => write!(f, "as-rel"),
+        }
+    }
+}
+
+impl FromStr for SourceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rir-alloc" => Ok(SourceType::RirAlloc),
+            "bgp" => Ok(SourceType::Bgp),
+            "irr" => Ok(SourceType::Irr),
+            "rpki" => Ok(SourceType::Rpki),
+            "iana-special" => Ok(SourceType::IanaSpecial),
+            "iana-alloc" => Ok(SourceType::IanaAlloc),
+            "as-name" => Ok(SourceType::AsName),
+            "as-rel" => Ok(SourceType::AsRel),
+            _ => Err(format!("unknown source type '{}'", s)),
+        }
+    }
+}
+
+impl Snapshot for SourceType {
+    fn write(&self, w: &mut Writer) {
+        let tag: u8 = match self {
+            SourceType::RirAlloc => 0,
+            SourceType::Bgp => 1,
+            SourceType::Irr => 2,
+            SourceType::Rpki => 3,
+            SourceType::IanaSpecial => 4,
+            SourceType::IanaAlloc => 5,
+            SourceType::AsName => 6,
+            SourceType::AsRel => 7,
+        };
+        tag.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        match u8::read(r)? {
+            0 => Ok(SourceType::RirAlloc),
+            1 => Ok(SourceType::Bgp),
+            2 => Ok(SourceType::Irr),
+            3 => Ok(SourceType::Rpki),
+            4 => Ok(SourceType::IanaSpecial),
+            5 => Ok(SourceType::IanaAlloc),
+            6 => Ok(SourceType::AsName),
+            7 => Ok(SourceType::AsRel),
+            tag => Reader::bad_tag("source type", tag),
+        }
+    }
+}
+
+//------------ SourceId ------------------------------------------------------
+
+/// The index of a source in the `SourceRegistry` of a store.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SourceId(u32);
+
+impl Snapshot for SourceId {
+    fn write(&self, w: &mut Writer) {
+        self.0.write(w);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        Ok(SourceId(Snapshot::read(r)?))
+    }
+}
+
+//------------ SourceInfo ----------------------------------------------------
+
+/// A loaded data set.
+#[derive(Clone, Debug)]
+pub struct SourceInfo {
+    pub source_type: SourceType,
+
+    /// The ID of the source, unique among the sources of its type.
+    pub id: String,
+
+    /// The serial the data itself has, e.g. in the version line of a
+    /// delegated-extended file.
+    pub serial: Option<u64>,
+
+    /// The time the data was last updated, e.g. from the Last-Modified
+    /// header of its download.
+    pub last_updated: Option<DateTime<FixedOffset>>,
+
+    /// The outcome of checking the checksum and signature of the data, if
+    /// they were checked.
+    pub verification: Option<Verification>,
+}
+
+impl SourceInfo {
+    /// Returns the serial of the data, or the time it was last updated as
+    /// a UNIX timestamp if it doesn't have one.
+    pub fn serial(&self) -> Option<u64> {
+        self.serial
+            .or_else(|| self.last_updated.map(|time| time.timestamp() as u64))
+    }
+
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_str("type", self.source_type);
+        builder.member_str("id", &self.id);
+        match self.serial() {
+            Some(serial) => builder.member_raw("serial", serial),
+            None => builder.member_raw("serial", "null"),
+        }
+        match self.last_updated {
+            Some(time) => builder.member_str("lastUpdated", time.format("%+")),
+            None => builder.member_raw("lastUpdated", "null"),
+        }
+        match &self.verification {
+            Some(verification) => builder.member_object("verification", |builder| verification.to_json_builder(builder)),
+            None => builder.member_raw("verification", "null"),
+        }
+    }
+}
+
+//------------ SourceRegistry ------------------------------------------------
+
+/// All sources of a store, in the order they were registered.
+#[derive(Clone, Debug, Default)]
+pub struct SourceRegistry {
+    sources: Vec<SourceInfo>,
+}
+
+impl SourceRegistry {
+    /// Returns the ID of a source, registering it if it is new.
+    pub fn register(&mut self, source_type: SourceType, id: &str) -> SourceId {
+        if let Some(source) = self.find(source_type, id) {
+            return source;
+        }
+        self.sources.push(SourceInfo {
+            source_type,
+            id: id.to_string(),
+            serial: None,
+            last_updated: None,
+            verification: None,
+        });
+        SourceId(self.sources.len() as u32 - 1)
+    }
+
+    /// Returns the ID of a source, if it was registered.
+    pub fn find(&self, source_type: SourceType, id: &str) -> Option<SourceId> {
+        self.sources
+            .iter()
+            .position(|source| source.source_type == source_type && source.id == id)
+            .map(|idx| SourceId(idx as u32))
+    }
+
+    /// Returns whether a source was registered with this registry.
+    pub fn contains(&self, source: SourceId) -> bool {
+        (source.0 as usize) < self.sources.len()
+    }
+
+    /// Returns a source.
+    ///
+    /// Panics if the source wasn't registered with this registry.
+    pub fn get(&self, source: SourceId) -> &SourceInfo {
+        &self.sources[source.0 as usize]
+    }
+
+    /// Sets the serial of the data of a source, registering it if it is
+    /// new.
+    ///
+    /// A source loaded from several files keeps the highest serial.
+    pub fn set_serial(&mut self, source_type: SourceType, id: &str, serial: u64) {
+        let source = self.register(source_type, id);
+        let source = &mut self.sources[source.0 as usize];
+        if source.serial.is_none_or(|last| last < serial) {
+            source.serial = Some(serial);
+        }
+    }
+
+    /// Sets the time a source was last updated, registering it if it is
+    /// new.
+    ///
+    /// A source loaded from several files, like the IANA registries with
+    /// one file per address family, keeps the latest time.
+    pub fn set_last_updated(&mut self, source_type: SourceType, id: &str, time: DateTime<FixedOffset>) {
+        let source = self.register(source_type, id);
+        let source = &mut self.sources[source.0 as usize];
+        if source.last_updated.is_none_or(|last| last < time) {
+            source.last_updated = Some(time);
+        }
+    }
+
+    /// Sets the outcome of checking the data of a source, registering it
+    /// if it is new.
+    ///
+    /// A source loaded from several files keeps the first failure.
+    pub fn set_verification(&mut self, source_type: SourceType, id: &str, verification: Verification) {
+        let source = self.register(source_type, id);
+        let source = &mut self.sources[source.0 as usize];
+        if !source.verification.as_ref().is_some_and(Verification::is_failed) {
+            source.verification = Some(verification);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SourceId, &SourceInfo)> {
+        self.sources
+            .iter()
+            .enumerate()
+            .map(|(idx, source)| (SourceId(idx as u32), source))
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Adds the `sources` array of `/status`.
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        builder.member_array("sources", |builder| {
+            for source in &self.sources {
+                builder.array_object(|builder| source.to_json_builder(builder));
+            }
+        });
+    }
+}
+
+impl Snapshot for SourceRegistry {
+    fn write(&self, w: &mut Writer) {
+        w.put_len(self.sources.len());
+        for source in &self.sources {
+            source.source_type.write(w);
+            source.id.write(w);
+            source.serial.write(w);
+            source.last_updated.write(w);
+            source.verification.write(w);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        let mut registry = SourceRegistry::default();
+        for _ in 0..r.take_len()? {
+            registry.sources.push(SourceInfo {
+                source_type: Snapshot::read(r)?,
+                id: Snapshot::read(r)?,
+                serial: Snapshot::read(r)?,
+                last_updated: Snapshot::read(r)?,
+                verification: Snapshot::read(r)?,
+            });
+        }
+        Ok(registry)
+    }
+}
+
+//------------ SourceFilter --------------------------------------------------
+
+/// The sources a search shows the records of.
+///
+/// A source is shown if its type and ID are in the include lists, if
+/// given, and not in the exclude lists.
+#[derive(Clone, Debug, Default)]
+pub struct SourceFilter {
+    pub include_types: Option<Vec<SourceType>>,
+    pub exclude_types: Vec<SourceType>,
+    pub include_ids: Option<Vec<String>>,
+    pub exclude_ids: Vec<String>,
+}
+
+impl SourceFilter {
+    /// Returns whether the records of a source are shown.
+    pub fn matches(&self, source_type: SourceType, id: &str) -> bool {
+        self.include_types
+            .as_ref()
+            .is_none_or(|types| types.contains(&source_type))
+            && !self.exclude_types.contains(&source_type)
+            && self
+                .include_ids
+                .as_ref()
+                .is_none_or(|ids| ids.iter().any(|include| include == id))
+            && !self.exclude_ids.iter().any(|exclude| exclude == id)
+    }
+}
+
+//------------ PrefixRecord --------------------------------------------------
+
+/// What a source has for a single prefix.
+pub trait PrefixRecord: Sized {
+    /// The type of the sources that have records of this kind.
+    const SOURCE_TYPE: SourceType;
+
+    /// Merges a later record of the same source for the same prefix.
+    fn merge(&mut self, other: Self);
+
+    /// Returns whether the source has nothing for the prefix anymore.
+    ///
+    /// Empty records are dropped after merging.
+    fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Returns the origin ASNs the source has for the prefix.
+    fn origin_asns(&self) -> &[Asn] {
+        &[]
+    }
+
+    /// Adds the fields of the record to its meta object.
+    ///
+    /// The `sourceType` and `sourceID` members are added by the store.
+    fn to_json_builder(&self, builder: &mut JsonBuilder);
+
+    fn into_meta(self) -> PrefixMeta;
+
+    fn from_meta(meta: &PrefixMeta) -> Option<&Self>;
+}
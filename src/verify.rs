@@ -0,0 +1,234 @@
+//! Verifying delegated-extended files.
+//!
+//! The RIRs publish an `.md5` file with the MD5 checksum and an `.asc` file
+//! with a detached OpenPGP signature next to each of their delegated files.
+//! If configured, a file is only loaded if it matches its checksum and was
+//! signed with the key of its RIR. Downloaded files are checked before they
+//! replace the previous one in the data dir, files on disk before loading.
+
+use crate::openpgp;
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::JsonBuilder;
+use md5::{Digest, Md5};
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//------------ Verify --------------------------------------------------------
+
+/// The checks a delegated-extended file has to pass.
+#[derive(Clone, Debug)]
+pub struct Verify {
+    /// Whether the file must match the checksum in its `.md5` file.
+    pub md5: bool,
+
+    /// The OpenPGP key of the RIR. If set, the file must have a valid
+    /// signature by this key in its `.asc` file.
+    pub key: Option<PathBuf>,
+}
+
+impl Verify {
+    /// Returns whether there is anything to check.
+    pub fn is_enabled(&self) -> bool {
+        self.md5 || self.key.is_some()
+    }
+
+    /// Checks the contents of a file against the contents of its `.md5`
+    /// and `.asc` files.
+    pub fn check(
+        &self,
+        data: &[u8],
+        md5: Option<&[u8]>,
+        signature: Option<&[u8]>,
+    ) -> Result<Verification, String> {
+        if self.md5 {
+            let expected = md5
+                .and_then(parse_md5)
+                .ok_or("missing or invalid MD5 checksum")?;
+            let actual = format!("{:x}", Md5::digest(data));
+            if actual != expected {
+                return Err(format!("MD5 checksum mismatch, expected {} but got {}", expected, actual));
+            }
+        }
+        let signed_by = match &self.key {
+            Some(path) => {
+                let keys = fs::read(path).map_err(|err| format!("cannot read key {}: {}", path.display(), err))?;
+                let signature = signature.ok_or("missing signature")?;
+                let key = openpgp::verify_detached(&keys, signature, data)
+                    .map_err(|err| format!("bad signature: {}", err))?;
+                Some(key.to_string())
+            }
+            None => None,
+        };
+        Ok(Verification::Passed {
+            md5: self.md5,
+            signed_by,
+        })
+    }
+
+    /// Checks a file on disk against its `.md5` and `.asc` files next to
+    /// it.
+    pub fn check_file(&self, path: &Path) -> Result<Verification, String> {
+        let data = fs::read(path).map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+        let md5 = match self.md5 {
+            true => fs::read(sidecar_path(path, "md5")).ok(),
+            false => None,
+        };
+        let signature = match self.key {
+            Some(_) => fs::read(sidecar_path(path, "asc")).ok(),
+            None => None,
+        };
+        self.check(&data, md5.as_deref(), signature.as_deref())
+    }
+}
+
+/// Returns the path of a file next to `path` with `ext` appended to its
+/// name, e.g. `ripencc.txt.md5`.
+pub fn sidecar_path(path: &Path, ext: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(".");
+    path.push(ext);
+    path.into()
+}
+
+/// Returns the checksum of an `.md5` file.
+///
+/// The RIRs use both the `md5sum` format, `<checksum>  <file>`, and the BSD
+/// format, `MD5 (<file>) = <checksum>`, so this looks for the first word
+/// that is a checksum.
+fn parse_md5(content: &[u8]) -> Option<String> {
+    std::str::from_utf8(content)
+        .ok()?
+        .split_whitespace()
+        .find(|word| word.len() == 32 && word.bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(str::to_ascii_lowercase)
+}
+
+//------------ Verification --------------------------------------------------
+
+/// What a passed signature check does not cover.
+const KEY_CHECKS_NOTE: &str = "key expiry, revocation and subkey binding are not checked";
+
+/// The outcome of checking the delegated file of a source.
+#[derive(Clone, Debug)]
+pub enum Verification {
+    /// The file passed the checks and was loaded.
+    Passed {
+        /// Whether the MD5 checksum was checked.
+        md5: bool,
+
+        /// The ID of the key that signed the file, if the signature was
+        /// checked.
+        signed_by: Option<String>,
+    },
+
+    /// The file failed a check.
+    Failed(String),
+}
+
+impl Verification {
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Verification::Failed(_))
+    }
+
+    pub fn to_json_builder(&self, builder: &mut JsonBuilder) {
+        match self {
+            Verification::Passed { md5, signed_by } => {
+                builder.member_str("status", "passed");
+                builder.member_raw("md5", md5);
+                match signed_by {
+                    Some(key) => {
+                        builder.member_str("signedBy", key);
+                        builder.member_str("keyChecks", KEY_CHECKS_NOTE);
+                    }
+                    None => builder.member_raw("signedBy", "null"),
+                }
+            }
+            Verification::Failed(err) => {
+                builder.member_str("status", "failed");
+                builder.member_str("error", err);
+            }
+        }
+    }
+}
+
+impl Snapshot for Verification {
+    fn write(&self, w: &mut Writer) {
+        match self {
+            Verification::Passed { md5, signed_by } => {
+                0u8.write(w);
+                md5.write(w);
+                signed_by.write(w);
+            }
+            Verification::Failed(err) => {
+                1u8.write(w);
+                err.write(w);
+            }
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, Box<dyn Error>> {
+        match u8::read(r)? {
+            0 => Ok(Verification::Passed {
+                md5: Snapshot::read(r)?,
+                signed_by: Snapshot::read(r)?,
+            }),
+            1 => Ok(Verification::Failed(Snapshot::read(r)?)),
+            tag => Reader::bad_tag("verification", tag),
+        }
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DATA: &[u8] = b"line one\nline two\n";
+    const MD5: &str = "987929d61c9b69f0c6406b840aa77fd8";
+
+    fn md5_only() -> Verify {
+        Verify { md5: true, key: None }
+    }
+
+    #[test]
+    fn parse_md5_formats() {
+        let expected = Some(MD5.to_string());
+        assert_eq!(parse_md5(format!("{}  delegated-ripencc-extended-latest\n", MD5).as_bytes()), expected);
+        assert_eq!(parse_md5(format!("MD5 (delegated-ripencc-extended-latest) = {}\n", MD5).as_bytes()), expected);
+        assert_eq!(parse_md5(MD5.to_ascii_uppercase().as_bytes()), expected);
+        assert_eq!(parse_md5(b"MD5 (file) = 987929d61c9b69f0c6406b840aa77fd\n"), None);
+        assert_eq!(parse_md5(b"\xff\xfe"), None);
+    }
+
+    #[test]
+    fn good_checksum() {
+        let verification = md5_only().check(DATA, Some(MD5.as_bytes()), None).unwrap();
+        assert!(matches!(verification, Verification::Passed { md5: true, signed_by: None }));
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let err = md5_only().check(b"line one\n", Some(MD5.as_bytes()), None).unwrap_err();
+        assert!(err.starts_with("MD5 checksum mismatch, expected 987929d61c9b69f0c6406b840aa77fd8 but got "));
+        let err = md5_only().check(DATA, None, None).unwrap_err();
+        assert_eq!(err, "missing or invalid MD5 checksum");
+        let err = md5_only().check(DATA, Some(b"not a checksum"), None).unwrap_err();
+        assert_eq!(err, "missing or invalid MD5 checksum");
+    }
+
+    #[test]
+    fn nothing_to_check() {
+        let verify = Verify { md5: false, key: None };
+        assert!(!verify.is_enabled());
+        let verification = verify.check(DATA, Some(b"not a checksum"), None).unwrap();
+        assert!(matches!(verification, Verification::Passed { md5: false, signed_by: None }));
+    }
+
+    #[test]
+    fn sidecar_paths() {
+        assert_eq!(sidecar_path(Path::new("data/ripencc.txt"), "md5"), Path::new("data/ripencc.txt.md5"));
+    }
+}